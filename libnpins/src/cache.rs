@@ -0,0 +1,91 @@
+//! Caching the results of requests within one run
+//!
+//! Projects often pin the same repository several times, e.g. on different branches or with and
+//! without submodules, and some pins query the same metadata in [`update`](crate::Updatable::update)
+//! and [`fetch`](crate::Updatable::fetch). With a [`RequestCache`] in the [`Config`](crate::Config),
+//! each of these requests is only made once, and concurrent identical requests wait for the first
+//! one instead of being sent again.
+//!
+//! Nothing is ever evicted, so a cache should only live as long as one batch of operations. The
+//! CLI uses a new one for each invocation.
+
+use anyhow::Result;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+type Entries = HashMap<(TypeId, String), Arc<dyn Any + Send + Sync>>;
+
+/// Results of requests, keyed by their URL and arguments
+///
+/// Cloning is cheap, the clones share their entries.
+#[derive(Debug, Clone, Default)]
+pub struct RequestCache {
+    entries: Arc<Mutex<Entries>>,
+}
+
+impl RequestCache {
+    /// Return the cached result for `key`, or else run `fetch` and cache its result
+    ///
+    /// Errors are not cached, the next caller for the same key runs its own `fetch`.
+    pub async fn get_or_try_insert<T, F>(&self, key: String, fetch: F) -> Result<T>
+    where
+        T: Clone + Send + Sync + 'static,
+        F: Future<Output = Result<T>>,
+    {
+        let cell = self
+            .entries
+            .lock()
+            .unwrap()
+            .entry((TypeId::of::<T>(), key))
+            .or_insert_with(|| Arc::new(OnceCell::<T>::new()))
+            .clone()
+            .downcast::<OnceCell<T>>()
+            .expect("entries are keyed by their type");
+        cell.get_or_try_init(|| fetch).await.cloned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures_util::future;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_request_cache() {
+        let cache = RequestCache::default();
+        let requests = AtomicUsize::new(0);
+        let request = |key: &str, result: Result<u32>| {
+            let (cache, requests) = (cache.clone(), &requests);
+            let key = key.to_owned();
+            async move {
+                cache
+                    .get_or_try_insert(key, async {
+                        requests.fetch_add(1, Ordering::SeqCst);
+                        tokio::task::yield_now().await;
+                        result
+                    })
+                    .await
+            }
+        };
+
+        /* Concurrent requests for the same key are only made once */
+        let results = future::join_all([request("a", Ok(1)), request("a", Ok(2))]).await;
+        assert_eq!(results[0].as_ref().unwrap(), &1);
+        assert_eq!(results[1].as_ref().unwrap(), &1);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        /* Other keys and types have their own entries */
+        assert_eq!(request("b", Ok(2)).await.unwrap(), 2);
+        let other_type = cache.get_or_try_insert("a".into(), async { Ok("a string") });
+        assert_eq!(other_type.await.unwrap(), "a string");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        /* Errors are retried */
+        assert!(request("c", Err(anyhow::anyhow!("failed"))).await.is_err());
+        assert_eq!(request("c", Ok(3)).await.unwrap(), 3);
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+    }
+}
@@ -0,0 +1,71 @@
+//! Compare links and commit lists for updated pins
+//!
+//! Only pins of git repositories hosted on a forge are supported, i.e. `Git`, `GitRelease` and
+//! `ReleaseAsset` pins whose repository is on GitHub, GitLab or Forgejo.
+
+use serde::Serialize;
+use url::Url;
+
+use crate::diff::DiffEntry;
+use crate::git::{Commit, Repository};
+use crate::outdated::Outdated;
+use crate::{Config, Pin};
+
+/// What changed upstream between the old and the new version of a pin
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Changelog {
+    /// Web page comparing both versions on the forge
+    pub compare_url: Url,
+    /// The commits between both versions, oldest first, see [`Changelog::fetch_commits`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commits: Option<Vec<Commit>>,
+    #[serde(skip)]
+    repository: Repository,
+    #[serde(skip)]
+    old: String,
+    #[serde(skip)]
+    new: String,
+}
+
+impl Changelog {
+    /// Build the changelog of an update from the diff returned by [`Pin::update`]
+    ///
+    /// Returns `None` if the version didn't change or the pin isn't hosted on a forge.
+    pub fn new(config: &Config, pin: &Pin, diff: &[DiffEntry]) -> anyhow::Result<Option<Self>> {
+        let repository = match pin {
+            Pin::Git { input, .. } => &input.repository,
+            Pin::GitRelease { input, .. } => &input.repository,
+            Pin::ReleaseAsset { input, .. } => &input.repository,
+            _ => return Ok(None),
+        };
+        /* Tags for releases, revisions for branches */
+        let Some(Outdated {
+            current: Some(old),
+            latest: new,
+            ..
+        }) = Outdated::from_diff(diff)
+        else {
+            return Ok(None);
+        };
+        let Some(compare_url) = repository.compare_url(config.hosts(), &old, &new)? else {
+            return Ok(None);
+        };
+
+        Ok(Some(Self {
+            compare_url,
+            commits: None,
+            repository: repository.clone(),
+            old,
+            new,
+        }))
+    }
+
+    /// List the commits between both versions through the forge's API
+    pub async fn fetch_commits(&mut self, config: &Config) -> anyhow::Result<()> {
+        self.commits = self
+            .repository
+            .commits_between(config, &self.old, &self.new)
+            .await?;
+        Ok(())
+    }
+}
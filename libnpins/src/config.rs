@@ -0,0 +1,518 @@
+//! Configuration of the network access
+//!
+//! A [`Config`] is passed to every [`Updatable`](crate::Updatable) operation. It carries the shared
+//! HTTP client, the hosts of the public forges and registries, per-host authentication and the
+//! [`Credentials`] of private repositories, the [`RetryPolicy`] and [`Timeouts`] of network
+//! operations, the optional [`RequestCache`], as well as the global minimum age of new versions.
+//!
+//! Embedders should construct one with [`Config::builder`]. The CLI uses [`Config::from_env`], which
+//! additionally honors the `NPINS_*` environment variables.
+
+use anyhow::{Context, Result};
+use reqwest::header::{AUTHORIZATION, HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Method, RequestBuilder};
+use std::time::Duration;
+use url::Url;
+
+use crate::cache::RequestCache;
+use crate::credentials::Credentials;
+use crate::prefetch::PrefetchBackend;
+use crate::retry::{RetryPolicy, Timeouts};
+
+/// Parse a base URL, making sure that relative URLs can be joined onto it without dropping the last path segment
+fn base_url(url: &str) -> Result<Url> {
+    if url.ends_with('/') {
+        Ok(url.parse()?)
+    } else {
+        Ok(format!("{url}/").parse()?)
+    }
+}
+
+/// Base URLs of the well-known forges and registries
+///
+/// Each of them can be pointed to a mirror or a local stand-in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hosts {
+    /// Used for cloning and archives of GitHub repositories
+    pub github: Url,
+    /// Used for release tarballs of GitHub repositories
+    pub github_api: Url,
+    /// The default GitLab instance, used for flake inputs
+    pub gitlab: Url,
+    /// The Python package index
+    pub pypi: Url,
+    /// The sparse index of crates.io
+    pub crates_index: Url,
+    /// The npm registry
+    pub npm: Url,
+    /// The server of the Nix channels
+    pub channels: Url,
+}
+
+impl Default for Hosts {
+    fn default() -> Self {
+        Self {
+            github: "https://github.com/".parse().unwrap(),
+            github_api: "https://api.github.com/".parse().unwrap(),
+            gitlab: "https://gitlab.com/".parse().unwrap(),
+            pypi: "https://pypi.org/".parse().unwrap(),
+            crates_index: "https://index.crates.io/".parse().unwrap(),
+            npm: "https://registry.npmjs.org/".parse().unwrap(),
+            channels: "https://channels.nixos.org/".parse().unwrap(),
+        }
+    }
+}
+
+impl Hosts {
+    /// The default hosts, overridden by the `NPINS_{GITHUB,GITHUB_API,GITLAB,PYPI,CRATES_INDEX,NPM,CHANNELS}_HOST`
+    /// environment variables if set
+    pub fn from_env() -> Result<Self> {
+        let mut hosts = Self::default();
+        for (variable, host) in [
+            ("NPINS_GITHUB_HOST", &mut hosts.github),
+            ("NPINS_GITHUB_API_HOST", &mut hosts.github_api),
+            ("NPINS_GITLAB_HOST", &mut hosts.gitlab),
+            ("NPINS_PYPI_HOST", &mut hosts.pypi),
+            ("NPINS_CRATES_INDEX_HOST", &mut hosts.crates_index),
+            ("NPINS_NPM_HOST", &mut hosts.npm),
+            ("NPINS_CHANNELS_HOST", &mut hosts.channels),
+        ] {
+            if let Ok(value) = std::env::var(variable) {
+                *host =
+                    base_url(&value).with_context(|| format!("Invalid value for {variable}"))?;
+            }
+        }
+        Ok(hosts)
+    }
+}
+
+/// Shared state and settings for all network access
+///
+/// Cloning is cheap, the HTTP client and its connection pool are shared between the clones.
+#[derive(Debug, Clone)]
+pub struct Config {
+    client: reqwest::Client,
+    hosts: Hosts,
+    /// `(host, name, value)` of the headers sent with all requests to the host
+    headers: Vec<(String, HeaderName, HeaderValue)>,
+    /// `(host, username, password)` used by git over HTTP
+    git_credentials: Vec<(String, String, String)>,
+    credentials: Credentials,
+    retry_policy: RetryPolicy,
+    timeouts: Timeouts,
+    request_cache: Option<RequestCache>,
+    prefetch_backend: PrefetchBackend,
+    min_age: Option<Duration>,
+}
+
+/// Length of the days in `min_age` settings
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+impl Config {
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// The default configuration, with the overrides from the `NPINS_*` environment variables applied
+    pub fn from_env() -> Result<Self> {
+        ConfigBuilder::from_env()?.build()
+    }
+
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    pub fn hosts(&self) -> &Hosts {
+        &self.hosts
+    }
+
+    pub fn credentials(&self) -> &Credentials {
+        &self.credentials
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    pub fn timeouts(&self) -> &Timeouts {
+        &self.timeouts
+    }
+
+    pub fn prefetch_backend(&self) -> PrefetchBackend {
+        self.prefetch_backend
+    }
+
+    /// The minimum age of new versions for a pin, given its own `min_age` in days
+    ///
+    /// The setting of the pin takes precedence over the global one, `0` disables the check.
+    pub fn min_age(&self, pin_min_age: Option<u32>) -> Option<Duration> {
+        match pin_min_age {
+            Some(0) => None,
+            Some(days) => Some(DAY * days),
+            None => self.min_age,
+        }
+    }
+
+    /// A copy of this configuration which also sends the header with all requests to the host
+    ///
+    /// It replaces headers of the same name which have been configured before.
+    pub fn with_header(
+        &self,
+        host: impl Into<String>,
+        name: HeaderName,
+        value: HeaderValue,
+    ) -> Self {
+        let mut config = self.clone();
+        config.headers.push((host.into(), name, value));
+        config
+    }
+
+    /// A copy of this configuration which authenticates git operations on the host
+    ///
+    /// Credentials embedded into git URLs take precedence.
+    pub fn with_git_credentials(
+        &self,
+        host: impl Into<String>,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        let mut config = self.clone();
+        config
+            .git_credentials
+            .push((host.into(), username.into(), password.into()));
+        config
+    }
+
+    /// The username and password for git operations on the URL's host, if any
+    pub fn git_credentials(&self, url: &Url) -> Option<(&str, &str)> {
+        self.git_credentials
+            .iter()
+            .rev()
+            .find(|(host, _, _)| url.host_str() == Some(host))
+            .map(|(_, username, password)| (username.as_str(), password.as_str()))
+    }
+
+    /// Environment variables which pass the git credentials for the URL's host to a `git` process
+    ///
+    /// They configure a credential helper that reads the username and password from the environment,
+    /// so that they don't end up on the command line. Don't log them.
+    pub(crate) fn git_credentials_env(&self, url: &str) -> Vec<(&'static str, String)> {
+        let Some((username, password)) =
+            url.parse().ok().and_then(|url| self.git_credentials(&url))
+        else {
+            return Vec::new();
+        };
+        vec![
+            ("GIT_CONFIG_COUNT", "1".into()),
+            ("GIT_CONFIG_KEY_0", "credential.helper".into()),
+            (
+                "GIT_CONFIG_VALUE_0",
+                r#"!f() { test "$1" = get && echo "username=$NPINS_GIT_USERNAME" && echo "password=$NPINS_GIT_PASSWORD"; }; f"#.into(),
+            ),
+            ("NPINS_GIT_USERNAME", username.into()),
+            ("NPINS_GIT_PASSWORD", password.into()),
+        ]
+    }
+
+    /// A copy of this configuration which caches the results of requests in `cache`
+    pub fn with_request_cache(&self, cache: RequestCache) -> Self {
+        let mut config = self.clone();
+        config.request_cache = Some(cache);
+        config
+    }
+
+    /// Whether results are cached, see [`Config::cached`]
+    pub(crate) fn has_request_cache(&self) -> bool {
+        self.request_cache.is_some()
+    }
+
+    /// Run `fetch` only if the request cache has no result for `key` yet
+    ///
+    /// Without a request cache, `fetch` is always run.
+    pub(crate) async fn cached<T, F>(&self, key: String, fetch: F) -> Result<T>
+    where
+        T: Clone + Send + Sync + 'static,
+        F: Future<Output = Result<T>>,
+    {
+        match &self.request_cache {
+            Some(cache) => cache.get_or_try_insert(key, fetch).await,
+            None => fetch.await,
+        }
+    }
+
+    /// Start a request, with the headers set which are configured for the URL's host
+    pub fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        let mut headers = HeaderMap::new();
+        for (_, name, value) in self
+            .headers
+            .iter()
+            .filter(|(host, _, _)| url.host_str() == Some(host))
+        {
+            headers.insert(name, value.clone());
+        }
+        self.client.request(method, url).headers(headers)
+    }
+
+    pub fn get(&self, url: Url) -> RequestBuilder {
+        self.request(Method::GET, url)
+    }
+
+    pub fn head(&self, url: Url) -> RequestBuilder {
+        self.request(Method::HEAD, url)
+    }
+}
+
+/// Builder for [`Config`]
+#[derive(Debug, Default)]
+pub struct ConfigBuilder {
+    client: Option<reqwest::Client>,
+    hosts: Hosts,
+    proxies: Vec<reqwest::Proxy>,
+    root_certificates: Vec<reqwest::Certificate>,
+    connect_timeout: Option<Duration>,
+    headers: Vec<(String, HeaderName, HeaderValue)>,
+    credentials: Credentials,
+    retry_policy: RetryPolicy,
+    timeouts: Timeouts,
+    request_cache: Option<RequestCache>,
+    prefetch_backend: PrefetchBackend,
+    min_age: Option<Duration>,
+}
+
+impl ConfigBuilder {
+    /// The defaults, with the overrides from the `NPINS_*` environment variables applied
+    pub fn from_env() -> Result<Self> {
+        let mut builder = Self::default()
+            .hosts(Hosts::from_env()?)
+            .credentials(Credentials::from_env())
+            .prefetch_backend(PrefetchBackend::from_env()?);
+        if let Ok(days) = std::env::var("NPINS_MIN_AGE") {
+            let days: u32 = days
+                .parse()
+                .context("Invalid value for NPINS_MIN_AGE, expected a number of days")?;
+            builder = builder.min_age(DAY * days);
+        }
+        Ok(builder)
+    }
+
+    /// Use an existing HTTP client
+    ///
+    /// The proxy, certificate and connect timeout settings of this builder are ignored in that
+    /// case, as they have to be configured on the client directly.
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    pub fn hosts(mut self, hosts: Hosts) -> Self {
+        self.hosts = hosts;
+        self
+    }
+
+    /// Route requests through a proxy. May be called multiple times
+    pub fn proxy(mut self, proxy: reqwest::Proxy) -> Self {
+        self.proxies.push(proxy);
+        self
+    }
+
+    /// Trust an additional root certificate. May be called multiple times
+    pub fn root_certificate(mut self, certificate: reqwest::Certificate) -> Self {
+        self.root_certificates.push(certificate);
+        self
+    }
+
+    /// Timeout for each API request as a whole, including downloading the body
+    ///
+    /// Shorthand for setting [`Timeouts::request`], the other operations have their own timeouts.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.request = Some(timeout);
+        self
+    }
+
+    /// Timeouts of single attempts of network operations, see [`Timeouts`] for the defaults
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Retry transient failures of network operations, three attempts by default
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Timeout for establishing connections
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Send an `Authorization` header with all requests to the given host
+    ///
+    /// Credentials embedded into git URLs take precedence.
+    pub fn auth_header(self, host: impl Into<String>, mut value: HeaderValue) -> Self {
+        value.set_sensitive(true);
+        self.header(host, AUTHORIZATION, value)
+    }
+
+    /// Send a header with all requests to the given host. May be called multiple times
+    pub fn header(mut self, host: impl Into<String>, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.push((host.into(), name, value));
+        self
+    }
+
+    /// Where the secrets of the credentials named by pins come from, nowhere by default
+    pub fn credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// Cache the results of requests, so that each is only made once
+    ///
+    /// See [`crate::cache`], the cache should not outlive one batch of operations.
+    pub fn request_cache(mut self, cache: RequestCache) -> Self {
+        self.request_cache = Some(cache);
+        self
+    }
+
+    pub fn prefetch_backend(mut self, backend: PrefetchBackend) -> Self {
+        self.prefetch_backend = backend;
+        self
+    }
+
+    /// Don't update pins to versions younger than this, unless the pin sets its own minimum age
+    ///
+    /// Only pins which can determine the age of their versions honor this, see the `min_age`
+    /// field of the pin types.
+    pub fn min_age(mut self, min_age: Duration) -> Self {
+        self.min_age = Some(min_age);
+        self
+    }
+
+    pub fn build(self) -> Result<Config> {
+        let client = match self.client {
+            Some(client) => client,
+            None => {
+                let mut builder = reqwest::Client::builder().user_agent(concat!(
+                    env!("CARGO_PKG_NAME"),
+                    " v",
+                    env!("CARGO_PKG_VERSION")
+                ));
+                for proxy in self.proxies {
+                    builder = builder.proxy(proxy);
+                }
+                for certificate in self.root_certificates {
+                    builder = builder.add_root_certificate(certificate);
+                }
+                if let Some(timeout) = self.connect_timeout {
+                    builder = builder.connect_timeout(timeout);
+                }
+                builder.build().context("Failed to build the HTTP client")?
+            },
+        };
+
+        Ok(Config {
+            client,
+            hosts: self.hosts,
+            headers: self.headers,
+            git_credentials: Vec::new(),
+            credentials: self.credentials,
+            retry_policy: self.retry_policy,
+            timeouts: self.timeouts,
+            request_cache: self.request_cache,
+            prefetch_backend: self.prefetch_backend,
+            min_age: self.min_age,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_base_url() {
+        assert_eq!(
+            base_url("http://localhost:8000").unwrap().as_str(),
+            "http://localhost:8000/"
+        );
+        assert_eq!(
+            base_url("http://localhost:8000/api").unwrap().as_str(),
+            "http://localhost:8000/api/"
+        );
+        assert_eq!(
+            base_url("http://localhost:8000/api/").unwrap().as_str(),
+            "http://localhost:8000/api/"
+        );
+        assert!(base_url("localhost").is_err());
+    }
+
+    #[test]
+    fn test_min_age() {
+        let config = Config::builder().build().unwrap();
+        assert_eq!(config.min_age(None), None);
+        assert_eq!(config.min_age(Some(2)), Some(DAY * 2));
+
+        let config = Config::builder().min_age(DAY * 7).build().unwrap();
+        assert_eq!(config.min_age(None), Some(DAY * 7));
+        assert_eq!(config.min_age(Some(2)), Some(DAY * 2));
+        assert_eq!(config.min_age(Some(0)), None);
+    }
+
+    #[test]
+    fn test_auth_header() {
+        let config = Config::builder()
+            .auth_header("example.org", HeaderValue::from_static("Bearer secret"))
+            .build()
+            .unwrap();
+        let request = |url: &str| config.get(url.parse().unwrap()).build().unwrap();
+
+        assert_eq!(
+            request("https://example.org/foo").headers()[AUTHORIZATION],
+            "Bearer secret"
+        );
+        assert!(
+            !request("https://example.com/foo")
+                .headers()
+                .contains_key(AUTHORIZATION)
+        );
+
+        let config = config.with_header(
+            "example.org",
+            HeaderName::from_static("private-token"),
+            HeaderValue::from_static("token"),
+        );
+        let request = |url: &str| config.get(url.parse().unwrap()).build().unwrap();
+        let headers = request("https://example.org/foo").headers().clone();
+        assert_eq!(headers[AUTHORIZATION], "Bearer secret");
+        assert_eq!(headers["private-token"], "token");
+    }
+
+    #[test]
+    fn test_git_credentials() {
+        let config = Config::builder().build().unwrap().with_git_credentials(
+            "example.org",
+            "oauth2",
+            "token",
+        );
+        let url = |url: &str| url.parse::<Url>().unwrap();
+        assert_eq!(
+            config.git_credentials(&url("https://example.org/foo.git")),
+            Some(("oauth2", "token"))
+        );
+        assert_eq!(
+            config.git_credentials(&url("https://example.com/foo.git")),
+            None
+        );
+        assert!(
+            config
+                .git_credentials_env("https://example.com/foo.git")
+                .is_empty()
+        );
+        assert!(
+            config
+                .git_credentials_env("https://example.org/foo.git")
+                .contains(&("NPINS_GIT_PASSWORD", "token".into()))
+        );
+    }
+}
@@ -0,0 +1,243 @@
+//! Version constraints like `>=1.4, <2, !=1.4.3`
+//!
+//! A constraint is a comma separated list of requirements, all of which must be met. Versions are
+//! parsed with the [`VersionScheme`] of the pin, so for the default lenient SemVer `v1.4` and
+//! `1.4.0` are equivalent. The supported operators are:
+//!
+//! - `=` or `==`, `!=`: exactly that version or anything else
+//! - `<`, `<=`, `>`, `>=`: the usual comparisons
+//! - `~1.4.3`: at least 1.4.3, but the same major and minor version (`~1` only fixes the major version)
+//! - `^1.4.3`: at least 1.4.3, but the same major version. For `0.x` releases, the same minor version
+//! - `~=1.4.3`: at least 1.4.3, with everything but the last given component fixed (i.e. 1.4.*).
+//!   This is the "compatible release" operator of Python packages
+//!
+//! `~`, `^` and `~=` work on the numeric components of the versions, and are thus not supported
+//! with the lexical scheme.
+
+use anyhow::{Context, Result};
+
+use crate::version_scheme::{ParsedVersion, VersionScheme};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Exact,
+    NotEqual,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Tilde,
+    Caret,
+    Compatible,
+}
+
+/* Longest operators first, so that `<=` isn't parsed as `<` */
+const OPERATORS: [(&str, Operator); 10] = [
+    ("~=", Operator::Compatible),
+    ("==", Operator::Exact),
+    ("!=", Operator::NotEqual),
+    (">=", Operator::GreaterEq),
+    ("<=", Operator::LessEq),
+    ("=", Operator::Exact),
+    (">", Operator::Greater),
+    ("<", Operator::Less),
+    ("~", Operator::Tilde),
+    ("^", Operator::Caret),
+];
+
+#[derive(Debug, Clone)]
+struct Requirement<'a> {
+    operator: Operator,
+    version: ParsedVersion<'a>,
+    /// Numeric components of the version
+    release: Vec<u64>,
+    /// Number of numeric components given, e.g. 2 for `1.4`
+    components: usize,
+}
+
+impl<'a> Requirement<'a> {
+    fn parse(requirement: &'a str, scheme: VersionScheme) -> Result<Self> {
+        let (operator, version) = OPERATORS
+            .iter()
+            .find_map(|(prefix, operator)| {
+                requirement
+                    .strip_prefix(prefix)
+                    .map(|version| (*operator, version.trim()))
+            })
+            .with_context(|| {
+                format!("Requirement `{requirement}` has no operator, use e.g. `^{requirement}` or `<{requirement}`")
+            })?;
+
+        let components = version
+            .trim_start_matches(['v', 'V'])
+            .split(['-', '+'])
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .take_while(|component| {
+                !component.is_empty() && component.bytes().all(|c| c.is_ascii_digit())
+            })
+            .count();
+        anyhow::ensure!(
+            operator != Operator::Compatible || components >= 2,
+            "`~=` requires at least two version components, e.g. `~=1.4`"
+        );
+        let version = scheme.parse(version).with_context(|| {
+            format!(
+                "Invalid version in requirement `{requirement}` for the {scheme} version scheme"
+            )
+        })?;
+        let release = match operator {
+            Operator::Tilde | Operator::Caret | Operator::Compatible => {
+                version.release().with_context(|| {
+                    format!("Requirement `{requirement}` is not supported by the {scheme} version scheme")
+                })?
+            },
+            _ => Vec::new(),
+        };
+
+        Ok(Self {
+            operator,
+            version,
+            release,
+            components,
+        })
+    }
+
+    fn matches(&self, version: &ParsedVersion) -> bool {
+        /* Whether the first `n` numeric components are equal, missing ones count as zero */
+        let same = |n: usize| {
+            let release = version.release().unwrap_or_default();
+            (0..n).all(|i| {
+                release.get(i).copied().unwrap_or(0) == self.release.get(i).copied().unwrap_or(0)
+            })
+        };
+        let component = |i: usize| self.release.get(i).copied().unwrap_or(0);
+        match self.operator {
+            Operator::Exact => version == &self.version,
+            Operator::NotEqual => version != &self.version,
+            Operator::Greater => version > &self.version,
+            Operator::GreaterEq => version >= &self.version,
+            Operator::Less => version < &self.version,
+            Operator::LessEq => version <= &self.version,
+            Operator::Tilde => version >= &self.version && same(self.components.clamp(1, 2)),
+            Operator::Caret => {
+                let fixed = if component(0) > 0 || self.components == 1 {
+                    1
+                } else if component(1) > 0 || self.components == 2 {
+                    2
+                } else {
+                    3
+                };
+                version >= &self.version && same(fixed)
+            },
+            Operator::Compatible => version >= &self.version && same(self.components - 1),
+        }
+    }
+}
+
+/// A parsed version constraint, see the [module documentation](self)
+#[derive(Debug, Clone)]
+pub struct Constraint<'a> {
+    requirements: Vec<Requirement<'a>>,
+}
+
+impl<'a> Constraint<'a> {
+    pub fn parse(constraint: &'a str, scheme: VersionScheme) -> Result<Self> {
+        let requirements = constraint
+            .split(',')
+            .map(str::trim)
+            .map(|requirement| Requirement::parse(requirement, scheme))
+            .collect::<Result<_>>()
+            .with_context(|| format!("Invalid version constraint `{constraint}`"))?;
+        Ok(Self { requirements })
+    }
+
+    /// Whether the version meets all requirements
+    pub fn matches(&self, version: &ParsedVersion) -> bool {
+        self.requirements
+            .iter()
+            .all(|requirement| requirement.matches(version))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn matches_in(scheme: VersionScheme, constraint: &str, version: &str) -> bool {
+        Constraint::parse(constraint, scheme)
+            .unwrap()
+            .matches(&scheme.parse(version).unwrap())
+    }
+
+    fn matches(constraint: &str, version: &str) -> bool {
+        matches_in(VersionScheme::Semver, constraint, version)
+    }
+
+    #[test]
+    fn test_comparisons() {
+        assert!(matches("<2", "1.99.3"));
+        assert!(!matches("<2", "2.0.0"));
+        assert!(matches("<=2", "v2.0"));
+        assert!(matches(">=1.4, <2", "1.4.0"));
+        assert!(!matches(">=1.4, <2", "1.3.9"));
+        assert!(matches(" > 1.4 ,<= 1.5 ", "1.5"));
+        assert!(!matches(">=1.4, <2, !=1.4.3", "1.4.3"));
+        assert!(matches(">=1.4, <2, !=1.4.3", "1.4.4"));
+        assert!(matches("=1.4", "1.4.0"));
+        assert!(matches("==1.4.0", "v1.4"));
+        assert!(!matches("==1.4.0", "1.4.1"));
+    }
+
+    #[test]
+    fn test_ranges() {
+        assert!(matches("~1.4", "1.4.7"));
+        assert!(matches("~1.4.3", "1.4.3"));
+        assert!(!matches("~1.4.3", "1.4.2"));
+        assert!(!matches("~1.4", "1.5.0"));
+        assert!(matches("~1", "1.9.0"));
+        assert!(!matches("~1", "2.0.0"));
+
+        assert!(matches("^1.4", "1.9.0"));
+        assert!(!matches("^1.4", "1.3.0"));
+        assert!(!matches("^1.4", "2.0.0"));
+        assert!(matches("^0.4.2", "0.4.9"));
+        assert!(!matches("^0.4.2", "0.5.0"));
+        assert!(matches("^0.0.3", "0.0.3"));
+        assert!(!matches("^0.0.3", "0.0.4"));
+        assert!(matches("^0", "0.9.0"));
+
+        assert!(matches("~=1.4", "1.9"));
+        assert!(!matches("~=1.4", "2.0"));
+        assert!(matches("~=1.4.2", "1.4.5"));
+        assert!(!matches("~=1.4.2", "1.5.0"));
+    }
+
+    #[test]
+    fn test_invalid() {
+        for invalid in ["", "1.4", ">=1.4,", "<", "~=1", ">=foo", "=>1.4"] {
+            assert!(
+                Constraint::parse(invalid, VersionScheme::Semver).is_err(),
+                "{invalid}"
+            );
+        }
+        assert!(Constraint::parse("^1.4", VersionScheme::Lexical).is_err());
+        assert!(Constraint::parse(">=2024-13-01", VersionScheme::Date).is_err());
+    }
+
+    #[test]
+    fn test_schemes() {
+        let pep440 = |constraint, version| matches_in(VersionScheme::Pep440, constraint, version);
+        assert!(pep440("~=1.4", "1.4.post1"));
+        assert!(pep440(">=1.4, !=1.4.post1", "1.4.post2"));
+        assert!(pep440("<1.4", "1.4.dev1"));
+        assert!(!pep440("<1.4", "1.4"));
+
+        let date = |constraint, version| matches_in(VersionScheme::Date, constraint, version);
+        assert!(date(">=2024-01-01, <2025-01-01", "20240615"));
+        assert!(!date("<2024-01-01", "2024.01.01"));
+
+        assert!(matches_in(VersionScheme::Lexical, "<b", "a10"));
+    }
+}
@@ -0,0 +1,272 @@
+//! Credentials for private repositories
+//!
+//! Pins never contain secrets, they only refer to a credential by its name. The secret is looked
+//! up whenever it is needed, in this order:
+//!
+//! 1. The environment variable `NPINS_CREDENTIAL_<NAME>`, where the name is uppercased and all
+//!    characters other than letters and digits are replaced by `_`
+//! 2. The command in `NPINS_CREDENTIAL_HELPER`, speaking the protocol of [git credential helpers]:
+//!    it gets the `protocol` and `host` on stdin and the name in the `NPINS_CREDENTIAL` environment
+//!    variable, and prints the `password`. For example, `git credential fill` asks the helpers
+//!    configured for git.
+//! 3. The password of the host in the netrc file at `NETRC` or `~/.netrc`. Nix can use the same
+//!    file to fetch the pins, see its `netrc-file` option.
+//!
+//! [git credential helpers]: https://git-scm.com/docs/gitcredentials
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+/// The environment variable with the credential helper command
+pub const CREDENTIAL_HELPER_ENV: &str = "NPINS_CREDENTIAL_HELPER";
+
+/// Where the secrets of named credentials come from, see the [module documentation](self)
+///
+/// Cloning is cheap, the secrets looked up so far are shared between the clones.
+#[derive(Clone, Default)]
+pub struct Credentials {
+    /// Secrets given explicitly, by name
+    secrets: Vec<(String, String)>,
+    /// Whether to look at the `NPINS_CREDENTIAL_<NAME>` environment variables
+    env: bool,
+    helper: Option<String>,
+    netrc: Option<PathBuf>,
+    cache: Arc<Mutex<Cache>>,
+}
+
+/// Secrets which have already been looked up, by name and host, including missing ones
+type Cache = HashMap<(String, String), Option<String>>;
+
+/* Never print the secrets */
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field(
+                "secrets",
+                &self
+                    .secrets
+                    .iter()
+                    .map(|(name, _)| name)
+                    .collect::<Vec<_>>(),
+            )
+            .field("env", &self.env)
+            .field("helper", &self.helper)
+            .field("netrc", &self.netrc)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Credentials {
+    /// Look up the secrets in the environment variables, the credential helper from
+    /// `NPINS_CREDENTIAL_HELPER` and the netrc file
+    pub fn from_env() -> Self {
+        let netrc = std::env::var_os("NETRC")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".netrc")));
+        Self {
+            env: true,
+            helper: std::env::var(CREDENTIAL_HELPER_ENV)
+                .ok()
+                .filter(|helper| !helper.is_empty()),
+            netrc,
+            ..Self::default()
+        }
+    }
+
+    /// Use this secret for the credential, before looking anywhere else
+    pub fn secret(mut self, name: impl Into<String>, secret: impl Into<String>) -> Self {
+        self.secrets.push((name.into(), secret.into()));
+        self
+    }
+
+    pub fn helper(mut self, command: impl Into<String>) -> Self {
+        self.helper = Some(command.into());
+        self
+    }
+
+    pub fn netrc(mut self, path: impl Into<PathBuf>) -> Self {
+        self.netrc = Some(path.into());
+        self
+    }
+
+    /// Look up the secret of a credential, for requests to the given server
+    pub async fn get(&self, name: &str, server: &Url) -> Result<String> {
+        match self.find(name, server).await? {
+            Some(secret) => Ok(secret),
+            None => {
+                let host = server.host_str().unwrap_or_default();
+                anyhow::bail!(
+                    "Credential `{name}` for {host} not found. Set it in the environment variable {}, configure {CREDENTIAL_HELPER_ENV} or add {host} to your netrc file",
+                    env_variable(name)
+                )
+            },
+        }
+    }
+
+    /// Like [`get`](Self::get), for optional credentials
+    pub async fn find(&self, name: &str, server: &Url) -> Result<Option<String>> {
+        let host = server.host_str().unwrap_or_default().to_owned();
+        let key = (name.to_owned(), host.clone());
+        if let Some(secret) = self.cache.lock().unwrap().get(&key) {
+            return Ok(secret.clone());
+        }
+
+        let secret = self.lookup(name, server, &host).await?;
+        self.cache.lock().unwrap().insert(key, secret.clone());
+        Ok(secret)
+    }
+
+    async fn lookup(&self, name: &str, server: &Url, host: &str) -> Result<Option<String>> {
+        if let Some((_, secret)) = self.secrets.iter().find(|(n, _)| n == name) {
+            return Ok(Some(secret.clone()));
+        }
+        if self.env
+            && let Ok(secret) = std::env::var(env_variable(name))
+        {
+            return Ok(Some(secret));
+        }
+        if let Some(helper) = &self.helper {
+            let secret = run_helper(helper, name, server.scheme(), host)
+                .await
+                .with_context(|| format!("Credential helper `{helper}` failed"))?;
+            if secret.is_some() {
+                return Ok(secret);
+            }
+        }
+        if let Some(netrc) = &self.netrc {
+            match tokio::fs::read_to_string(netrc).await {
+                Ok(contents) => return Ok(netrc_password(&contents, host)),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {},
+                Err(err) => {
+                    return Err(err).with_context(|| format!("Failed to read {}", netrc.display()));
+                },
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The environment variable of a credential, e.g. `NPINS_CREDENTIAL_WORK_GITLAB` for `work-gitlab`
+pub fn env_variable(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect();
+    format!("NPINS_CREDENTIAL_{name}")
+}
+
+async fn run_helper(
+    helper: &str,
+    name: &str,
+    protocol: &str,
+    host: &str,
+) -> Result<Option<String>> {
+    let mut command = tokio::process::Command::new("sh");
+    command
+        .arg("-c")
+        .arg(helper)
+        .env("NPINS_CREDENTIAL", name)
+        /* Don't let git prompt for missing credentials */
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::inherit());
+    log::debug!("Asking the credential helper for `{name}` on {host}");
+    let mut child = command
+        .spawn()
+        .context("Failed to spawn the credential helper")?;
+    let mut stdin = child.stdin.take().unwrap();
+    stdin
+        .write_all(format!("protocol={protocol}\nhost={host}\n\n").as_bytes())
+        .await?;
+    drop(stdin);
+
+    let output = child.wait_with_output().await?;
+    anyhow::ensure!(output.status.success(), "Exited with {}", output.status);
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("password="))
+        .map(str::to_owned))
+}
+
+/// Find the password for a host in a netrc file, falling back to the `default` entry
+///
+/// See <https://www.gnu.org/software/inetutils/manual/html_node/The-_002enetrc-file.html>
+fn netrc_password(contents: &str, host: &str) -> Option<String> {
+    let mut tokens = contents.split_whitespace();
+    /* `Some(true)` inside the entry of the host, `Some(false)` inside the default entry, `None` elsewhere */
+    let mut entry = None;
+    let mut default = None;
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" => entry = (tokens.next() == Some(host)).then_some(true),
+            "default" => entry = Some(false),
+            "password" => {
+                let password = tokens.next()?;
+                match entry {
+                    Some(true) => return Some(password.to_owned()),
+                    Some(false) if default.is_none() => default = Some(password.to_owned()),
+                    _ => {},
+                }
+            },
+            /* Macros end with an empty line, which can't be seen anymore. They are rare enough */
+            "login" | "account" => {
+                tokens.next();
+            },
+            _ => {},
+        }
+    }
+    default
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_env_variable() {
+        assert_eq!(env_variable("work-gitlab"), "NPINS_CREDENTIAL_WORK_GITLAB");
+        assert_eq!(env_variable("a.b_c"), "NPINS_CREDENTIAL_A_B_C");
+    }
+
+    #[test]
+    fn test_netrc_password() {
+        let netrc = "machine gitlab.com login oauth2 password glpat-one\n\
+                     machine example.org\n  login foo\n  password secret\n\
+                     default login anonymous password guest\n";
+        assert_eq!(
+            netrc_password(netrc, "gitlab.com").as_deref(),
+            Some("glpat-one")
+        );
+        assert_eq!(
+            netrc_password(netrc, "example.org").as_deref(),
+            Some("secret")
+        );
+        assert_eq!(
+            netrc_password(netrc, "example.com").as_deref(),
+            Some("guest")
+        );
+        assert_eq!(
+            netrc_password("machine example.org login foo", "example.org"),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get() {
+        let server: Url = "https://gitlab.example.org/".parse().unwrap();
+        let credentials = Credentials::default()
+            .secret("explicit", "one")
+            .helper(r#"test "$NPINS_CREDENTIAL" = helper && grep -q host=gitlab.example.org && echo password=two"#);
+        assert_eq!(credentials.get("explicit", &server).await.unwrap(), "one");
+        assert_eq!(credentials.get("helper", &server).await.unwrap(), "two");
+        assert!(credentials.get("missing", &server).await.is_err());
+    }
+}
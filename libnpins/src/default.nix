@@ -52,13 +52,31 @@ let
           /. + builtins.getEnv "PWD" + "/${ersatz}"
       );
 
+  # Pins with artifacts per Nix system have a `url` and `hash` for each of them, pick the requested one
+  selectSystem =
+    spec: system:
+    if !(spec ? systems) then
+      spec
+    else if system == null then
+      builtins.throw "The system cannot be determined in pure evaluation mode, pass `system` or `pkgs` explicitly"
+    else if spec.systems ? ${system} then
+      spec // spec.systems.${system}
+    else
+      builtins.throw "No artifact for system ${system}, available are: ${
+        builtins.concatStringsSep ", " (builtins.attrNames spec.systems)
+      }";
+
   mkSource =
-    name: spec:
+    name: rawSpec:
     {
       pkgs ? null,
+      system ?
+        if pkgs != null then pkgs.stdenv.hostPlatform.system else builtins.currentSystem or null,
     }:
-    assert spec ? type;
+    assert rawSpec ? type;
     let
+      spec = selectSystem rawSpec system;
+
       # Unify across builtin and pkgs fetchers.
       # `fetchGit` requires a wrapper because of slight API differences.
       fetchers =
@@ -104,9 +122,18 @@ let
           mkGitSource fetchers spec
         else if spec.type == "PyPi" then
           mkPyPiSource fetchers spec
+        else if spec.type == "Crate" then
+          mkCrateSource pkgs spec
+        else if spec.type == "Npm" then
+          mkNpmSource pkgs spec
         else if spec.type == "Channel" then
           mkChannelSource fetchers spec
-        else if spec.type == "Url" || spec.type == "MutableUrl" then
+        else if
+          spec.type == "Url"
+          || spec.type == "MutableUrl"
+          || spec.type == "PerSystemUrl"
+          || spec.type == "ReleaseAsset"
+        then
           mkUrlSource fetchers spec
         else if spec.type == "Container" then
           mkContainerSource pkgs spec
@@ -181,6 +208,57 @@ let
       sha256 = hash;
     };
 
+  mkCrateSource =
+    pkgs:
+    {
+      name,
+      version,
+      url,
+      hash,
+      ...
+    }:
+    # The hash is the checksum from the registry index, i.e. of the `.crate` file itself.
+    # Unpacking it needs a derivation, as `builtins.fetchTarball` would need the hash of the contents.
+    if pkgs == null then
+      builtins.throw "crate sources require passing in a Nixpkgs value: https://github.com/andir/npins/blob/master/README.md#using-the-nixpkgs-fetchers"
+    else
+      let
+        crate = pkgs.fetchurl {
+          inherit url;
+          name = "${name}-${version}.crate";
+          sha256 = hash;
+        };
+      in
+      pkgs.runCommand "${name}-${version}" { } ''
+        mkdir $out
+        tar -xzf ${crate} -C $out --strip-components=1
+      '';
+
+  mkNpmSource =
+    pkgs:
+    {
+      name,
+      version,
+      url,
+      hash,
+      ...
+    }:
+    # npm publishes SHA-512 hashes, which the builtin fetchers do not support
+    if pkgs == null then
+      builtins.throw "npm sources require passing in a Nixpkgs value: https://github.com/andir/npins/blob/master/README.md#using-the-nixpkgs-fetchers"
+    else
+      let
+        pname = builtins.replaceStrings [ "@" "/" ] [ "" "-" ] name;
+        tarball = pkgs.fetchurl {
+          inherit url hash;
+          name = "${pname}-${version}.tgz";
+        };
+      in
+      pkgs.runCommand "${pname}-${version}" { } ''
+        mkdir $out
+        tar -xzf ${tarball} -C $out --strip-components=1
+      '';
+
   mkChannelSource =
     { fetchTarball, ... }:
     {
@@ -253,7 +331,7 @@ mkFunctor (
         throw "Unsupported input type ${builtins.typeOf input}, must be a path or an attrset";
     version = data.version;
   in
-  if version == 8 then
+  if version == 10 then
     builtins.mapAttrs (name: spec: mkFunctor (mkSource name spec)) data.pins
   else
     throw "Unsupported format version ${toString version} in sources.json. Try running `npins upgrade`"
@@ -1,29 +1,57 @@
 //! Helper tools for generating differential output
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+use serde::Serialize;
+
+/// How a single property changed
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "change", rename_all = "lowercase")]
 pub enum Change {
-    Added(String),
-    Removed(String),
-    Changed(String, String),
+    Added { new: String },
+    Removed { old: String },
+    Changed { old: String, new: String },
+}
+
+impl Change {
+    /// The value before the change, if any
+    pub fn old_value(&self) -> Option<&str> {
+        match self {
+            Change::Removed { old } | Change::Changed { old, .. } => Some(old),
+            Change::Added { .. } => None,
+        }
+    }
+
+    /// The value after the change, if any
+    pub fn new_value(&self) -> Option<&str> {
+        match self {
+            Change::Added { new } | Change::Changed { new, .. } => Some(new),
+            Change::Removed { .. } => None,
+        }
+    }
+}
+
+/// The change of one property of a pin
+///
+/// Serializes to a flat object like `{"property": "revision", "change": "changed", "old": "…", "new": "…"}`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct DiffEntry {
+    pub property: String,
+    #[serde(flatten)]
+    pub change: Change,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct DiffEntry(String, Change);
+impl DiffEntry {
+    pub fn new(property: String, change: Change) -> Self {
+        Self { property, change }
+    }
+}
 
 impl std::fmt::Display for DiffEntry {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let DiffEntry(property, change) = self;
-        match change {
-            Change::Removed(text) | Change::Changed(text, _) => {
-                writeln!(f, "-    {}: {}", property, text)?;
-            },
-            _ => (),
+        if let Some(old) = self.change.old_value() {
+            writeln!(f, "-    {}: {}", self.property, old)?;
         }
-        match change {
-            Change::Added(text) | Change::Changed(_, text) => {
-                writeln!(f, "+    {}: {}", property, text)?;
-            },
-            _ => (),
+        if let Some(new) = self.change.new_value() {
+            writeln!(f, "+    {}: {}", self.property, new)?;
         }
         Ok(())
     }
@@ -43,21 +71,29 @@ pub fn diff(old: &[(String, String)], new: &[(String, String)]) -> Vec<DiffEntry
         match old_keys.remove(new_key) {
             Some(old_value) => {
                 if &old_value != new_value {
-                    changes.push(DiffEntry(
+                    changes.push(DiffEntry::new(
                         new_key.clone(),
-                        Change::Changed(old_value, new_value.clone()),
+                        Change::Changed {
+                            old: old_value,
+                            new: new_value.clone(),
+                        },
                     ));
                 }
             },
             None => {
-                changes.push(DiffEntry(new_key.clone(), Change::Added(new_value.clone())));
+                changes.push(DiffEntry::new(
+                    new_key.clone(),
+                    Change::Added {
+                        new: new_value.clone(),
+                    },
+                ));
             },
         }
     }
 
     /* All remaining keys that weren't matched were removed */
     for (key, value) in old_keys {
-        changes.push(DiffEntry(key.clone(), Change::Removed(value)));
+        changes.push(DiffEntry::new(key, Change::Removed { old: value }));
     }
 
     changes
@@ -97,7 +133,7 @@ where
             None => value
                 .properties()
                 .into_iter()
-                .map(|(key, val)| DiffEntry(key, Change::Added(val)))
+                .map(|(key, new)| DiffEntry::new(key, Change::Added { new }))
                 .collect(),
         };
         *self = Some(value);
@@ -124,7 +160,10 @@ mod test {
 
         assert_eq!(
             None.insert_diffed(TestObject),
-            vec![DiffEntry("foo".into(), Change::Added("bar".into()))],
+            vec![DiffEntry::new(
+                "foo".into(),
+                Change::Added { new: "bar".into() }
+            )],
         );
         assert_eq!(Some(TestObject).insert_diffed(TestObject), vec![],);
     }
@@ -141,13 +180,51 @@ mod test {
         assert_eq!(
             diff(&[(foo(), empty())], &[(bar(), empty())],),
             vec![
-                DiffEntry(bar(), Change::Added(empty())),
-                DiffEntry(foo(), Change::Removed(empty())),
+                DiffEntry::new(bar(), Change::Added { new: empty() }),
+                DiffEntry::new(foo(), Change::Removed { old: empty() }),
             ],
         );
         assert_eq!(
             diff(&[(baz(), foo())], &[(baz(), bar())],),
-            vec![DiffEntry(baz(), Change::Changed(foo(), bar())),],
+            vec![DiffEntry::new(
+                baz(),
+                Change::Changed {
+                    old: foo(),
+                    new: bar()
+                }
+            ),],
+        );
+    }
+
+    #[test]
+    fn test_serialize() {
+        assert_eq!(
+            serde_json::to_value(DiffEntry::new(
+                "revision".into(),
+                Change::Changed {
+                    old: "foo".into(),
+                    new: "bar".into()
+                }
+            ))
+            .unwrap(),
+            serde_json::json!({
+                "property": "revision",
+                "change": "changed",
+                "old": "foo",
+                "new": "bar",
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(DiffEntry::new(
+                "hash".into(),
+                Change::Added { new: "bar".into() }
+            ))
+            .unwrap(),
+            serde_json::json!({
+                "property": "hash",
+                "change": "added",
+                "new": "bar",
+            })
         );
     }
 }
@@ -5,7 +5,7 @@ use git::fetch_default_branch;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
-use crate::{Pin, git, urlpin};
+use crate::{Config, Pin, git, urlpin};
 
 /// Pin entry from a nix flake's lock file
 ///
@@ -65,7 +65,7 @@ impl FlakePin {
 }
 
 impl FlakePin {
-    pub async fn try_to_pin(self: FlakePin) -> Result<Pin, anyhow::Error> {
+    pub async fn try_to_pin(self: FlakePin, config: &Config) -> Result<Pin, anyhow::Error> {
         use FlakeType::*;
 
         // "indirect" inputs (i.e. dependencies of flake dependencies) are
@@ -76,7 +76,8 @@ impl FlakePin {
             Gitlab => {
                 // TODO: parsing the query string to retrieve servers other than
                 // gitlab.com is not supported for now, but could be added.
-                let branch = self.fetch_default_branch("https://gitlab.com").await?;
+                let server = config.hosts().gitlab.clone();
+                let branch = self.fetch_default_branch(config, &server).await?;
                 git::GitPin::new(
                     git::Repository::gitlab(
                         format!(
@@ -88,7 +89,7 @@ impl FlakePin {
                                 .repo
                                 .context("missing field repo in gitlab flake input")?
                         ),
-                        None,
+                        Some(server),
                         None,
                     ),
                     branch,
@@ -97,7 +98,9 @@ impl FlakePin {
                 .into()
             },
             Github => {
-                let branch = self.fetch_default_branch("https://github.com").await?;
+                let branch = self
+                    .fetch_default_branch(config, &config.hosts().github)
+                    .await?;
                 git::GitPin::new(
                     git::Repository::github(
                         self.locked
@@ -137,16 +140,22 @@ impl FlakePin {
         })
     }
 
-    async fn fetch_default_branch(self: &FlakePin, prefix: &str) -> Result<String, anyhow::Error> {
+    async fn fetch_default_branch(
+        self: &FlakePin,
+        config: &Config,
+        server: &Url,
+    ) -> Result<String, anyhow::Error> {
         match &self.original.ref_ {
             Some(a) => Ok(a.to_owned()),
             None => {
-                fetch_default_branch(&Url::parse(&format!(
-                    "{}/{}/{}",
-                    prefix,
-                    self.locked.owner.as_ref().unwrap(),
-                    self.locked.repo.as_ref().unwrap()
-                ))?)
+                fetch_default_branch(
+                    config,
+                    &server.join(&format!(
+                        "{}/{}",
+                        self.locked.owner.as_ref().unwrap(),
+                        self.locked.repo.as_ref().unwrap()
+                    ))?,
+                )
                 .await
             },
         }
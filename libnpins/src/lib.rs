@@ -6,45 +6,45 @@
 use anyhow::Context;
 use diff::{Diff, OptionExt};
 use nix_compat::nixhash::NixHash;
-use reqwest::IntoUrl;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
+use timestamp::Timestamp;
 use url::Url;
+use version_scheme::VersionScheme;
 
 pub mod pins;
+pub use config::Config;
 pub use pins::*;
 
+pub mod cache;
+pub mod changelog;
+pub mod config;
+pub mod constraint;
+pub mod credentials;
 pub mod diff;
 pub mod flake;
+pub mod git_http;
 pub mod niv;
 pub mod nix;
+pub mod outdated;
+pub mod prefetch;
+pub mod rate_limit;
+pub mod retry;
+pub mod schedule;
+pub mod spec;
+pub mod timestamp;
+pub mod version_scheme;
 pub mod versions;
 
 pub const DEFAULT_NIX: &str = include_str!("default.nix");
 
-/// Helper method to build you a client.
-// TODO make injectable via a configuration mechanism
-pub fn build_client() -> Result<reqwest::Client, reqwest::Error> {
-    reqwest::Client::builder()
-        .user_agent(concat!(
-            env!("CARGO_PKG_NAME"),
-            " v",
-            env!("CARGO_PKG_VERSION")
-        ))
-        .build()
-}
-
-/// Helper method for doing various API calls
-async fn get_and_deserialize<T, U>(url: U) -> anyhow::Result<T>
+/// Helper method for doing various API calls, retrying transient failures
+async fn get_and_deserialize<T>(config: &Config, url: Url) -> anyhow::Result<T>
 where
     T: for<'a> Deserialize<'a> + 'static,
-    U: IntoUrl,
 {
-    let response = build_client()?
-        .get(url)
-        .send()
+    let response = retry::send(config, retry::Operation::Request, config.get(url))
         .await?
-        .error_for_status()?
         .text()
         .await?;
     Ok(serde_json::from_str(&response)?)
@@ -58,7 +58,7 @@ where
 ///
 /// If `result` is `Ok`, it is passed on unchanged and nothing is done.
 /// If `result` is `Err`, the check will be executed and the error replaced in case of failure.
-async fn check_url<T>(result: anyhow::Result<T>, url: &str) -> anyhow::Result<T> {
+async fn check_url<T>(config: &Config, result: anyhow::Result<T>, url: &str) -> anyhow::Result<T> {
     if result.is_ok() {
         return result;
     }
@@ -74,19 +74,25 @@ async fn check_url<T>(result: anyhow::Result<T>, url: &str) -> anyhow::Result<T>
      * Some return 405 Method Not Allowed which would be fine, however GitLab for example simply returns
      * 403 Forbidden on HEAD for an URL that is 200 on GET.
      */
-    let Err(response_error) = build_client()?.get(url).send().await?.error_for_status() else {
+    let Err(response_error) = retry::send(config, retry::Operation::Request, config.get(url)).await
+    else {
         return result;
     };
 
-    result.context(response_error)
+    result.context(format!("{response_error:#}"))
 }
 
 /// The git url to a repo has no defined endpoint in the protocol, and thus
 /// may not be routed by all web servers. However, $GIT_REMOTE/info/refs is
 /// a valid endpoint that MUST be implemented by all git servers.
 /// https://git-scm.com/docs/http-protocol
-async fn check_git_url<T>(result: anyhow::Result<T>, git_url: &str) -> anyhow::Result<T> {
+async fn check_git_url<T>(
+    config: &Config,
+    result: anyhow::Result<T>,
+    git_url: &str,
+) -> anyhow::Result<T> {
     check_url(
+        config,
         result,
         &format!("{git_url}/info/refs?service=git-upload-pack"),
     )
@@ -138,10 +144,21 @@ pub trait Updatable:
     /// Fetch the latest applicable commit data
     ///
     /// The old version may be passed to help guarantee monotonicity of the versions.
-    async fn update(&self, old: Option<&Self::Version>) -> anyhow::Result<Self::Version>;
+    async fn update(
+        &self,
+        config: &Config,
+        old: Option<&Self::Version>,
+    ) -> anyhow::Result<Self::Version>;
 
     /// Fetch hashes for a given version
-    async fn fetch(&self, version: &Self::Version) -> anyhow::Result<Self::Hashes>;
+    async fn fetch(&self, config: &Config, version: &Self::Version)
+    -> anyhow::Result<Self::Hashes>;
+
+    /// The host that [`update`](Self::update) and [`fetch`](Self::fetch) mostly talk to
+    ///
+    /// This is used to limit the number of concurrent operations per host, see [`schedule`].
+    /// `None` if the pin doesn't access the network, e.g. for local git repositories.
+    fn host(&self, config: &Config) -> Option<String>;
 }
 
 /// The main struct the CLI operates on
@@ -180,11 +197,41 @@ pub struct GenericVersion {
     /// Note that "version" must be seen in the context of the pin.
     /// Without that context, it shall be treated as opaque string.
     pub version: String,
+    /// When this version was released. Only recorded if a minimum age applies to the pin
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<Timestamp>,
+}
+
+impl GenericVersion {
+    /// Make sure that updating to `latest` does not go backwards
+    ///
+    /// Both versions are parsed with the given scheme. If either of them fails, monotonicity cannot
+    /// be checked and only a warning is emitted.
+    fn ensure_monotonic(&self, latest: &str, scheme: VersionScheme) -> anyhow::Result<()> {
+        match (scheme.parse(&self.version), scheme.parse(latest)) {
+            (Some(old_version), Some(latest_version)) => {
+                anyhow::ensure!(
+                    latest_version >= old_version,
+                    "Failed to ensure version monotonicity, latest found version is {latest} but current is {}",
+                    self.version
+                );
+            },
+            _ => {
+                log::warn!(
+                    "Versions {} and {latest} failed to parse with the {scheme} version scheme, cannot ensure monotonicity",
+                    self.version
+                );
+            },
+        }
+        Ok(())
+    }
 }
 
 impl diff::Diff for GenericVersion {
     fn properties(&self) -> Vec<(String, String)> {
-        vec![("version".into(), self.version.clone())]
+        std::iter::once(("version".into(), self.version.clone()))
+            .chain(self.date.map(|date| ("date".into(), date.to_string())))
+            .collect()
     }
 }
 
@@ -243,6 +290,60 @@ impl diff::Diff for GenericUrlHashes {
     }
 }
 
+/// An URL and its hash for each Nix system, e.g. `x86_64-linux`
+///
+/// `default.nix` selects the entry for the current system and otherwise treats it like a pin with a single URL.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct PerSystemHashes {
+    pub systems: BTreeMap<String, GenericUrlHashes>,
+}
+
+impl PerSystemHashes {
+    /// Fetch the hashes for all systems concurrently
+    pub async fn fetch_all<'a, F, Fut>(
+        urls: impl IntoIterator<Item = (&'a String, &'a Url)>,
+        fetch: F,
+    ) -> anyhow::Result<Self>
+    where
+        F: Fn(&'a Url) -> Fut,
+        Fut: std::future::Future<Output = anyhow::Result<NixHash>>,
+    {
+        let systems = futures_util::future::try_join_all(urls.into_iter().map(|(system, url)| {
+            let hash = fetch(url);
+            async move {
+                let hash = hash
+                    .await
+                    .with_context(|| format!("Failed to fetch the artifact for {system}"))?;
+                Ok::<_, anyhow::Error>((
+                    system.clone(),
+                    GenericUrlHashes {
+                        url: url.clone(),
+                        hash,
+                    },
+                ))
+            }
+        }))
+        .await?;
+        Ok(Self {
+            systems: systems.into_iter().collect(),
+        })
+    }
+}
+
+impl diff::Diff for PerSystemHashes {
+    fn properties(&self) -> Vec<(String, String)> {
+        self.systems
+            .iter()
+            .flat_map(|(system, hashes)| {
+                hashes
+                    .properties()
+                    .into_iter()
+                    .map(move |(key, value)| (format!("{system}.{key}"), value))
+            })
+            .collect()
+    }
+}
+
 fn elide_to_first_line(input_string: &str) -> String {
     let mut lines = input_string.lines();
     let value = lines.next().unwrap_or("(unknown)");
@@ -2,7 +2,8 @@ use anyhow::{Context, Result};
 use nix_compat::nixhash::{HashAlgo, NixHash};
 use std::path::Path;
 
-use crate::{DEFAULT_NIX, check_git_url, check_url, format_command};
+use crate::retry::{self, Operation};
+use crate::{Config, DEFAULT_NIX, check_git_url, check_url, format_command};
 
 #[allow(unused)]
 pub struct PrefetchInfo {
@@ -10,11 +11,15 @@ pub struct PrefetchInfo {
     hash: String,
 }
 
-pub async fn nix_prefetch_tarball(url: impl AsRef<str>) -> Result<NixHash> {
-    nix_prefetch_url(url, true).await
+pub async fn nix_prefetch_tarball(config: &Config, url: impl AsRef<str>) -> Result<NixHash> {
+    nix_prefetch_url(config, url, true).await
 }
 
-pub async fn nix_prefetch_url(url: impl AsRef<str>, unpack: bool) -> Result<NixHash> {
+pub async fn nix_prefetch_url(
+    config: &Config,
+    url: impl AsRef<str>,
+    unpack: bool,
+) -> Result<NixHash> {
     let url = url.as_ref();
     let result = async {
         let mut command = tokio::process::Command::new("nix-prefetch-url");
@@ -30,10 +35,9 @@ pub async fn nix_prefetch_url(url: impl AsRef<str>, unpack: bool) -> Result<NixH
 
         log::debug!("Executing: {}", format_command(&command)?);
 
-        let output = command
-            .output()
+        let output = retry::output(config, Operation::Prefetch, &mut command)
             .await
-            .with_context(|| format!("Failed to spawn nix-prefetch-url for {}", url))?;
+            .with_context(|| format!("Failed to run nix-prefetch-url for {}", url))?;
 
         // FIXME: handle errors and pipe stderr through
         if !output.status.success() {
@@ -55,10 +59,11 @@ pub async fn nix_prefetch_url(url: impl AsRef<str>, unpack: bool) -> Result<NixH
         NixHash::from_nix_nixbase32(&annotated_nix32_hash)
             .with_context(|| format!("failed to convert {} to NixHash", hash_str))
     };
-    check_url(result.await, url).await
+    check_url(config, result.await, url).await
 }
 
 pub async fn nix_prefetch_git(
+    config: &Config,
     url: impl AsRef<str>,
     git_ref: impl AsRef<str>,
     submodules: bool,
@@ -78,14 +83,18 @@ pub async fn nix_prefetch_git(
             .arg(git_ref.as_ref());
 
         log::debug!("Executing: {}", format_command(&command)?);
+        /* Only after logging the command, as they contain the password */
+        command.envs(config.git_credentials_env(url));
 
-        let output = command.output().await.with_context(|| {
-            format!(
-                "Failed to spawn nix-prefetch-git for {} @ {}",
-                url,
-                git_ref.as_ref()
-            )
-        })?;
+        let output = retry::output(config, Operation::Prefetch, &mut command)
+            .await
+            .with_context(|| {
+                format!(
+                    "Failed to run nix-prefetch-git for {} @ {}",
+                    url,
+                    git_ref.as_ref()
+                )
+            })?;
 
         // FIXME: handle errors and pipe stderr through
         if !output.status.success() {
@@ -122,7 +131,7 @@ pub async fn nix_prefetch_git(
         NixHash::from_str(&info.sha256, Some(HashAlgo::Sha256))
             .with_context(|| format!("failed to parse {} as NixHash", &info.sha256))
     };
-    check_git_url(result.await, url).await
+    check_git_url(config, result.await, url).await
 }
 
 #[allow(unused)]
@@ -140,6 +149,7 @@ pub struct NixPrefetchDockerResponse {
 }
 
 pub async fn nix_prefetch_docker(
+    config: &Config,
     image_name: impl AsRef<str>,
     image_tag: impl AsRef<str>,
     arch: &Option<String>,
@@ -165,12 +175,14 @@ pub async fn nix_prefetch_docker(
 
     log::debug!("Executing: {}", format_command(&command)?);
 
-    let output = command.output().await.with_context(|| {
-        format!(
-            "Failed to spawn nix-prefetch-docker for {}:{}",
-            image_name, image_tag
-        )
-    })?;
+    let output = retry::output(config, Operation::Prefetch, &mut command)
+        .await
+        .with_context(|| {
+            format!(
+                "Failed to run nix-prefetch-docker for {}:{}",
+                image_name, image_tag
+            )
+        })?;
 
     // FIXME: handle errors and pipe stderr through
     if !output.status.success() {
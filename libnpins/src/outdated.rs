@@ -0,0 +1,157 @@
+//! Classify available updates of pins, as reported by `npins outdated`
+
+use lenient_version::Version;
+use serde::Serialize;
+
+use crate::diff::DiffEntry;
+
+/// How big an available update is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UpdateKind {
+    Major,
+    Minor,
+    Patch,
+    /// The latest version is a pre-release
+    Prerelease,
+    /// A tracked branch has new commits
+    BranchMoved,
+    /// The versions are not comparable, e.g. for channels
+    Other,
+}
+
+impl std::fmt::Display for UpdateKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            UpdateKind::Major => "major",
+            UpdateKind::Minor => "minor",
+            UpdateKind::Patch => "patch",
+            UpdateKind::Prerelease => "prerelease",
+            UpdateKind::BranchMoved => "branch-moved",
+            UpdateKind::Other => "other",
+        })
+    }
+}
+
+impl UpdateKind {
+    /// Compare two release versions, parsed leniently as SemVer like in `latest_release`
+    pub fn classify(current: &str, latest: &str) -> Self {
+        match (
+            lenient_semver_parser::parse::<Version>(current),
+            lenient_semver_parser::parse::<Version>(latest),
+        ) {
+            (Ok(_), Ok(latest)) if latest.is_pre_release() => UpdateKind::Prerelease,
+            (Ok(current), Ok(latest)) if current.major != latest.major => UpdateKind::Major,
+            (Ok(current), Ok(latest)) if current.minor != latest.minor => UpdateKind::Minor,
+            (Ok(_), Ok(_)) => UpdateKind::Patch,
+            _ => UpdateKind::Other,
+        }
+    }
+}
+
+/// An available update of a pin
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Outdated {
+    /// The current version, `None` if the pin has never been updated
+    pub current: Option<String>,
+    pub latest: String,
+    pub kind: UpdateKind,
+}
+
+impl Outdated {
+    /// Extract the available update from the diff returned by [`Pin::update`](crate::Pin::update)
+    ///
+    /// Returns `None` if the pin is up to date.
+    pub fn from_diff(diff: &[DiffEntry]) -> Option<Self> {
+        let find = |property: &str| diff.iter().find(|entry| entry.property == property);
+
+        let (entry, kind) = if let Some(entry) = find("version") {
+            let kind = match entry.change.old_value() {
+                Some(current) => {
+                    UpdateKind::classify(current, entry.change.new_value().unwrap_or_default())
+                },
+                None => UpdateKind::Other,
+            };
+            (entry, kind)
+        } else if let Some(entry) = find("revision") {
+            (entry, UpdateKind::BranchMoved)
+        } else {
+            (diff.first()?, UpdateKind::Other)
+        };
+
+        Some(Outdated {
+            current: entry.change.old_value().map(str::to_owned),
+            latest: entry.change.new_value()?.to_owned(),
+            kind,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::diff::Change;
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(UpdateKind::classify("1.2.3", "2.0.0"), UpdateKind::Major);
+        assert_eq!(UpdateKind::classify("v1.2.3", "v1.3.0"), UpdateKind::Minor);
+        assert_eq!(UpdateKind::classify("1.2.3", "1.2.4"), UpdateKind::Patch);
+        assert_eq!(UpdateKind::classify("1.2", "1.2.0.1"), UpdateKind::Patch);
+        assert_eq!(
+            UpdateKind::classify("1.2.3", "2.0.0-rc.1"),
+            UpdateKind::Prerelease
+        );
+        assert_eq!(
+            UpdateKind::classify("nixos-24.11", "not a version"),
+            UpdateKind::Other
+        );
+    }
+
+    #[test]
+    fn test_from_diff() {
+        let changed = |property: &str, old: &str, new: &str| {
+            DiffEntry::new(
+                property.into(),
+                Change::Changed {
+                    old: old.into(),
+                    new: new.into(),
+                },
+            )
+        };
+
+        assert_eq!(Outdated::from_diff(&[]), None);
+        assert_eq!(
+            Outdated::from_diff(&[
+                changed("revision", "abc", "def"),
+                changed("version", "1.0.0", "1.1.0"),
+            ]),
+            Some(Outdated {
+                current: Some("1.0.0".into()),
+                latest: "1.1.0".into(),
+                kind: UpdateKind::Minor,
+            })
+        );
+        assert_eq!(
+            Outdated::from_diff(&[changed("revision", "abc", "def")]),
+            Some(Outdated {
+                current: Some("abc".into()),
+                latest: "def".into(),
+                kind: UpdateKind::BranchMoved,
+            })
+        );
+        assert_eq!(
+            Outdated::from_diff(&[DiffEntry::new(
+                "version".into(),
+                Change::Added {
+                    new: "1.0.0".into()
+                }
+            )]),
+            Some(Outdated {
+                current: None,
+                latest: "1.0.0".into(),
+                kind: UpdateKind::Other,
+            })
+        );
+    }
+}
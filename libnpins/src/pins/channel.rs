@@ -5,7 +5,8 @@
 use nix_compat::nixhash::NixHash;
 use serde::{Deserialize, Serialize};
 
-use crate::{Updatable, build_client, diff, nix};
+use crate::retry::{self, Operation};
+use crate::{Config, Updatable, diff, prefetch};
 
 /// Stability note: this may change over time as upstream provides other compression algorithms
 pub const NIXPKGS_ARTIFACT: &str = "nixexprs.tar.xz";
@@ -66,16 +67,21 @@ impl Updatable for Pin {
     type Version = ChannelVersion;
     type Hashes = ChannelHash;
 
-    async fn update(&self, _old: Option<&ChannelVersion>) -> anyhow::Result<ChannelVersion> {
+    async fn update(
+        &self,
+        config: &Config,
+        _old: Option<&ChannelVersion>,
+    ) -> anyhow::Result<ChannelVersion> {
         /* We want to get from something like https://channels.nixos.org/nixos-21.11
          * to https://releases.nixos.org/nixos/21.11/nixos-21.11.335807.df4f1f7cc3f/nixexprs.tar.xz
          */
-        let url = build_client()?
-            .head(format!(
-                "https://channels.nixos.org/{}/{}",
-                self.name, self.artifact,
-            ))
-            .send()
+        let request = config.head(
+            config
+                .hosts()
+                .channels
+                .join(&format!("{}/{}", self.name, self.artifact))?,
+        );
+        let url = retry::send_unchecked(config, Operation::Request, request)
             .await?
             .url()
             .clone();
@@ -83,11 +89,19 @@ impl Updatable for Pin {
         Ok(ChannelVersion { url })
     }
 
-    async fn fetch(&self, version: &ChannelVersion) -> anyhow::Result<Self::Hashes> {
+    async fn fetch(
+        &self,
+        config: &Config,
+        version: &ChannelVersion,
+    ) -> anyhow::Result<Self::Hashes> {
         /* Prefetch an URL that looks like
          * https://releases.nixos.org/nixos/21.11/nixos-21.11.335807.df4f1f7cc3f
          */
-        let hash = nix::nix_prefetch_tarball(&version.url).await?;
+        let hash = prefetch::prefetch_tarball(config, &version.url).await?;
         Ok(Self::Hashes { hash })
     }
+
+    fn host(&self, config: &Config) -> Option<String> {
+        config.hosts().channels.host_str().map(Into::into)
+    }
 }
@@ -2,7 +2,7 @@
 
 use serde::{Deserialize, Serialize};
 
-use crate::{Updatable, diff, nix::nix_prefetch_docker};
+use crate::{Config, Updatable, diff, nix::nix_prefetch_docker};
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub struct Pin {
@@ -54,17 +54,32 @@ impl Updatable for Pin {
     type Version = ContainerVersion;
     type Hashes = ContainerHash;
 
-    async fn update(&self, _old: Option<&ContainerVersion>) -> anyhow::Result<ContainerVersion> {
+    async fn update(
+        &self,
+        config: &Config,
+        _old: Option<&ContainerVersion>,
+    ) -> anyhow::Result<ContainerVersion> {
         Ok(ContainerVersion {
-            image_digest: nix_prefetch_docker(&self.image_name, &self.image_tag, &self.arch, None)
-                .await?
-                .image_digest,
+            image_digest: nix_prefetch_docker(
+                config,
+                &self.image_name,
+                &self.image_tag,
+                &self.arch,
+                None,
+            )
+            .await?
+            .image_digest,
         })
     }
 
-    async fn fetch(&self, version: &ContainerVersion) -> anyhow::Result<ContainerHash> {
+    async fn fetch(
+        &self,
+        config: &Config,
+        version: &ContainerVersion,
+    ) -> anyhow::Result<ContainerHash> {
         Ok(ContainerHash {
             hash: nix_prefetch_docker(
+                config,
                 &self.image_name,
                 &self.image_tag,
                 &self.arch,
@@ -74,22 +89,51 @@ impl Updatable for Pin {
             .hash,
         })
     }
+
+    fn host(&self, _config: &Config) -> Option<String> {
+        Some(registry(&self.image_name).into())
+    }
+}
+
+/// The registry serving an image, following the rules of Docker for references without one
+///
+/// The first path component is only a registry if it looks like a host name, otherwise the image
+/// is on Docker Hub, e.g. `nginx` or `library/nginx`.
+fn registry(image_name: &str) -> &str {
+    match image_name.split_once('/') {
+        Some((registry, _)) if registry.contains(['.', ':']) || registry == "localhost" => registry,
+        _ => "docker.io",
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    fn config() -> Config {
+        Config::builder().build().unwrap()
+    }
+
+    #[test]
+    fn test_registry() {
+        assert_eq!(registry("nginx"), "docker.io");
+        assert_eq!(registry("dperson/torproxy"), "docker.io");
+        assert_eq!(registry("docker.io/dperson/torproxy"), "docker.io");
+        assert_eq!(registry("ghcr.io/owner/image"), "ghcr.io");
+        assert_eq!(registry("localhost:5000/image"), "localhost:5000");
+    }
+
     const DEAD_TEST_CONTAINER: &str = "docker.io/dperson/torproxy";
 
     #[tokio::test]
+    #[ignore = "requires network access"]
     async fn update_and_fetch_container() {
         let pin = Pin {
             image_name: DEAD_TEST_CONTAINER.to_string(),
             image_tag: "latest".to_string(),
             arch: None,
         };
-        let version = pin.update(None).await.unwrap();
+        let version = pin.update(&config(), None).await.unwrap();
         assert_eq!(
             version,
             ContainerVersion {
@@ -99,7 +143,7 @@ mod test {
             }
         );
         assert_eq!(
-            pin.fetch(&version).await.unwrap(),
+            pin.fetch(&config(), &version).await.unwrap(),
             ContainerHash {
                 hash: "sha256-1js//EIumaRXILTRW2fp/uinV0dvfA7CzFPQM7neIUo=".to_string()
             }
@@ -0,0 +1,328 @@
+//! Pin a crate from crates.io or any other registry with a sparse index
+
+use anyhow::{Context, Result};
+use nix_compat::nixhash::{self, NixHash};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use url::Url;
+
+use crate::constraint::Constraint;
+use crate::git::{self, TagFilter, latest_release_until};
+use crate::retry::{self, Operation};
+use crate::timestamp::Timestamp;
+use crate::version_scheme::VersionScheme;
+use crate::{Config, GenericUrlHashes, GenericVersion, Updatable, diff, get_and_deserialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct Pin {
+    pub name: String,
+    /// The sparse index of the registry, without the `sparse+` prefix
+    ///
+    /// If not set, crates.io is used.
+    pub index: Option<Url>,
+    /// Also track pre-releases.
+    #[serde(default)]
+    pub pre_releases: bool,
+    /// Optionally restrict the releases to pin, for example `^1.4`. See [`crate::constraint`]
+    pub constraint: Option<String>,
+    /// Minimum age in days of the releases to pin, overriding the global setting. `0` disables it
+    ///
+    /// Newer releases are skipped in favor of the latest one which is old enough. Sparse indexes
+    /// don't record when a release was published, so this needs a registry with the web API of
+    /// crates.io, announced as `api` in its `config.json`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_age: Option<u32>,
+}
+
+impl diff::Diff for Pin {
+    fn properties(&self) -> Vec<(String, String)> {
+        [
+            Some(("name".into(), self.name.clone())),
+            self.index
+                .as_ref()
+                .map(|index| ("index".into(), index.to_string())),
+            Some(("pre_releases".into(), self.pre_releases.to_string())),
+            self.constraint
+                .as_ref()
+                .map(|constraint| ("constraint".into(), constraint.clone())),
+            self.min_age
+                .map(|min_age| ("min_age".into(), min_age.to_string())),
+        ]
+        .into_iter()
+        .flat_map(Option::into_iter)
+        .collect()
+    }
+}
+
+impl Pin {
+    fn index<'a>(&'a self, config: &'a Config) -> &'a Url {
+        self.index.as_ref().unwrap_or(&config.hosts().crates_index)
+    }
+
+    /// Fetch all releases of the crate from the index
+    async fn releases(&self, config: &Config) -> Result<Vec<IndexEntry>> {
+        let url = self.index(config).join(&index_path(&self.name)?)?;
+        let response = retry::send(config, Operation::Request, config.get(url))
+            .await?
+            .text()
+            .await?;
+        response
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).context("Invalid index entry"))
+            .collect()
+    }
+
+    async fn index_config(&self, config: &Config) -> Result<IndexConfig> {
+        get_and_deserialize(config, self.index(config).join("config.json")?)
+            .await
+            .context("Could not fetch the registry configuration")
+    }
+
+    /// When each release was published, from the web API of the registry
+    ///
+    /// Url template: `$api/api/v1/crates/$name/versions`, with the name as in the index
+    async fn release_dates(
+        &self,
+        config: &Config,
+        name: &str,
+    ) -> Result<HashMap<String, Timestamp>> {
+        let api = self.index_config(config).await?.api.context(
+            "The registry has no web API, which is needed to know the age of the releases",
+        )?;
+        let url = format!(
+            "{}/api/v1/crates/{}/versions",
+            api.trim_end_matches('/'),
+            name
+        );
+        let versions: ApiVersions = get_and_deserialize(config, url.parse()?)
+            .await
+            .with_context(|| format!("Could not fetch the releases of crate {}", self.name))?;
+        Ok(versions
+            .versions
+            .into_iter()
+            .map(|version| (version.num, version.created_at))
+            .collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl Updatable for Pin {
+    type Version = GenericVersion;
+    type Hashes = GenericUrlHashes;
+
+    async fn update(
+        &self,
+        config: &Config,
+        old: Option<&GenericVersion>,
+    ) -> Result<GenericVersion> {
+        let constraint = self
+            .constraint
+            .as_deref()
+            .map(|constraint| Constraint::parse(constraint, VersionScheme::Semver))
+            .transpose()
+            .context("Field `constraint` is invalid")?;
+
+        let entries = self
+            .releases(config)
+            .await
+            .with_context(|| format!("Could not fetch the index entry of crate {}", self.name))?;
+        let releases = git::releases(
+            entries
+                .iter()
+                /* Yanked releases must not be picked up, but may still be pinned explicitly */
+                .filter(|release| !release.yanked)
+                .map(|release| release.vers.as_str()),
+            self.pre_releases,
+            constraint.as_ref(),
+            &TagFilter::All,
+            VersionScheme::Semver,
+        );
+
+        let (latest, date) = match config.min_age(self.min_age) {
+            None => (
+                releases
+                    .into_iter()
+                    .next()
+                    .context("Crate has no matching releases")?,
+                None,
+            ),
+            Some(min_age) => {
+                /* The name may be spelled differently than in the index, e.g. capitalized */
+                let name = entries.first().map_or(&self.name, |entry| &entry.name);
+                let dates = self.release_dates(config, name).await?;
+                match latest_release_until(
+                    releases,
+                    |tag| std::future::ready(Ok(dates.get(&tag).copied())),
+                    old,
+                    VersionScheme::Semver,
+                    min_age,
+                )
+                .await?
+                {
+                    Some((latest, date)) => (latest, Some(date)),
+                    None => return Ok(old.expect("Only the old version can be newer").clone()),
+                }
+            },
+        };
+
+        if let Some(old) = old {
+            old.ensure_monotonic(&latest.name, VersionScheme::Semver)?;
+        }
+
+        Ok(GenericVersion {
+            version: latest.tag,
+            date,
+        })
+    }
+
+    async fn fetch(&self, config: &Config, version: &GenericVersion) -> Result<GenericUrlHashes> {
+        let release = self
+            .releases(config)
+            .await
+            .with_context(|| format!("Could not fetch the index entry of crate {}", self.name))?
+            .into_iter()
+            .find(|release| release.vers == version.version)
+            .with_context(|| format!("Could not find requested version {}", version.version))?;
+
+        let index_config = self.index_config(config).await?;
+
+        let hash = NixHash::from_str(&release.cksum, Some(nixhash::HashAlgo::Sha256))
+            .context("failed to convert to NixHash")?;
+
+        Ok(GenericUrlHashes {
+            url: download_url(&index_config.dl, &release)?
+                .parse()
+                .context("Invalid download URL")?,
+            hash,
+        })
+    }
+
+    fn host(&self, config: &Config) -> Option<String> {
+        self.index(config).host_str().map(Into::into)
+    }
+}
+
+/// Path of a crate's file within the index
+///
+/// See <https://doc.rust-lang.org/cargo/reference/registry-index.html#index-files>
+fn index_path(name: &str) -> Result<String> {
+    let name = name.to_lowercase();
+    Ok(format!("{}/{name}", prefix(&name)?))
+}
+
+/// The directory prefix of a crate name, as used in the index paths and the `{prefix}` marker
+fn prefix(name: &str) -> Result<String> {
+    /* The prefix is made of bytes, crate names are ASCII */
+    anyhow::ensure!(
+        !name.is_empty() && name.is_ascii(),
+        "Invalid crate name `{name}`, crate names consist of ASCII characters"
+    );
+    Ok(match name.len() {
+        1 => "1".into(),
+        2 => "2".into(),
+        3 => format!("3/{}", &name[..1]),
+        _ => format!("{}/{}", &name[..2], &name[2..4]),
+    })
+}
+
+/// Expand the `dl` template of the registry configuration
+///
+/// The name of the crate is the one in the index, as the download servers may not be as lenient
+/// about its spelling as the index paths are.
+///
+/// See <https://doc.rust-lang.org/cargo/reference/registry-index.html#index-configuration>
+fn download_url(template: &str, release: &IndexEntry) -> Result<String> {
+    let name = &release.name;
+    const MARKERS: &[&str] = &[
+        "{crate}",
+        "{version}",
+        "{prefix}",
+        "{lowerprefix}",
+        "{sha256-checksum}",
+    ];
+
+    Ok(if MARKERS.iter().any(|marker| template.contains(marker)) {
+        template
+            .replace("{crate}", name)
+            .replace("{version}", &release.vers)
+            .replace("{prefix}", &prefix(name)?)
+            .replace("{lowerprefix}", &prefix(&name.to_lowercase())?)
+            .replace("{sha256-checksum}", &release.cksum)
+    } else {
+        format!(
+            "{}/{name}/{}/download",
+            template.trim_end_matches('/'),
+            release.vers
+        )
+    })
+}
+
+/// `config.json` at the root of the index, we only need the download URL template and the API
+#[derive(Debug, Deserialize)]
+struct IndexConfig {
+    dl: String,
+    /// Base URL of the web API, e.g. `https://crates.io`. Not all registries have one
+    api: Option<String>,
+}
+
+/// Response of the `versions` endpoint of the web API, again only the parts we need
+#[derive(Debug, Deserialize)]
+struct ApiVersions {
+    versions: Vec<ApiVersion>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiVersion {
+    num: String,
+    created_at: Timestamp,
+}
+
+/// One line of a crate's index file. Again, this is not complete
+#[derive(Debug, Deserialize)]
+struct IndexEntry {
+    /// The name as published, index paths are lowercase
+    name: String,
+    vers: String,
+    /// SHA256 of the `.crate` file, in hex
+    cksum: String,
+    #[serde(default)]
+    yanked: bool,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_index_path() {
+        assert_eq!(index_path("a").unwrap(), "1/a");
+        assert_eq!(index_path("cc").unwrap(), "2/cc");
+        assert_eq!(index_path("syn").unwrap(), "3/s/syn");
+        assert_eq!(index_path("Serde_JSON").unwrap(), "se/rd/serde_json");
+        assert!(index_path("").is_err());
+        assert!(index_path("sérde").is_err());
+    }
+
+    #[test]
+    fn test_download_url() {
+        let release = IndexEntry {
+            name: "Foo".into(),
+            vers: "1.0.0".into(),
+            cksum: "abcd".into(),
+            yanked: false,
+        };
+        assert_eq!(
+            download_url("https://static.crates.io/crates", &release).unwrap(),
+            "https://static.crates.io/crates/Foo/1.0.0/download"
+        );
+        assert_eq!(
+            download_url(
+                "https://example.org/{prefix}/{lowerprefix}/{crate}-{version}.crate?{sha256-checksum}",
+                &release
+            )
+            .unwrap(),
+            "https://example.org/3/F/3/f/Foo-1.0.0.crate?abcd"
+        );
+    }
+}
@@ -8,25 +8,26 @@
 //! for more on this.
 
 use anyhow::{Context, Result};
-use lenient_version::Version;
 use nix_compat::nixhash::NixHash;
+use regex::Regex;
+use reqwest::header::{AUTHORIZATION, HeaderName, HeaderValue};
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::process::Command;
 use url::Url;
 
+use crate::config::Hosts;
+use crate::constraint::Constraint;
+use crate::retry::{self, Operation};
+use crate::timestamp::Timestamp;
+use crate::version_scheme::VersionScheme;
 use crate::{
-    GenericVersion, Updatable, check_git_url, diff, format_command, get_and_deserialize, nix,
+    Config, GenericVersion, Updatable, check_git_url, diff, format_command, get_and_deserialize,
+    git_http, nix, prefetch,
 };
 
-fn get_github_url() -> String {
-    std::env::var("NPINS_GITHUB_HOST").unwrap_or_else(|_| String::from("https://github.com"))
-}
-
-fn get_github_api_url() -> String {
-    std::env::var("NPINS_GITHUB_API_HOST")
-        .unwrap_or_else(|_| String::from("https://api.github.com"))
-}
-
 /// Extract the scheme and host of a domain, stripping the rest
 fn strip_url(mut url: Url) -> Url {
     url.set_query(None);
@@ -39,6 +40,9 @@ fn strip_url(mut url: Url) -> Url {
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct GitRevision {
     revision: String,
+    /// The commit date. Only recorded if a minimum age applies to the pin
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    date: Option<Timestamp>,
 }
 
 impl GitRevision {
@@ -46,13 +50,29 @@ impl GitRevision {
         if !revision.chars().all(|c| c.is_ascii_hexdigit()) || revision.len() != 40 {
             anyhow::bail!("'{revision}' is not a valid git revision (sha1 hash)");
         }
-        Ok(Self { revision })
+        Ok(Self {
+            revision,
+            date: None,
+        })
+    }
+
+    pub fn with_date(self, date: Timestamp) -> Self {
+        Self {
+            date: Some(date),
+            ..self
+        }
+    }
+
+    pub fn date(&self) -> Option<Timestamp> {
+        self.date
     }
 }
 
 impl diff::Diff for GitRevision {
     fn properties(&self) -> Vec<(String, String)> {
-        vec![("revision".into(), self.revision.clone())]
+        std::iter::once(("revision".into(), self.revision.clone()))
+            .chain(self.date.map(|date| ("date".into(), date.to_string())))
+            .collect()
     }
 }
 
@@ -123,10 +143,12 @@ pub enum Repository {
         ///
         /// It must fit into the schema `<server>/<owner>/<repo>` to get a repository's URL.
         server: Url,
-        /// access token for private repositories
-        #[serde(skip_serializing_if = "Option::is_none")]
-        #[serde(default)]
-        private_token: Option<String>,
+        /// Name of the credential with the access token for private repositories
+        ///
+        /// The token itself is never stored in the pin, see [`crate::credentials`] for where it
+        /// is looked up. Without a credential, the `GITLAB_TOKEN` environment variable is used if set.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        credential: Option<String>,
     },
 }
 
@@ -136,7 +158,7 @@ impl Repository {
     /// For forges that can also be self-hosted, only the well-known "main" forge will be taken into account (e.g. gitlab.com).
     /// Only works for HTTP(S) URLs.
     /// Invalid URLs will equally fall back to `Self::Git`, as we can't know for sure what is invalid without trying it out later.
-    pub async fn git_auto(url: Url) -> Self {
+    pub async fn git_auto(config: &Config, url: Url) -> Self {
         let url2 = url.clone();
         match (url.scheme(), url.domain()) {
             ("http" | "https", Some("github.com")) => {
@@ -154,7 +176,7 @@ impl Repository {
                 Self::forgejo_from_url(url)
                     .inspect(|_| log::info!("Auto-detected Forgejo repository (codeberg.org)"))
             },
-            ("http" | "https", _) => Self::probe_forge(url).await,
+            ("http" | "https", _) => Self::probe_forge(config, url).await,
             _ => None,
         }
         .unwrap_or_else(|| {
@@ -166,7 +188,7 @@ impl Repository {
     ///
     /// Takes in a URL of unknown forge and tries to determine which forge the hoster is
     /// And then parse the url into the according Repository Variant
-    async fn probe_forge(url: Url) -> Option<Self> {
+    async fn probe_forge(config: &Config, url: Url) -> Option<Self> {
         log::debug!("Probing {url} for Forgejo and GitLab API endpoints");
 
         /* We probe some known endpoints unique to the respective GitLab and Forgejo APIs to determine if a corresponding server is running */
@@ -187,7 +209,7 @@ impl Repository {
             let probe = |mut test_url: Url| async {
                 test_url.set_path(path);
                 log::debug!("Probing {test_url} to check for {forge_type}");
-                let _: serde_json::Value = get_and_deserialize(test_url).await?;
+                let _: serde_json::Value = get_and_deserialize(config, test_url).await?;
                 Ok::<(), anyhow::Error>(())
             };
 
@@ -261,12 +283,13 @@ impl Repository {
         }
     }
 
-    pub fn gitlab(repo_path: String, server: Option<Url>, private_token: Option<String>) -> Self {
-        let server = server.unwrap_or_else(|| "https://gitlab.com/".parse().unwrap());
+    /// If no server is given, gitlab.com is used
+    pub fn gitlab(repo_path: String, server: Option<Url>, credential: Option<String>) -> Self {
+        let server = server.unwrap_or_else(|| Hosts::default().gitlab);
         Repository::GitLab {
             repo_path,
             server,
-            private_token,
+            credential,
         }
     }
 
@@ -289,11 +312,11 @@ impl Repository {
     }
 
     /// Get the URL to the represented Git repository
-    fn git_url(&self) -> Result<Url> {
+    pub(crate) fn git_url(&self, hosts: &Hosts) -> Result<Url> {
         Ok(match self {
             Repository::Git { url } => url.clone(),
             Repository::GitHub { owner, repo } => {
-                format!("{}/{}/{}.git", get_github_url(), owner, repo).parse()?
+                hosts.github.join(&format!("{owner}/{repo}.git"))?
             },
             Repository::Forgejo {
                 server,
@@ -305,36 +328,24 @@ impl Repository {
                 server
             },
             Repository::GitLab {
-                repo_path,
-                server,
-                private_token,
-            } => {
-                let mut server = server.clone();
-                if let Some(token) = private_token {
-                    server.set_username("oauth2").ok();
-                    server.set_password(Some(token)).ok();
-                } else if let Ok(token) = std::env::var("GITLAB_TOKEN") {
-                    server.set_username("oauth2").ok();
-                    server.set_password(Some(&token)).ok();
-                }
-                server.join(&format!("{}.git", repo_path))?
-            },
+                repo_path, server, ..
+            } => server.join(&format!("{}.git", repo_path))?,
         })
     }
 
+    /// The host serving the repository, `None` for local repositories
+    pub(crate) fn host(&self, hosts: &Hosts) -> Option<String> {
+        self.git_url(hosts).ok()?.host_str().map(Into::into)
+    }
+
     /// Get the url to a tarball of the requested revision
-    fn url(&self, revision: &str) -> Result<Option<Url>> {
+    fn url(&self, hosts: &Hosts, revision: &str) -> Result<Option<Url>> {
         Ok(match self {
             Repository::Git { .. } => None,
             Repository::GitHub { owner, repo } => Some(
-                format!(
-                    "{github}/{owner}/{repo}/archive/{revision}.tar.gz",
-                    github = get_github_url(),
-                    owner = owner,
-                    repo = repo,
-                    revision = revision,
-                )
-                .parse()?,
+                hosts
+                    .github
+                    .join(&format!("{owner}/{repo}/archive/{revision}.tar.gz"))?,
             ),
             Repository::Forgejo {
                 server,
@@ -346,9 +357,7 @@ impl Repository {
                 Some(server)
             },
             Repository::GitLab {
-                repo_path,
-                server,
-                private_token,
+                repo_path, server, ..
             } => {
                 let mut url = server.clone();
                 url.path_segments_mut()
@@ -365,27 +374,19 @@ impl Repository {
                         .iter(),
                     );
                 url.set_query(Some(&format!("sha={}", revision)));
-                if let Some(token) = private_token {
-                    url.set_query(Some(&format!("private_token={}", token)));
-                }
                 Some(url)
             },
         })
     }
 
     /// Get the url to a tarball of the requested release
-    fn release_url(&self, tag: &str) -> Result<Option<Url>> {
+    fn release_url(&self, hosts: &Hosts, tag: &str) -> Result<Option<Url>> {
         Ok(match self {
             Repository::Git { .. } => None,
             Repository::GitHub { owner, repo } => Some(
-                format!(
-                    "{github_api}/repos/{owner}/{repo}/tarball/refs/tags/{tag}",
-                    github_api = get_github_api_url(),
-                    owner = owner,
-                    repo = repo,
-                    tag = tag,
-                )
-                .parse()?,
+                hosts
+                    .github_api
+                    .join(&format!("repos/{owner}/{repo}/tarball/refs/tags/{tag}"))?,
             ),
             Repository::Forgejo {
                 server,
@@ -395,9 +396,7 @@ impl Repository {
                 Some(format!("{server}api/v1/repos/{owner}/{repo}/archive/{tag}.tar.gz",).parse()?)
             },
             Repository::GitLab {
-                repo_path,
-                server,
-                private_token,
+                repo_path, server, ..
             } => {
                 let mut url = server.clone();
                 url.path_segments_mut()
@@ -414,13 +413,373 @@ impl Repository {
                         .iter(),
                     );
                 url.set_query(Some(&format!("sha={}", tag)));
-                if let Some(token) = private_token {
-                    url.set_query(Some(&format!("private_token={}", token)));
-                }
                 Some(url)
             },
         })
     }
+
+    /// Get the API endpoint listing the releases of the repository
+    pub(crate) fn releases_url(&self, hosts: &Hosts) -> Result<Option<Url>> {
+        Ok(match self {
+            Repository::Git { .. } => None,
+            Repository::GitHub { owner, repo } => {
+                let mut url = hosts
+                    .github_api
+                    .join(&format!("repos/{owner}/{repo}/releases"))?;
+                url.set_query(Some("per_page=100"));
+                Some(url)
+            },
+            Repository::Forgejo {
+                server,
+                owner,
+                repo,
+            } => Some(format!("{server}api/v1/repos/{owner}/{repo}/releases?limit=50").parse()?),
+            Repository::GitLab {
+                repo_path, server, ..
+            } => {
+                let mut url = server.clone();
+                url.path_segments_mut()
+                    .map_err(|()| anyhow::format_err!("GitLab server URL must be a base"))?
+                    .extend(["api", "v4", "projects", repo_path, "releases"].iter());
+                url.set_query(Some("per_page=100"));
+                Some(url)
+            },
+        })
+    }
+
+    /// Add the credentials of the repository to the configuration
+    ///
+    /// For GitLab, the access token is sent in the `PRIVATE-TOKEN` header to the API and as the
+    /// password to git. GitHub uses the optional [`GITHUB_CREDENTIAL`] for a higher rate limit of
+    /// its API. Other repositories don't need any, their configuration is returned as is.
+    pub(crate) async fn authenticated(&self, config: &Config) -> Result<Config> {
+        let (server, credential) = match self {
+            Repository::GitLab {
+                server, credential, ..
+            } => (server, credential),
+            Repository::GitHub { .. } => return github_authenticated(config).await,
+            _ => return Ok(config.clone()),
+        };
+        let token = match credential {
+            Some(name) => config.credentials().get(name, server).await?,
+            None => match std::env::var("GITLAB_TOKEN") {
+                Ok(token) => token,
+                Err(_) => return Ok(config.clone()),
+            },
+        };
+
+        let host = server.host_str().unwrap_or_default();
+        let mut value = HeaderValue::from_str(&token)
+            .context("The GitLab token is not a valid header value")?;
+        value.set_sensitive(true);
+        Ok(config
+            .with_header(host, HeaderName::from_static("private-token"), value)
+            .with_git_credentials(host, "oauth2", token))
+    }
+
+    /// Get the URL of a web page comparing two revisions or tags
+    pub fn compare_url(&self, hosts: &Hosts, old: &str, new: &str) -> Result<Option<Url>> {
+        Ok(match self {
+            Repository::Git { .. } => None,
+            Repository::GitHub { owner, repo } => Some(
+                hosts
+                    .github
+                    .join(&format!("{owner}/{repo}/compare/{old}...{new}"))?,
+            ),
+            Repository::Forgejo {
+                server,
+                owner,
+                repo,
+            } => Some(server.join(&format!("{owner}/{repo}/compare/{old}...{new}"))?),
+            Repository::GitLab {
+                repo_path, server, ..
+            } => Some(server.join(&format!("{repo_path}/-/compare/{old}...{new}"))?),
+        })
+    }
+
+    /// List the commits between two revisions or tags through the forge's API, oldest first
+    ///
+    /// Forges may truncate long lists. Returns `None` for plain git repositories.
+    pub async fn commits_between(
+        &self,
+        config: &Config,
+        old: &str,
+        new: &str,
+    ) -> Result<Option<Vec<Commit>>> {
+        let config = &self.authenticated(config).await?;
+        let hosts = config.hosts();
+        let commits = match self {
+            Repository::Git { .. } => return Ok(None),
+            Repository::GitHub { owner, repo } => {
+                let url = hosts
+                    .github_api
+                    .join(&format!("repos/{owner}/{repo}/compare/{old}...{new}"))?;
+                let comparison: GitHubComparison = get_and_deserialize(config, url).await?;
+                comparison.commits.into_iter().map(Commit::from).collect()
+            },
+            /* Forgejo copied the GitHub API */
+            Repository::Forgejo {
+                server,
+                owner,
+                repo,
+            } => {
+                let url = server.join(&format!(
+                    "api/v1/repos/{owner}/{repo}/compare/{old}...{new}"
+                ))?;
+                let comparison: GitHubComparison = get_and_deserialize(config, url).await?;
+                comparison.commits.into_iter().map(Commit::from).collect()
+            },
+            Repository::GitLab {
+                repo_path, server, ..
+            } => {
+                let url = gitlab_api_url(
+                    server,
+                    repo_path,
+                    &["compare"],
+                    &[("from", old), ("to", new)],
+                )?;
+                let comparison: GitLabComparison = get_and_deserialize(config, url).await?;
+                comparison
+                    .commits
+                    .into_iter()
+                    .map(|commit| Commit {
+                        url: commit.web_url.parse().ok(),
+                        id: commit.id,
+                        title: commit.title,
+                    })
+                    .collect()
+            },
+        };
+        Ok(Some(commits))
+    }
+
+    /// Get the commit date of a revision, branch or tag through the forge's API
+    pub async fn commit_date(&self, config: &Config, rev: &str) -> Result<Timestamp> {
+        let config = &self.authenticated(config).await?;
+        let hosts = config.hosts();
+        match self {
+            Repository::Git { .. } => anyhow::bail!(NO_COMMIT_DATES),
+            Repository::GitHub { owner, repo } => {
+                let url = hosts
+                    .github_api
+                    .join(&format!("repos/{owner}/{repo}/commits/{rev}"))?;
+                let commit: GitHubCommit = get_and_deserialize(config, url).await?;
+                commit.date()
+            },
+            Repository::Forgejo {
+                server,
+                owner,
+                repo,
+            } => {
+                let url = server.join(&format!("api/v1/repos/{owner}/{repo}/git/commits/{rev}"))?;
+                let commit: GitHubCommit = get_and_deserialize(config, url).await?;
+                commit.date()
+            },
+            Repository::GitLab {
+                repo_path, server, ..
+            } => {
+                let url = gitlab_api_url(server, repo_path, &["commits", rev], &[])?;
+                let commit: GitLabCommit = get_and_deserialize(config, url).await?;
+                commit.committed_date.context("The commit has no date")
+            },
+        }
+    }
+
+    /// Get the latest commit of a branch which is not newer than `until` through the forge's API
+    ///
+    /// Returns the revision and its commit date, or `None` if all commits are newer.
+    pub async fn latest_commit_until(
+        &self,
+        config: &Config,
+        branch: &str,
+        until: Timestamp,
+    ) -> Result<Option<(String, Timestamp)>> {
+        let config = &self.authenticated(config).await?;
+        let hosts = config.hosts();
+        let until = until.to_string();
+        let commit = match self {
+            Repository::Git { .. } => anyhow::bail!(NO_COMMIT_DATES),
+            Repository::GitHub { owner, repo } => {
+                let mut url = hosts
+                    .github_api
+                    .join(&format!("repos/{owner}/{repo}/commits"))?;
+                url.query_pairs_mut()
+                    .append_pair("sha", branch)
+                    .append_pair("until", &until)
+                    .append_pair("per_page", "1");
+                let commits: Vec<GitHubCommit> = get_and_deserialize(config, url).await?;
+                commits
+                    .into_iter()
+                    .next()
+                    .map(|commit| Ok((commit.date()?, commit.sha)))
+            },
+            Repository::Forgejo {
+                server,
+                owner,
+                repo,
+            } => {
+                let mut url = server.join(&format!("api/v1/repos/{owner}/{repo}/commits"))?;
+                url.query_pairs_mut()
+                    .append_pair("sha", branch)
+                    .append_pair("until", &until)
+                    .append_pair("limit", "1");
+                let commits: Vec<GitHubCommit> = get_and_deserialize(config, url).await?;
+                commits
+                    .into_iter()
+                    .next()
+                    .map(|commit| Ok((commit.date()?, commit.sha)))
+            },
+            Repository::GitLab {
+                repo_path, server, ..
+            } => {
+                let url = gitlab_api_url(
+                    server,
+                    repo_path,
+                    &["commits"],
+                    &[("ref_name", branch), ("until", &until), ("per_page", "1")],
+                )?;
+                let commits: Vec<GitLabCommit> = get_and_deserialize(config, url).await?;
+                commits.into_iter().next().map(|commit| {
+                    Ok((
+                        commit.committed_date.context("The commit has no date")?,
+                        commit.id,
+                    ))
+                })
+            },
+        };
+        commit
+            .transpose()
+            .map(|commit| commit.map(|(date, revision)| (revision, date)))
+    }
+}
+
+/// The credential with the token for the GitHub API, which is optional
+///
+/// The `GITHUB_TOKEN` environment variable is used if it is not set.
+pub const GITHUB_CREDENTIAL: &str = "github";
+
+/// Send the GitHub token, if there is one, as bearer token to the API and as the password to git
+async fn github_authenticated(config: &Config) -> Result<Config> {
+    let hosts = config.hosts();
+    let token = match config
+        .credentials()
+        .find(GITHUB_CREDENTIAL, &hosts.github)
+        .await?
+    {
+        Some(token) => token,
+        None => match std::env::var("GITHUB_TOKEN") {
+            Ok(token) if !token.is_empty() => token,
+            _ => return Ok(config.clone()),
+        },
+    };
+
+    let mut value = HeaderValue::from_str(&format!("Bearer {token}"))
+        .context("The GitHub token is not a valid header value")?;
+    value.set_sensitive(true);
+    Ok(config
+        .with_header(
+            hosts.github_api.host_str().unwrap_or_default(),
+            AUTHORIZATION,
+            value,
+        )
+        .with_git_credentials(
+            hosts.github.host_str().unwrap_or_default(),
+            "x-access-token",
+            token,
+        ))
+}
+
+const NO_COMMIT_DATES: &str = "A minimum age requires the commit dates from the API of a forge, which plain git repositories don't have";
+
+/// Build the URL of an endpoint below `projects/:id/repository` of the GitLab API
+fn gitlab_api_url(
+    server: &Url,
+    repo_path: &str,
+    path: &[&str],
+    query: &[(&str, &str)],
+) -> Result<Url> {
+    let mut url = server.clone();
+    url.path_segments_mut()
+        .map_err(|()| anyhow::format_err!("GitLab server URL must be a base"))?
+        .extend(["api", "v4", "projects", repo_path, "repository"])
+        .extend(path);
+    url.query_pairs_mut().extend_pairs(query);
+    Ok(url)
+}
+
+/// A commit, as listed by [`Repository::commits_between`]
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct Commit {
+    pub id: String,
+    /// The first line of the commit message
+    pub title: String,
+    /// Web page of the commit
+    pub url: Option<Url>,
+}
+
+/// Used by GitHub and Forgejo, again this is not complete
+#[derive(Debug, Deserialize)]
+struct GitHubComparison {
+    commits: Vec<GitHubCommit>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubCommit {
+    sha: String,
+    html_url: Option<String>,
+    commit: GitHubCommitDetails,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubCommitDetails {
+    message: String,
+    committer: Option<GitHubSignature>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubSignature {
+    date: Timestamp,
+}
+
+impl GitHubCommit {
+    fn date(&self) -> Result<Timestamp> {
+        Ok(self
+            .commit
+            .committer
+            .as_ref()
+            .context("The commit has no committer")?
+            .date)
+    }
+}
+
+impl From<GitHubCommit> for Commit {
+    fn from(commit: GitHubCommit) -> Self {
+        Commit {
+            id: commit.sha,
+            title: commit
+                .commit
+                .message
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .into(),
+            url: commit.html_url.and_then(|url| url.parse().ok()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GitLabComparison {
+    commits: Vec<GitLabCommit>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitLabCommit {
+    id: String,
+    title: String,
+    web_url: String,
+    #[serde(default)]
+    committed_date: Option<Timestamp>,
 }
 
 /// Track a given branch on a repository and always use the latest commit
@@ -431,6 +790,12 @@ pub struct GitPin {
     /// Also fetch submodules
     #[serde(default)]
     pub submodules: bool,
+    /// Minimum age in days of the commits to pin, overriding the global setting. `0` disables it
+    ///
+    /// Newer commits are skipped in favor of the latest one which is old enough. This requires
+    /// the commit dates from the API of the forge, so plain git repositories are not supported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_age: Option<u32>,
 }
 
 impl diff::Diff for GitPin {
@@ -438,11 +803,20 @@ impl diff::Diff for GitPin {
         vec![
             (
                 "repository".into(),
-                self.repository.git_url().unwrap().to_string(),
+                self.repository
+                    .git_url(&Hosts::default())
+                    .unwrap()
+                    .to_string(),
             ),
             ("branch".into(), self.branch.clone()),
             ("submodules".into(), self.submodules.to_string()),
         ]
+        .into_iter()
+        .chain(
+            self.min_age
+                .map(|min_age| ("min_age".into(), min_age.to_string())),
+        )
+        .collect()
     }
 }
 
@@ -452,6 +826,7 @@ impl GitPin {
             repository,
             branch,
             submodules,
+            min_age: None,
         }
     }
 }
@@ -461,37 +836,85 @@ impl Updatable for GitPin {
     type Version = GitRevision;
     type Hashes = OptionalUrlHashes;
 
-    async fn update(&self, _old: Option<&GitRevision>) -> Result<GitRevision> {
-        let repo_url = self.repository.git_url()?;
-        let latest = fetch_branch_head(&repo_url, &self.branch)
+    async fn update(&self, config: &Config, old: Option<&GitRevision>) -> Result<GitRevision> {
+        let config = &self.repository.authenticated(config).await?;
+        if let Some(min_age) = config.min_age(self.min_age) {
+            let (revision, date) = self
+                .repository
+                .latest_commit_until(config, &self.branch, Timestamp::now() - min_age)
+                .await
+                .context("Couldn't fetch the latest commit")?
+                .with_context(|| {
+                    format!(
+                        "Branch {} has no commits older than {} days",
+                        self.branch,
+                        min_age.as_secs() / 86400
+                    )
+                })?;
+            /* Don't go back if the current revision is newer, e.g. because the minimum age was raised.
+             * Pins locked before minimum ages existed have no date recorded, so look it up.
+             */
+            if let Some(old) = old
+                && old.revision != revision
+            {
+                let old_date = match old.date {
+                    Some(old_date) => old_date,
+                    None => self
+                        .repository
+                        .commit_date(config, &old.revision)
+                        .await
+                        .with_context(|| {
+                            format!(
+                                "Couldn't fetch the date of the pinned commit {}",
+                                old.revision
+                            )
+                        })?,
+                };
+                if old_date > date {
+                    return Ok(old.clone());
+                }
+            }
+            return Ok(GitRevision {
+                revision,
+                date: Some(date),
+            });
+        }
+
+        let repo_url = self.repository.git_url(config.hosts())?;
+        let latest = fetch_branch_head(config, &repo_url, &self.branch)
             .await
             .context("Couldn't fetch the latest commit")?
             .revision;
 
-        Ok(GitRevision { revision: latest })
+        Ok(GitRevision {
+            revision: latest,
+            date: None,
+        })
     }
 
-    async fn fetch(&self, version: &GitRevision) -> Result<OptionalUrlHashes> {
+    async fn fetch(&self, config: &Config, version: &GitRevision) -> Result<OptionalUrlHashes> {
+        let config = &self.repository.authenticated(config).await?;
+        let repo_url = self.repository.git_url(config.hosts())?;
         if self.submodules {
             Ok(OptionalUrlHashes {
                 url: None,
-                hash: nix::nix_prefetch_git(&self.repository.git_url()?, &version.revision, true)
-                    .await?,
+                hash: nix::nix_prefetch_git(config, &repo_url, &version.revision, true).await?,
             })
         } else {
             // Try to find an URL for fetchtarball first, as it is faster than fetchgit
-            let url = self.repository.url(&version.revision)?;
+            let url = self.repository.url(config.hosts(), &version.revision)?;
             let hash = match url.as_ref() {
-                Some(url) => nix::nix_prefetch_tarball(url).await?,
-                None => {
-                    nix::nix_prefetch_git(&self.repository.git_url()?, &version.revision, false)
-                        .await?
-                },
+                Some(url) => prefetch::prefetch_tarball(config, url).await?,
+                None => nix::nix_prefetch_git(config, &repo_url, &version.revision, false).await?,
             };
 
             Ok(OptionalUrlHashes { url, hash })
         }
     }
+
+    fn host(&self, config: &Config) -> Option<String> {
+        self.repository.host(config.hosts())
+    }
 }
 
 /// Try to follow the latest release of the given project
@@ -501,19 +924,13 @@ pub struct GitReleasePin {
     /// Also track pre-releases.
     #[serde(default)]
     pub pre_releases: bool,
-    /// Optionally restrict to only pin older releases
+    /// Optionally restrict the releases to pin
     ///
-    /// Sometimes, we want to track an older major version separately. For example, set it to
-    /// 2.0 to track 1.* releases. (Note that the bound is exclusive. In mathematical terms, it
-    /// is the infimum and not a maximum, because the set of compatible releases is not closed.)
+    /// Sometimes, we want to track an older major version separately, or skip a broken release.
+    /// For example, `>=1.4, <2, !=1.4.3` or `^1.4`. See [`crate::constraint`] for the syntax.
     ///
-    /// If present, only versions < version_upper_bound will be pinned. This is a restricted
-    /// syntax compared to the "version requirement grammar" with multiple different operators
-    /// that are common in dependency resolution tools. The reason is, that we aren't interested
-    /// in providing lower bounds for releases, so all we need is a "less than" operator.
-    ///
-    /// Versions will be parsed the in the same rather lenient way as the tags themselves.
-    pub version_upper_bound: Option<String>,
+    /// Versions will be parsed with the version scheme, in the same way as the tags themselves.
+    pub constraint: Option<String>,
     /// Optionally filter the considered release names / tags by a prefix
     ///
     /// Some projects have a more elaborate tag structure that
@@ -522,9 +939,29 @@ pub struct GitReleasePin {
     /// those tags that contain the specified prefix and have the
     /// prefix stripped before any version comparison happens.
     pub release_prefix: Option<String>,
+    /// Optionally filter the considered tags by a regular expression, instead of a prefix
+    ///
+    /// The expression must match the whole tag, and its capture groups extract the version
+    /// from it. Multiple groups are joined with dots, for example `curl-(\d+)_(\d+)_(\d+)`
+    /// yields the version `8.5.0` for the tag `curl-8_5_0`, and `foo@(.+)` selects the releases
+    /// of a single package in a monorepo. The pinned version is still the full tag.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag_pattern: Option<String>,
+    /// How the versions are parsed and ordered, lenient SemVer by default
+    ///
+    /// Tags which are not valid versions in the scheme are ignored.
+    #[serde(default, skip_serializing_if = "VersionScheme::is_default")]
+    pub version_scheme: VersionScheme,
     /// Also fetch submodules
     #[serde(default)]
     pub submodules: bool,
+    /// Minimum age in days of the releases to pin, overriding the global setting. `0` disables it
+    ///
+    /// Newer releases are skipped in favor of the latest one which is old enough. The age is the
+    /// one of the tagged commit, which requires the API of the forge, so plain git repositories
+    /// are not supported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_age: Option<u32>,
 }
 
 impl diff::Diff for GitReleasePin {
@@ -532,18 +969,26 @@ impl diff::Diff for GitReleasePin {
         [
             Some((
                 "repository".into(),
-                self.repository.git_url().unwrap().to_string(),
+                self.repository
+                    .git_url(&Hosts::default())
+                    .unwrap()
+                    .to_string(),
             )),
             Some(("pre_releases".into(), self.pre_releases.to_string())),
-            self.version_upper_bound
+            self.constraint
                 .as_ref()
-                .map(|version_upper_bound| {
-                    ("version_upper_bound".into(), version_upper_bound.clone())
-                }),
+                .map(|constraint| ("constraint".into(), constraint.clone())),
             self.release_prefix
                 .as_ref()
                 .map(|release_prefix| ("release_prefix".into(), release_prefix.clone())),
+            self.tag_pattern
+                .as_ref()
+                .map(|tag_pattern| ("tag_pattern".into(), tag_pattern.clone())),
+            (!self.version_scheme.is_default())
+                .then(|| ("version_scheme".into(), self.version_scheme.to_string())),
             Some(("submodules".into(), self.submodules.to_string())),
+            self.min_age
+                .map(|min_age| ("min_age".into(), min_age.to_string())),
         ]
         .into_iter()
         .flat_map(Option::into_iter)
@@ -555,16 +1000,19 @@ impl GitReleasePin {
     pub fn new(
         repository: Repository,
         pre_releases: bool,
-        version_upper_bound: Option<String>,
+        constraint: Option<String>,
         release_prefix: Option<String>,
         submodules: bool,
     ) -> Self {
         Self {
             repository,
             pre_releases,
-            version_upper_bound,
+            constraint,
             release_prefix,
+            tag_pattern: None,
+            version_scheme: VersionScheme::default(),
             submodules,
+            min_age: None,
         }
     }
 }
@@ -574,88 +1022,101 @@ impl Updatable for GitReleasePin {
     type Version = GenericVersion;
     type Hashes = ReleasePinHashes;
 
-    async fn update(&self, old: Option<&GenericVersion>) -> Result<GenericVersion> {
-        let repo_url = self.repository.git_url()?;
-
-        let version_upper_bound: Option<Version<'_>> = self
-            .version_upper_bound
+    async fn update(
+        &self,
+        config: &Config,
+        old: Option<&GenericVersion>,
+    ) -> Result<GenericVersion> {
+        let config = &self.repository.authenticated(config).await?;
+        let repo_url = self.repository.git_url(config.hosts())?;
+
+        let scheme = self.version_scheme;
+        let constraint = self
+            .constraint
             .as_deref()
-            .map(lenient_semver_parser::parse::<Version>)
+            .map(|constraint| Constraint::parse(constraint, scheme))
             .transpose()
-            .map_err(|err| err.owned())
-            .context("Field `version_upper_bound` is invalid")?;
+            .context("Field `constraint` is invalid")?;
+        let filter = TagFilter::new(self.release_prefix.as_deref(), self.tag_pattern.as_deref())?;
 
-        let latest = latest_release(
-            fetch_tags(&repo_url)
+        let releases = releases(
+            fetch_tags(config, &repo_url)
                 .await
                 .context("Couldn't fetch the release tags")?
                 .iter()
                 /* Strip the common prefix, filter those that don't have it (that should actually never happen) */
                 .filter_map(|tag| tag.ref_.strip_prefix("refs/tags/")),
             self.pre_releases,
-            version_upper_bound.as_ref(),
-            self.release_prefix.as_deref(),
-        ).context("Repository has no matching release tags")?;
-
-        // If we have a release prefix strip it from the previous version for semver comparison.
-        // If the old version didn't have a prefix we keep it as is.
-        let old = match (old, &self.release_prefix) {
-            (Some(version), None) => Some(version.clone()),
-            (Some(old), Some(prefix)) => {
-                let version = match old.version.strip_prefix(prefix) {
-                    None => old.version.clone(),
-                    Some(v) => v.into(),
+            constraint.as_ref(),
+            &filter,
+            scheme,
+        );
+
+        let current = old.cloned();
+        // If we have a release prefix or pattern, extract the version from the previous tag for
+        // version comparison. If the old version doesn't match, we keep it as is.
+        let old = old.map(|old| GenericVersion {
+            version: filter
+                .version(&old.version)
+                .map_or_else(|| old.version.clone(), Cow::into_owned),
+            date: None,
+        });
+
+        let (latest, date) = match config.min_age(self.min_age) {
+            None => (
+                releases
+                    .into_iter()
+                    .next()
+                    .context("Repository has no matching release tags")?,
+                None,
+            ),
+            Some(min_age) => {
+                let date = |tag: String| async move {
+                    self.repository
+                        .commit_date(config, &tag)
+                        .await
+                        .with_context(|| format!("Couldn't fetch the date of {tag}"))
+                        .map(Some)
                 };
-                Some(GenericVersion { version })
+                match latest_release_until(releases, date, old.as_ref(), scheme, min_age).await? {
+                    Some((latest, date)) => (latest, Some(date)),
+                    None => return Ok(current.expect("Only the old version can be newer")),
+                }
             },
-            (None, _) => None,
         };
 
         if let Some(old) = old {
-            let old_version = lenient_semver_parser::parse::<Version>(&old.version);
-            let latest = lenient_semver_parser::parse::<Version>(&latest.name)
-                /* The first thing we do is filter tags with this exact requirement. */
-                .expect("Latest version must parse as SemVer");
-            match old_version {
-                Ok(old_version) => {
-                    anyhow::ensure!(
-                        latest >= old_version,
-                        "Failed to ensure version monotonicity, latest found version is {latest} but current is {old_version}"
-                    );
-                },
-                Err(_) => {
-                    log::warn!(
-                        "Old version ({}) failed to parse as SemVer, cannot ensure monotonicity",
-                        old.version
-                    );
-                },
-            }
+            old.ensure_monotonic(&latest.name, scheme)?;
         }
 
         Ok(GenericVersion {
             version: latest.tag,
+            date,
         })
     }
 
-    async fn fetch(&self, version: &GenericVersion) -> Result<ReleasePinHashes> {
-        let repo_url = self.repository.git_url()?;
+    async fn fetch(&self, config: &Config, version: &GenericVersion) -> Result<ReleasePinHashes> {
+        let config = &self.repository.authenticated(config).await?;
+        let repo_url = self.repository.git_url(config.hosts())?;
 
-        let revision = fetch_ref(&repo_url, format!("refs/tags/{}", version.version))
+        let revision = fetch_ref(config, &repo_url, format!("refs/tags/{}", version.version))
             .await?
             .revision;
 
         if self.submodules {
             Ok(ReleasePinHashes {
                 url: None,
-                hash: nix::nix_prefetch_git(&repo_url, &revision, true).await?,
+                hash: nix::nix_prefetch_git(config, &repo_url, &revision, true).await?,
                 revision,
             })
         } else {
             // Try to find an URL for fetchtarball first, as it is faster than fetchgit
-            let url = self.repository.release_url(&version.version)?;
+            let url = self
+                .repository
+                .release_url(config.hosts(), &version.version)?;
             let hash = match url.as_ref() {
-                Some(url) => nix::nix_prefetch_tarball(url).await?,
-                None => nix::nix_prefetch_git(&repo_url, &revision, false).await?,
+                Some(url) => prefetch::prefetch_tarball(config, url).await?,
+                None => nix::nix_prefetch_git(config, &repo_url, &revision, false).await?,
             };
             Ok(ReleasePinHashes {
                 url,
@@ -664,6 +1125,10 @@ impl Updatable for GitReleasePin {
             })
         }
     }
+
+    fn host(&self, config: &Config) -> Option<String> {
+        self.repository.host(config.hosts())
+    }
 }
 
 /// Output of `git ls-remote`
@@ -673,6 +1138,16 @@ pub struct RemoteInfo {
     pub ref_: String,
 }
 
+/// Like `git ls-remote --refs`, peeled tags are ignored
+impl From<git_http::Ref> for RemoteInfo {
+    fn from(ref_: git_http::Ref) -> Self {
+        Self {
+            revision: ref_.oid,
+            ref_: ref_.name,
+        }
+    }
+}
+
 impl RemoteInfo {
     pub fn new(revision: impl Into<String>, ref_: impl Into<String>) -> Self {
         Self {
@@ -683,7 +1158,9 @@ impl RemoteInfo {
 }
 
 /// Convenience wrapper around calling `git ls-remote`
-async fn fetch_remote(url: &str, args: &[&str]) -> Result<Vec<RemoteInfo>> {
+///
+/// This is only used for remotes which [`git_http`] can't handle, like SSH or local repositories.
+async fn fetch_remote(config: &Config, url: &str, args: &[&str]) -> Result<Vec<RemoteInfo>> {
     let result = async {
         let mut command = Command::new("git");
         command
@@ -694,9 +1171,10 @@ async fn fetch_remote(url: &str, args: &[&str]) -> Result<Vec<RemoteInfo>> {
             .args(args);
 
         log::debug!("Executing: {}", format_command(&command)?);
+        /* Only after logging the command, as they contain the password */
+        command.envs(config.git_credentials_env(url));
 
-        let process = command
-            .output()
+        let process = retry::output(config, Operation::Git, &mut command)
             .await
             .context("Failed waiting for git ls-remote subprocess")?;
         if !process.status.success() {
@@ -734,16 +1212,152 @@ async fn fetch_remote(url: &str, args: &[&str]) -> Result<Vec<RemoteInfo>> {
             })
             .collect::<Result<Vec<RemoteInfo>>>()
     };
-    check_git_url(result.await, url).await
+    check_git_url(config, result.await, url).await
+}
+
+/// The refs of a remote starting with `prefix`, and for `HEAD` its target
+///
+/// Pins often share a remote, e.g. on different branches, and release pins need the tags in
+/// both `update` and `fetch`. With a request cache in the configuration, the queries are cached
+/// by the URL and the credentials used for it, see [`RefQueries`] for which ones are made.
+/// Without one, only the refs starting with `prefix` are queried, as listing all refs of
+/// repositories like nixpkgs takes a while.
+async fn list_refs(config: &Config, repo: &Url, prefix: &str) -> Result<Vec<git_http::Ref>> {
+    if !config.has_request_cache() {
+        return query_refs(config, repo, prefix).await;
+    }
+    let remote = format!("{repo} {:?}", config.git_credentials(repo));
+    let queries: Arc<Mutex<RefQueries>> = config
+        .cached(format!("ref queries {remote}"), async {
+            Ok(Arc::default())
+        })
+        .await?;
+    let query = queries.lock().unwrap().query(prefix);
+    let refs = config
+        .cached(
+            format!("refs {remote} {query}"),
+            query_refs(config, repo, &query),
+        )
+        .await?;
+    Ok(refs
+        .into_iter()
+        .filter(|ref_| ref_.name.starts_with(prefix))
+        .collect())
+}
+
+/// Query the refs of a remote starting with `prefix`, or all branches and tags and `HEAD` if it is empty
+async fn query_refs(config: &Config, repo: &Url, prefix: &str) -> Result<Vec<git_http::Ref>> {
+    let prefixes = match prefix {
+        "" => vec!["HEAD", "refs/heads/", "refs/tags/"],
+        prefix => vec![prefix],
+    };
+    if git_http::supports(repo) {
+        return git_http::ls_refs(config, repo, &prefixes).await;
+    }
+    /* `git ls-remote` takes patterns instead of prefixes, which match the end of the ref names */
+    let patterns: Vec<String> = prefixes
+        .iter()
+        .map(|prefix| match *prefix {
+            "HEAD" => "HEAD".into(),
+            prefix => format!("{prefix}*"),
+        })
+        .collect();
+    let mut args = vec!["--symref", repo.as_str()];
+    args.extend(patterns.iter().map(String::as_str));
+    let refs = refs_from_ls_remote(fetch_remote(config, repo.as_str(), &args).await?);
+    Ok(refs
+        .into_iter()
+        .filter(|ref_| prefixes.iter().any(|prefix| ref_.name.starts_with(prefix)))
+        .collect())
+}
+
+/// The ref prefixes queried on one remote, to share the results between lookups
+///
+/// The first lookup only queries the refs it needs, as most of the time it is the only one, e.g.
+/// when adding a pin. Lookups covered by an earlier query reuse its result, like the tag of a
+/// release pin in `fetch` after listing all tags in `update`. Any other lookup lists all branches
+/// and tags once, which covers all later ones too.
+#[derive(Debug, Default)]
+struct RefQueries {
+    prefixes: Vec<String>,
+}
+
+impl RefQueries {
+    /// The prefix to query for a lookup of the refs starting with `prefix`, empty for all refs
+    fn query(&mut self, prefix: &str) -> String {
+        if let Some(query) = self
+            .prefixes
+            .iter()
+            .find(|query| prefix.starts_with(query.as_str()))
+        {
+            return query.clone();
+        }
+        let query = if self.prefixes.is_empty() {
+            prefix.to_owned()
+        } else {
+            String::new()
+        };
+        self.prefixes.push(query.clone());
+        query
+    }
+}
+
+/// Turn the output of `git ls-remote --symref` into refs like the ones of `ls-refs`
+///
+/// Symbolic refs are printed as `ref: <target>`, and the peeled object of annotated tags on an
+/// extra line with the name suffixed by `^{}`.
+fn refs_from_ls_remote(remotes: Vec<RemoteInfo>) -> Vec<git_http::Ref> {
+    let mut refs: Vec<git_http::Ref> = Vec::new();
+    let mut symrefs = Vec::new();
+    for RemoteInfo { revision, ref_ } in remotes {
+        if let Some(target) = revision.strip_prefix("ref: ") {
+            symrefs.push((ref_, target.to_owned()));
+        } else if let Some(name) = ref_.strip_suffix("^{}") {
+            if let Some(tag) = refs.iter_mut().find(|tag| tag.name == name) {
+                tag.peeled = Some(revision);
+            }
+        } else {
+            refs.push(git_http::Ref {
+                name: ref_,
+                oid: revision,
+                symref_target: None,
+                peeled: None,
+            });
+        }
+    }
+    for (name, target) in symrefs {
+        if let Some(symref) = refs.iter_mut().find(|symref| symref.name == name) {
+            symref.symref_target = Some(target);
+        }
+    }
+    refs
 }
 
 /// Get the commit for a ref
-pub async fn fetch_ref(repo: &Url, ref_: impl AsRef<str>) -> Result<RemoteInfo> {
+///
+/// Branches and tags are looked up with [`list_refs`], other refs are queried directly.
+pub async fn fetch_ref(config: &Config, repo: &Url, ref_: impl AsRef<str>) -> Result<RemoteInfo> {
     let ref_ = ref_.as_ref();
 
-    let remotes = fetch_remote(repo.as_str(), &["--refs", repo.as_str(), ref_])
-        .await
-        .with_context(|| format!("Failed to get revision from remote for {} {}", repo, ref_))?;
+    if ref_.starts_with("refs/heads/") || ref_.starts_with("refs/tags/") {
+        let refs = list_refs(config, repo, ref_)
+            .await
+            .with_context(|| format!("Failed to get revision from remote for {} {}", repo, ref_))?;
+        return refs
+            .into_iter()
+            .find(|r| r.name == ref_)
+            .map(RemoteInfo::from)
+            .with_context(|| format!("Remote {repo} has no ref '{ref_}'. Note: If you want to tag a revision, you need to also specify a branch ('--branch')."));
+    }
+
+    let remotes = if git_http::supports(repo) {
+        git_http::ls_refs(config, repo, &[ref_])
+            .await
+            .map(|refs| refs.into_iter().map(RemoteInfo::from).collect())
+    } else {
+        fetch_remote(config, repo.as_str(), &["--refs", repo.as_str(), ref_]).await
+    }
+    .with_context(|| format!("Failed to get revision from remote for {} {}", repo, ref_))?;
 
     anyhow::ensure!(
         !remotes.is_empty(),
@@ -759,42 +1373,40 @@ pub async fn fetch_ref(repo: &Url, ref_: impl AsRef<str>) -> Result<RemoteInfo>
 }
 
 /// Get the revision for a branch
-pub async fn fetch_branch_head(repo: &Url, branch: impl AsRef<str>) -> Result<RemoteInfo> {
-    fetch_ref(repo, format!("refs/heads/{}", branch.as_ref())).await
+pub async fn fetch_branch_head(
+    config: &Config,
+    repo: &Url,
+    branch: impl AsRef<str>,
+) -> Result<RemoteInfo> {
+    fetch_ref(config, repo, format!("refs/heads/{}", branch.as_ref())).await
 }
 
 /// List all tags of a repo
-pub async fn fetch_tags(repo: &Url) -> Result<Vec<RemoteInfo>> {
-    let remotes = fetch_remote(repo.as_str(), &["--refs", repo.as_str(), "refs/tags/*"])
+pub async fn fetch_tags(config: &Config, repo: &Url) -> Result<Vec<RemoteInfo>> {
+    let refs = list_refs(config, repo, "refs/tags/")
         .await
         .with_context(|| format!("Failed to list tags for {}", repo))?;
-
-    Ok(remotes)
+    Ok(refs.into_iter().map(RemoteInfo::from).collect())
 }
 
-pub async fn fetch_default_branch(repo: &Url) -> Result<String> {
-    let remotes = fetch_remote(repo.as_str(), &["--symref", repo.as_str(), "HEAD"])
+pub async fn fetch_default_branch(config: &Config, repo: &Url) -> Result<String> {
+    let refs = list_refs(config, repo, "HEAD")
         .await
         .with_context(|| format!("Failed to resolve default branch for {}", repo))?;
-
-    let info = remotes
-        .iter()
-        .find(|info| info.revision.starts_with("ref: refs/heads/") && info.ref_ == "HEAD")
-        .with_context(|| format!("Failed to resolve HEAD to a ref for {}", repo))?;
-
-    info.revision
-        .strip_prefix("ref: refs/heads/")
-        .map(|s| s.to_owned())
-        .with_context(|| format!("Failed to parse git ls-remote output for {}", repo))
+    refs.into_iter()
+        .find(|ref_| ref_.name == "HEAD")
+        .and_then(|ref_| ref_.symref_target)
+        .and_then(|target| target.strip_prefix("refs/heads/").map(str::to_owned))
+        .with_context(|| format!("Failed to resolve HEAD to a ref for {}", repo))
 }
 
 #[cfg_attr(test, derive(PartialEq, Debug))]
-struct LatestRelease {
+pub(crate) struct LatestRelease {
     /// The tag as used by git, e.g. release/2.0
-    tag: String,
+    pub(crate) tag: String,
 
     /// The tag as communicated to the user, e.g. 2.0
-    name: String,
+    pub(crate) name: String,
 }
 
 #[cfg(test)]
@@ -808,43 +1420,143 @@ impl LatestRelease {
     }
 }
 
+/// Selects the release tags and extracts their version, see [`GitReleasePin::release_prefix`]
+/// and [`GitReleasePin::tag_pattern`]
+#[derive(Debug, Clone, Default)]
+pub(crate) enum TagFilter {
+    /// Every tag is a release and its own version
+    #[default]
+    All,
+    Prefix(String),
+    Pattern(Regex),
+}
+
+impl TagFilter {
+    pub(crate) fn new(release_prefix: Option<&str>, tag_pattern: Option<&str>) -> Result<Self> {
+        Ok(match (release_prefix, tag_pattern) {
+            (None, None) => Self::All,
+            (Some(prefix), None) => Self::Prefix(prefix.into()),
+            (None, Some(pattern)) => {
+                /* The pattern must match the whole tag */
+                let regex = Regex::new(&format!("^(?:{pattern})$"))
+                    .context("Field `tag_pattern` is not a valid regular expression")?;
+                anyhow::ensure!(
+                    regex.captures_len() > 1,
+                    "Field `tag_pattern` must contain a capture group for the version"
+                );
+                Self::Pattern(regex)
+            },
+            (Some(_), Some(_)) => {
+                anyhow::bail!("Only one of `release_prefix` and `tag_pattern` may be set")
+            },
+        })
+    }
+
+    /// The version part of a tag, or `None` if the tag doesn't match
+    pub(crate) fn version<'a>(&self, tag: &'a str) -> Option<Cow<'a, str>> {
+        match self {
+            Self::All => Some(Cow::Borrowed(tag)),
+            Self::Prefix(prefix) => tag.strip_prefix(prefix.as_str()).map(Cow::Borrowed),
+            Self::Pattern(regex) => {
+                let captures = regex.captures(tag)?;
+                let groups: Vec<&'a str> = captures
+                    .iter()
+                    .skip(1)
+                    .flatten()
+                    .map(|group| group.as_str())
+                    .collect();
+                match groups[..] {
+                    [] => None,
+                    [version] => Some(Cow::Borrowed(version)),
+                    _ => Some(Cow::Owned(groups.join("."))),
+                }
+            },
+        }
+    }
+}
+
 /// Take an iterator of tags and spit out the latest release
-fn latest_release<'a>(
+pub(crate) fn latest_release<'a>(
     tags: impl Iterator<Item = &'a str>,
     pre_releases: bool,
-    version_upper_bound: Option<&Version>,
-    prefix: Option<&str>,
+    constraint: Option<&Constraint>,
+    filter: &TagFilter,
+    scheme: VersionScheme,
 ) -> Option<LatestRelease> {
-    // Optionally filter all tags by a prefix
-    let tags: Box<dyn Iterator<Item = &'a str>> = match prefix {
-        None => Box::new(tags),
-        Some(prefix) => Box::new(tags.filter_map(move |tag| tag.strip_prefix(prefix))),
-    };
+    releases(tags, pre_releases, constraint, filter, scheme)
+        .into_iter()
+        .next()
+}
 
-    let tag = tags
+/// Take an iterator of tags and spit out all releases, the latest first
+pub(crate) fn releases<'a>(
+    tags: impl Iterator<Item = &'a str>,
+    pre_releases: bool,
+    constraint: Option<&Constraint>,
+    filter: &TagFilter,
+    scheme: VersionScheme,
+) -> Vec<LatestRelease> {
+    // Filter the tags and extract their version, e.g. by stripping a prefix
+    let tags: Vec<(&str, Cow<str>)> = tags
+        .filter_map(|tag| filter.version(tag).map(|name| (tag, name)))
+        .collect();
+
+    let mut tags = tags
+        .iter()
         /* Try to parse as version, ignore those that are invalid (not every tag will be a release) */
-        .filter_map(|tag| lenient_semver_parser::parse::<Version>(tag)
-            .ok()
-            .map(|version| (tag, version))
-        )
+        .filter_map(|(tag, name)| scheme.parse(name).map(|version| ((*tag, name), version)))
         /* Optionally filter out pre-releases */
         .filter(|(_, version)| pre_releases || !version.is_pre_release())
-        /* Filter against our upper bound */
-        .filter(|(_, version)| match &version_upper_bound {
-            Some(version_upper_bound) => version < version_upper_bound,
-            None => true,
+        /* Filter against our constraint */
+        .filter(|(_, version)| constraint.is_none_or(|constraint| constraint.matches(version)))
+        .collect::<Vec<_>>();
+
+    /* Latest version first. The sort is stable, so among equal versions the last tag wins */
+    tags.sort_by(|(_, version_a), (_, version_b)| version_a.cmp(version_b));
+    tags.into_iter()
+        .rev()
+        .map(|((tag, name), _)| LatestRelease {
+            tag: tag.to_owned(),
+            name: name.to_string(),
         })
-        /* Get the latest version */
-        .max_by(|(_, version_a), (_, version_b)| version_a.cmp(version_b))
-        .map(|(tag, _)| tag.to_owned());
-
-    tag.map(|tag| LatestRelease {
-        tag: match prefix {
-            Some(p) => format!("{p}{tag}"),
-            None => tag.clone(),
-        },
-        name: tag,
-    })
+        .collect()
+}
+
+/// Take the releases from [`releases`] and pick the latest one published before the minimum age, with its date
+///
+/// The dates are looked up by tag, one release after the other until one is old enough. Releases
+/// without a date are skipped. Returns `None` if that would go back from the `old` version, e.g.
+/// because the minimum age was raised.
+pub(crate) async fn latest_release_until<F>(
+    releases: Vec<LatestRelease>,
+    date: impl Fn(String) -> F,
+    old: Option<&GenericVersion>,
+    scheme: VersionScheme,
+    min_age: Duration,
+) -> Result<Option<(LatestRelease, Timestamp)>>
+where
+    F: Future<Output = Result<Option<Timestamp>>>,
+{
+    let old_version = old.and_then(|old| scheme.parse(&old.version));
+    let until = Timestamp::now() - min_age;
+    for release in releases {
+        if let Some(old_version) = &old_version
+            && scheme
+                .parse(&release.name)
+                .is_some_and(|version| &version <= old_version)
+        {
+            return Ok(None);
+        }
+        if let Some(date) = date(release.tag.clone()).await?
+            && date <= until
+        {
+            return Ok(Some((release, date)));
+        }
+    }
+    anyhow::bail!(
+        "No matching versions older than {} days found",
+        min_age.as_secs() / 86400
+    )
 }
 
 /* All repositories used for tests are dead, super dead, or
@@ -857,19 +1569,41 @@ fn latest_release<'a>(
 mod test {
     use super::*;
 
+    fn config() -> Config {
+        Config::builder().build().unwrap()
+    }
+
     #[tokio::test]
     async fn test_latest_release() {
-        let v2 = lenient_semver_parser::parse::<Version>("2").unwrap();
+        let v2 = Constraint::parse("<2", VersionScheme::Semver).unwrap();
         assert_eq!(
-            latest_release(["foo"].iter().copied(), false, None, None),
+            latest_release(
+                ["foo"].iter().copied(),
+                false,
+                None,
+                &TagFilter::All,
+                VersionScheme::Semver
+            ),
             None
         );
         assert_eq!(
-            latest_release(["1.0", "foo"].iter().copied(), false, None, None),
+            latest_release(
+                ["1.0", "foo"].iter().copied(),
+                false,
+                None,
+                &TagFilter::All,
+                VersionScheme::Semver
+            ),
             Some(LatestRelease::tag("1.0"))
         );
         assert_eq!(
-            latest_release(["1.0", "2.0"].iter().copied(), false, Some(&v2), None),
+            latest_release(
+                ["1.0", "2.0"].iter().copied(),
+                false,
+                Some(&v2),
+                &TagFilter::All,
+                VersionScheme::Semver
+            ),
             Some(LatestRelease::tag("1.0"))
         );
         assert_eq!(
@@ -877,7 +1611,8 @@ mod test {
                 ["1.0", "2.0", "2.0-pre"].iter().copied(),
                 false,
                 Some(&v2),
-                None
+                &TagFilter::All,
+                VersionScheme::Semver
             ),
             Some(LatestRelease::tag("1.0"))
         );
@@ -886,10 +1621,21 @@ mod test {
                 ["1.0", "2.0", "2.0-pre"].iter().copied(),
                 true,
                 Some(&v2),
-                None
+                &TagFilter::All,
+                VersionScheme::Semver
             ),
             Some(LatestRelease::tag("2.0-pre"))
         );
+        assert_eq!(
+            latest_release(
+                ["1.0", "1.4", "1.5", "2.0"].iter().copied(),
+                false,
+                Some(&Constraint::parse(">=1.0, <2, !=1.5", VersionScheme::Semver).unwrap()),
+                &TagFilter::All,
+                VersionScheme::Semver
+            ),
+            Some(LatestRelease::tag("1.4"))
+        );
 
         assert_eq!(
             latest_release(
@@ -905,7 +1651,8 @@ mod test {
                 .copied(),
                 false,
                 None,
-                Some("zes/")
+                &TagFilter::Prefix("zes/".into()),
+                VersionScheme::Semver
             ),
             Some(LatestRelease {
                 tag: "zes/2.0".into(),
@@ -914,9 +1661,178 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_releases() {
+        assert_eq!(
+            releases(
+                ["1.0", "foo", "2.0", "1.1", "2.1-rc1"].iter().copied(),
+                false,
+                None,
+                &TagFilter::All,
+                VersionScheme::Semver
+            ),
+            vec![
+                LatestRelease::tag("2.0"),
+                LatestRelease::tag("1.1"),
+                LatestRelease::tag("1.0"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_version_schemes() {
+        let releases = |tags: &[&str], scheme| {
+            releases(tags.iter().copied(), false, None, &TagFilter::All, scheme)
+                .into_iter()
+                .map(|release| release.tag)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            releases(
+                &["24.04", "23.10", "24.10", "24.10-rc1", "foo"],
+                VersionScheme::Calver
+            ),
+            vec!["24.10", "24.04", "23.10"]
+        );
+        assert_eq!(
+            releases(
+                &["2024-01-15", "20240201", "2023-12-24", "2024-13-01"],
+                VersionScheme::Date
+            ),
+            vec!["20240201", "2024-01-15", "2023-12-24"]
+        );
+        assert_eq!(
+            releases(
+                &["1.0-2", "1.0-10", "1.1~rc1", "1:0.9"],
+                VersionScheme::Debian
+            ),
+            vec!["1:0.9", "1.0-10", "1.0-2"]
+        );
+        assert_eq!(
+            releases(&["a10", "b", "a9"], VersionScheme::Lexical),
+            vec!["b", "a9", "a10"]
+        );
+    }
+
+    #[test]
+    fn test_ref_queries() {
+        let mut queries = RefQueries::default();
+        /* The first lookup is narrow, and reused by those it covers */
+        assert_eq!(queries.query("refs/tags/"), "refs/tags/");
+        assert_eq!(queries.query("refs/tags/v1.0"), "refs/tags/");
+        /* Any other one lists everything, once */
+        assert_eq!(queries.query("refs/heads/main"), "");
+        assert_eq!(queries.query("HEAD"), "");
+        assert_eq!(queries.query("refs/tags/v2.0"), "refs/tags/");
+        assert_eq!(queries.prefixes.len(), 2);
+    }
+
+    #[test]
+    fn test_refs_from_ls_remote() {
+        let refs = refs_from_ls_remote(vec![
+            RemoteInfo::new("ref: refs/heads/main", "HEAD"),
+            RemoteInfo::new("1111", "HEAD"),
+            RemoteInfo::new("1111", "refs/heads/main"),
+            RemoteInfo::new("2222", "refs/tags/v1.0"),
+            RemoteInfo::new("3333", "refs/tags/v1.0^{}"),
+        ]);
+        let ref_ = |name: &str, oid: &str, symref_target: Option<&str>, peeled: Option<&str>| {
+            git_http::Ref {
+                name: name.into(),
+                oid: oid.into(),
+                symref_target: symref_target.map(Into::into),
+                peeled: peeled.map(Into::into),
+            }
+        };
+        assert_eq!(
+            refs,
+            vec![
+                ref_("HEAD", "1111", Some("refs/heads/main"), None),
+                ref_("refs/heads/main", "1111", None, None),
+                ref_("refs/tags/v1.0", "2222", None, Some("3333")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tag_pattern() {
+        let filter = |pattern| TagFilter::new(None, Some(pattern)).unwrap();
+        assert_eq!(
+            releases(
+                [
+                    "curl-7_88_1",
+                    "curl-8_5_0",
+                    "curl-8_10_0",
+                    "tiny-curl-8_4_0",
+                    "8.11.0"
+                ]
+                .iter()
+                .copied(),
+                false,
+                None,
+                &filter(r"curl-(\d+)_(\d+)_(\d+)"),
+                VersionScheme::Semver
+            ),
+            vec![
+                LatestRelease {
+                    tag: "curl-8_10_0".into(),
+                    name: "8.10.0".into()
+                },
+                LatestRelease {
+                    tag: "curl-8_5_0".into(),
+                    name: "8.5.0".into()
+                },
+                LatestRelease {
+                    tag: "curl-7_88_1".into(),
+                    name: "7.88.1".into()
+                },
+            ]
+        );
+        assert_eq!(
+            latest_release(
+                ["foo@1.2.3", "bar@2.0.0", "foo@1.10.0", "foo-2.0.0"]
+                    .iter()
+                    .copied(),
+                false,
+                None,
+                &filter("foo@(.+)"),
+                VersionScheme::Semver
+            ),
+            Some(LatestRelease {
+                tag: "foo@1.10.0".into(),
+                name: "1.10.0".into()
+            })
+        );
+        assert_eq!(
+            latest_release(
+                [
+                    "v2024.01.15-stable",
+                    "v2024.02.01-beta",
+                    "v2023.12.24-stable"
+                ]
+                .iter()
+                .copied(),
+                false,
+                None,
+                &filter(r"v([\d.]+)-stable"),
+                VersionScheme::Semver
+            ),
+            Some(LatestRelease {
+                tag: "v2024.01.15-stable".into(),
+                name: "2024.01.15".into()
+            })
+        );
+
+        assert!(TagFilter::new(None, Some("foo-.*")).is_err());
+        assert!(TagFilter::new(None, Some("foo-(")).is_err());
+        assert!(TagFilter::new(Some("release/"), Some("release/(.*)")).is_err());
+    }
+
     #[tokio::test]
+    #[ignore = "requires network access"]
     async fn test_fetch_branch() -> Result<()> {
         let branch = fetch_branch_head(
+            &config(),
             &"https://github.com/oliverwatkins/swing_library.git"
                 .parse()
                 .unwrap(),
@@ -928,8 +1844,10 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires network access"]
     async fn test_fetch_tags() -> Result<()> {
         let tags = fetch_tags(
+            &config(),
             &"https://gitlab.com/maxigaz/gitlab-dark.git"
                 .parse()
                 .unwrap(),
@@ -982,10 +1900,12 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires network access"]
     async fn test_fetch_ref() {
         /* Regression test for https://github.com/andir/npins/issues/142 */
         assert_eq!(
             fetch_ref(
+                &config(),
                 &"https://seed.radicle.garden/z3gqcJUoA1n9HaHKufZs5FCSGazv5.git"
                     .parse()
                     .unwrap(),
@@ -999,6 +1919,7 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires network access"]
     async fn test_git_update() -> Result<()> {
         let pin = GitPin {
             repository: Repository::Git {
@@ -1008,16 +1929,18 @@ mod test {
             },
             branch: "master".into(),
             submodules: false,
+            min_age: None,
         };
-        let version = pin.update(None).await?;
+        let version = pin.update(&config(), None).await?;
         assert_eq!(
             version,
             GitRevision {
                 revision: "1edb0a9cebe046cc915a218c57dbf7f40739aeee".into(),
+                date: None,
             }
         );
         assert_eq!(
-            pin.fetch(&version).await?,
+            pin.fetch(&config(), &version).await?,
             OptionalUrlHashes {
                 url: None,
                 hash: NixHash::from_sri("sha256-zUM/evAqAwwjGXg67IVzqZvvwp2NjFG1HAUSdLv98Z0=")
@@ -1028,25 +1951,30 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires network access"]
     async fn test_git_release_update() -> Result<()> {
         let pin = GitReleasePin {
             repository: Repository::Git {
                 url: "https://github.com/jstutters/MidiOSC.git".parse().unwrap(),
             },
             pre_releases: false,
-            version_upper_bound: None,
+            constraint: None,
             release_prefix: None,
+            tag_pattern: None,
+            version_scheme: VersionScheme::default(),
             submodules: false,
+            min_age: None,
         };
-        let version = pin.update(None).await?;
+        let version = pin.update(&config(), None).await?;
         assert_eq!(
             version,
             GenericVersion {
                 version: "v1.1".into(),
+                date: None,
             }
         );
         assert_eq!(
-            pin.fetch(&version).await?,
+            pin.fetch(&config(), &version).await?,
             ReleasePinHashes {
                 url: None,
                 hash: NixHash::from_sri("sha256-BjxJ5aG8NyfDLcBNZrDVV2CAK4tdHNCBdiuJYKB8BmA=")
@@ -1058,6 +1986,7 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires network access"]
     async fn test_github_update() -> Result<()> {
         let pin = GitPin {
             repository: Repository::GitHub {
@@ -1066,16 +1995,18 @@ mod test {
             },
             branch: "master".into(),
             submodules: false,
+            min_age: None,
         };
-        let version = pin.update(None).await?;
+        let version = pin.update(&config(), None).await?;
         assert_eq!(
             version,
             GitRevision {
                 revision: "1edb0a9cebe046cc915a218c57dbf7f40739aeee".into(),
+                date: None,
             }
         );
         assert_eq!(
-            pin.fetch(&version).await?,
+            pin.fetch(&config(), &version).await?,
             OptionalUrlHashes {
                 url: Some("https://github.com/oliverwatkins/swing_library/archive/1edb0a9cebe046cc915a218c57dbf7f40739aeee.tar.gz".parse().unwrap()),
                 hash: NixHash::from_sri("sha256-zUM/evAqAwwjGXg67IVzqZvvwp2NjFG1HAUSdLv98Z0=").unwrap(),
@@ -1085,6 +2016,7 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires network access"]
     async fn test_github_release_update() -> Result<()> {
         let pin = GitReleasePin {
             repository: Repository::GitHub {
@@ -1092,19 +2024,23 @@ mod test {
                 repo: "MidiOSC".into(),
             },
             pre_releases: false,
-            version_upper_bound: None,
+            constraint: None,
             release_prefix: None,
+            tag_pattern: None,
+            version_scheme: VersionScheme::default(),
             submodules: false,
+            min_age: None,
         };
-        let version = pin.update(None).await?;
+        let version = pin.update(&config(), None).await?;
         assert_eq!(
             version,
             GenericVersion {
                 version: "v1.1".into(),
+                date: None,
             }
         );
         assert_eq!(
-            pin.fetch(&version).await?,
+            pin.fetch(&config(), &version).await?,
             ReleasePinHashes {
                 revision: "35be5b2b2c3431de1100996487d53134f658b866".into(),
                 url: Some(
@@ -1127,19 +2063,24 @@ mod test {
     //   "status": "300"
     // }
     #[tokio::test]
+    #[ignore = "requires network access"]
     async fn test_github_release_ambiguous() -> Result<()> {
         let pin = GitReleasePin {
             repository: Repository::github("alexfedosov", "AFHorizontalDayPicker"),
             pre_releases: false,
-            version_upper_bound: None,
+            constraint: None,
             release_prefix: None,
+            tag_pattern: None,
+            version_scheme: VersionScheme::default(),
             submodules: false,
+            min_age: None,
         };
         let version = GenericVersion {
             version: "0.2.1".into(),
+            date: None,
         };
         assert_eq!(
-            pin.fetch(&version).await?,
+            pin.fetch(&config(), &version).await?,
             ReleasePinHashes {
                 revision: "ca59ad1dc1b55108f1d17f20bdf443aad3e2f0f5".into(),
                 url: Some(
@@ -1154,6 +2095,7 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires network access"]
     async fn test_forgejo_update() -> Result<()> {
         let pin = GitPin {
             repository: Repository::Forgejo {
@@ -1163,16 +2105,18 @@ mod test {
             },
             branch: "release-2.90".into(),
             submodules: false,
+            min_age: None,
         };
-        let version = pin.update(None).await?;
+        let version = pin.update(&config(), None).await?;
         assert_eq!(
             version,
             GitRevision {
                 revision: "4bbdb2f5564b9b42bcaf0e1eec28325300f31c72".into(),
+                date: None,
             }
         );
         assert_eq!(
-            pin.fetch(&version).await?,
+            pin.fetch(&config(), &version).await?,
             OptionalUrlHashes {
                 url: Some("https://git.lix.systems/lix-project/lix/archive/4bbdb2f5564b9b42bcaf0e1eec28325300f31c72.tar.gz".parse().unwrap()),
                 hash: NixHash::from_sri("sha256-w8JAk9Z3Fmkyway0VCjy/PtoBC6bGQVhNfTzFA98Pg8=").unwrap(),
@@ -1182,6 +2126,7 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires network access"]
     async fn test_forgejo_release_update() -> Result<()> {
         let pin = GitReleasePin {
             repository: Repository::Forgejo {
@@ -1190,19 +2135,23 @@ mod test {
                 repo: "lix".into(),
             },
             pre_releases: false,
-            version_upper_bound: Some("2.90.1".to_string()),
+            constraint: Some("<2.90.1".to_string()),
             release_prefix: None,
+            tag_pattern: None,
+            version_scheme: VersionScheme::default(),
             submodules: false,
+            min_age: None,
         };
-        let version = pin.update(None).await?;
+        let version = pin.update(&config(), None).await?;
         assert_eq!(
             version,
             GenericVersion {
                 version: "2.90.0".into(),
+                date: None,
             }
         );
         assert_eq!(
-            pin.fetch(&version).await?,
+            pin.fetch(&config(), &version).await?,
             ReleasePinHashes {
                 revision: "2a4376be20d70feaa2b0e640c5041fb66ddc67ed".into(),
                 url: Some(
@@ -1218,25 +2167,28 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires network access"]
     async fn test_gitlab_update() -> Result<()> {
         let pin = GitPin {
             repository: Repository::GitLab {
                 repo_path: "maxigaz/gitlab-dark".into(),
                 server: "https://gitlab.com/".parse().unwrap(),
-                private_token: None,
+                credential: None,
             },
             branch: "master".into(),
             submodules: false,
+            min_age: None,
         };
-        let version = pin.update(None).await?;
+        let version = pin.update(&config(), None).await?;
         assert_eq!(
             version,
             GitRevision {
                 revision: "e7145078163692697b843915a665d4f41139a65c".into(),
+                date: None,
             }
         );
         assert_eq!(
-            pin.fetch(&version).await?,
+            pin.fetch(&config(), &version).await?,
             OptionalUrlHashes {
                 url: Some("https://gitlab.com/api/v4/projects/maxigaz%2Fgitlab-dark/repository/archive.tar.gz?sha=e7145078163692697b843915a665d4f41139a65c".parse().unwrap()),
                 hash: NixHash::from_sri("sha256-WzPqIwEe6HzISyeg1XBSHNO2fd9+Pc1T90RXBh7IrFo=").unwrap(),
@@ -1246,27 +2198,32 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires network access"]
     async fn test_gitlab_release_update() -> Result<()> {
         let pin = GitReleasePin {
             repository: Repository::GitLab {
                 repo_path: "maxigaz/gitlab-dark".into(),
                 server: "https://gitlab.com/".parse().unwrap(),
-                private_token: None,
+                credential: None,
             },
             pre_releases: false,
-            version_upper_bound: None,
+            constraint: None,
             release_prefix: None,
+            tag_pattern: None,
+            version_scheme: VersionScheme::default(),
             submodules: false,
+            min_age: None,
         };
-        let version = pin.update(None).await?;
+        let version = pin.update(&config(), None).await?;
         assert_eq!(
             version,
             GenericVersion {
                 version: "v1.16.0".into(),
+                date: None,
             }
         );
         assert_eq!(
-            pin.fetch(&version).await?,
+            pin.fetch(&config(), &version).await?,
             ReleasePinHashes {
                 revision: "d42ec2b04df9da97e465883fcd1f9a5d6e794027".into(),
                 url: Some("https://gitlab.com/api/v4/projects/maxigaz%2Fgitlab-dark/repository/archive.tar.gz?sha=v1.16.0"
@@ -1282,24 +2239,29 @@ mod test {
     // Regression test for https://github.com/andir/npins/issues/146
     // We pin some old GNOME version, to make sure the pin won't fail
     #[tokio::test]
+    #[ignore = "requires network access"]
     async fn test_gitlab_release_noupdate() -> Result<()> {
         let pin = GitReleasePin {
             repository: Repository::GitLab {
                 repo_path: "GNOME/gnome-shell".into(),
                 server: "https://gitlab.gnome.org/".parse().unwrap(),
-                private_token: None,
+                credential: None,
             },
             pre_releases: false,
-            version_upper_bound: None,
+            constraint: None,
             release_prefix: None,
+            tag_pattern: None,
+            version_scheme: VersionScheme::default(),
             submodules: false,
+            min_age: None,
         };
         let version = GenericVersion {
             version: "40.0".into(),
+            date: None,
         };
 
         assert_eq!(
-            pin.fetch(&version).await?,
+            pin.fetch(&config(), &version).await?,
             ReleasePinHashes {
                 revision: "435d48ad7eaf9d91cc6719fda852cd9fd54afa2e".into(),
                 url: Some("https://gitlab.gnome.org/api/v4/projects/GNOME%2Fgnome-shell/repository/archive.tar.gz?sha=40.0"
@@ -1313,25 +2275,28 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires network access"]
     async fn test_gitlab_selfhosted_update() -> Result<()> {
         let pin = GitPin {
             repository: Repository::GitLab {
                 repo_path: "Archive/gnome-games".into(),
                 server: "https://gitlab.gnome.org/".parse().unwrap(),
-                private_token: None,
+                credential: None,
             },
             branch: "master".into(),
             submodules: false,
+            min_age: None,
         };
-        let version = pin.update(None).await?;
+        let version = pin.update(&config(), None).await?;
         assert_eq!(
             version,
             GitRevision {
                 revision: "bca2071b6923d45d9aabac27b3ea1e40f5fa3006".into(),
+                date: None,
             }
         );
         assert_eq!(
-            pin.fetch(&version).await?,
+            pin.fetch(&config(), &version).await?,
             OptionalUrlHashes {
                 url: Some("https://gitlab.gnome.org/api/v4/projects/Archive%2Fgnome-games/repository/archive.tar.gz?sha=bca2071b6923d45d9aabac27b3ea1e40f5fa3006".parse().unwrap()),
                 hash: NixHash::from_sri("sha256-r84Y5/hI0rM/UWK569+nWo+BHuovmlQh3Zs6U2Srx14=").unwrap(),
@@ -1341,27 +2306,32 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires network access"]
     async fn test_gitlab_selfhosted_release_update() -> Result<()> {
         let pin = GitReleasePin {
             repository: Repository::GitLab {
                 repo_path: "Archive/gnome-games".into(),
                 server: "https://gitlab.gnome.org/".parse().unwrap(),
-                private_token: None,
+                credential: None,
             },
             pre_releases: false,
-            version_upper_bound: None,
+            constraint: None,
             release_prefix: None,
+            tag_pattern: None,
+            version_scheme: VersionScheme::default(),
             submodules: false,
+            min_age: None,
         };
-        let version = pin.update(None).await?;
+        let version = pin.update(&config(), None).await?;
         assert_eq!(
             version,
             GenericVersion {
                 version: "40.0".into(),
+                date: None,
             }
         );
         assert_eq!(
-            pin.fetch(&version).await?,
+            pin.fetch(&config(), &version).await?,
             ReleasePinHashes {
                 revision: "2c89145d52d072a4ca5da900c2676d890bfab1ff".into(),
                 url: Some("https://gitlab.gnome.org/api/v4/projects/Archive%2Fgnome-games/repository/archive.tar.gz?sha=40.0".parse().unwrap()),
@@ -1372,23 +2342,36 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires network access"]
     async fn test_repository_auto() {
         assert_eq!(
-            Repository::git_auto("https://github.com/NixOS/Nixpkgs".parse().unwrap()).await,
+            Repository::git_auto(
+                &config(),
+                "https://github.com/NixOS/Nixpkgs".parse().unwrap()
+            )
+            .await,
             Repository::GitHub {
                 owner: "NixOS".into(),
                 repo: "Nixpkgs".into()
             },
         );
         assert_eq!(
-            Repository::git_auto("https://github.com/NixOS/Nixpkgs/".parse().unwrap()).await,
+            Repository::git_auto(
+                &config(),
+                "https://github.com/NixOS/Nixpkgs/".parse().unwrap()
+            )
+            .await,
             Repository::GitHub {
                 owner: "NixOS".into(),
                 repo: "Nixpkgs".into()
             },
         );
         assert_eq!(
-            Repository::git_auto("https://github.com/NixOS/Nixpkgs.git".parse().unwrap()).await,
+            Repository::git_auto(
+                &config(),
+                "https://github.com/NixOS/Nixpkgs.git".parse().unwrap()
+            )
+            .await,
             Repository::GitHub {
                 owner: "NixOS".into(),
                 repo: "Nixpkgs".into()
@@ -1396,6 +2379,7 @@ mod test {
         );
         assert_eq!(
             Repository::git_auto(
+                &config(),
                 "https://gitlab.com/Repos/Dont/Have/To/Be/Real"
                     .parse()
                     .unwrap()
@@ -1404,11 +2388,12 @@ mod test {
             Repository::GitLab {
                 server: "https://gitlab.com".parse().unwrap(),
                 repo_path: "/Repos/Dont/Have/To/Be/Real".into(),
-                private_token: None
+                credential: None
             },
         );
         assert_eq!(
             Repository::git_auto(
+                &config(),
                 "https://gitlab.gnome.org/GNOME/gnome-control-center/"
                     .parse()
                     .unwrap()
@@ -1417,11 +2402,12 @@ mod test {
             Repository::GitLab {
                 server: "https://gitlab.gnome.org".parse().unwrap(),
                 repo_path: "/GNOME/gnome-control-center".to_string(),
-                private_token: None
+                credential: None
             },
         );
         assert_eq!(
             Repository::git_auto(
+                &config(),
                 "https://gitlab.gnome.org/GNOME/gnome-control-center.git"
                     .parse()
                     .unwrap()
@@ -1430,17 +2416,25 @@ mod test {
             Repository::GitLab {
                 server: "https://gitlab.gnome.org".parse().unwrap(),
                 repo_path: "/GNOME/gnome-control-center".to_string(),
-                private_token: None
+                credential: None
             },
         );
         assert_eq!(
-            Repository::git_auto("https://github.com/MyOrganization".parse().unwrap()).await,
+            Repository::git_auto(
+                &config(),
+                "https://github.com/MyOrganization".parse().unwrap()
+            )
+            .await,
             Repository::Git {
                 url: "https://github.com/MyOrganization".parse().unwrap()
             },
         );
         assert_eq!(
-            Repository::git_auto("https://git.lix.systems/lix-project/lix".parse().unwrap()).await,
+            Repository::git_auto(
+                &config(),
+                "https://git.lix.systems/lix-project/lix".parse().unwrap()
+            )
+            .await,
             Repository::Forgejo {
                 server: "https://git.lix.systems".parse().unwrap(),
                 owner: "lix-project".to_string(),
@@ -1448,7 +2442,11 @@ mod test {
             },
         );
         assert_eq!(
-            Repository::git_auto("https://git.lix.systems/lix-project/lix/".parse().unwrap()).await,
+            Repository::git_auto(
+                &config(),
+                "https://git.lix.systems/lix-project/lix/".parse().unwrap()
+            )
+            .await,
             Repository::Forgejo {
                 server: "https://git.lix.systems".parse().unwrap(),
                 owner: "lix-project".to_string(),
@@ -1457,6 +2455,7 @@ mod test {
         );
         assert_eq!(
             Repository::git_auto(
+                &config(),
                 "https://git.lix.systems/lix-project/lix.git"
                     .parse()
                     .unwrap()
@@ -1,14 +1,17 @@
 //! The npins `Pin` base type
 //! [`Pin`] is the main type for all pins, and then the variants are implemented in submodules.
 
-use crate::{Diff, Frozen, OptionExt, Updatable, diff};
+use crate::{Config, Diff, Frozen, OptionExt, Updatable, diff};
 use anyhow::Context;
 use serde::{Deserialize, Serialize};
 
 pub mod channel;
 pub mod container;
+pub mod crates;
 pub mod git;
+pub mod npm;
 pub mod pypi;
+pub mod release_asset;
 pub mod urlpin;
 
 /// Create the `Pin` type
@@ -54,11 +57,11 @@ macro_rules! mk_pin {
             })*
 
             /* If an error is returned, `self` remains unchanged */
-            pub async fn update(&mut self) -> ::anyhow::Result<Vec<diff::DiffEntry>> {
+            pub async fn update(&mut self, config: &Config) -> ::anyhow::Result<Vec<diff::DiffEntry>> {
                 Ok(match self {
                     $(Self::$name { input, version, .. } => {
                         /* Use very explicit syntax to force the correct types and get good compile errors */
-                        let new_version = <$input_name as Updatable>::update(input, version.as_ref()).await?;
+                        let new_version = <$input_name as Updatable>::update(input, config, version.as_ref()).await?;
                         version.insert_diffed(new_version)
                     }),*
                 })
@@ -67,18 +70,25 @@ macro_rules! mk_pin {
             /* If an error is returned, `self` remains unchanged. This returns a double result: the outer one
              * indicates that `update` should be called first, the inner is from the actual operation.
              */
-            pub async fn fetch(&mut self) -> ::anyhow::Result<Vec<diff::DiffEntry>> {
+            pub async fn fetch(&mut self, config: &Config) -> ::anyhow::Result<Vec<diff::DiffEntry>> {
                 Ok(match self {
                     $(Self::$name { input, version, hashes, .. } => {
                         let version = version.as_ref()
                             .context("No version information available, call `update` first or manually set one")?;
                         /* Use very explicit syntax to force the correct types and get good compile errors */
-                        let new_hashes = <$input_name as Updatable>::fetch(input, &version).await?;
+                        let new_hashes = <$input_name as Updatable>::fetch(input, config, &version).await?;
                         hashes.insert_diffed(new_hashes)
                     }),*
                 })
             }
 
+            /// The host most of the network traffic goes to, see [`Updatable::host`]
+            pub fn host(&self, config: &Config) -> Option<String> {
+                match self {
+                    $(Self::$name { input, .. } => <$input_name as Updatable>::host(input, config) ),*
+                }
+            }
+
             pub fn has_version(&self) -> bool {
                 match self {
                     $(Self::$name { version, ..} => version.is_some() ),*
@@ -91,6 +101,20 @@ macro_rules! mk_pin {
                 }
             }
 
+            /// Whether this pin has been resolved from `spec`
+            ///
+            /// This is the case if both have the same type and input, and if `spec` specifies
+            /// a version, the versions are equal too. Hashes and the frozen state are ignored.
+            pub fn matches_spec(&self, spec: &Pin) -> bool {
+                match (self, spec) {
+                    $((
+                        Self::$name { input, version, .. },
+                        Self::$name { input: spec_input, version: spec_version, .. },
+                    ) => input == spec_input && (spec_version.is_none() || version == spec_version),)*
+                    _ => false,
+                }
+            }
+
             /// Human readable name of the pin type
             pub fn pin_type(&self) -> &'static str {
                 match self {
@@ -120,24 +144,30 @@ macro_rules! mk_pin {
             }
         }
 
-        impl std::fmt::Display for Pin {
-            fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        impl Diff for Pin {
+            fn properties(&self) -> Vec<(String, String)> {
                 match self {
                     $(Self::$name { input, version, hashes, frozen } => {
-                        /* Concat all properties and then print them */
-                        let properties = input.properties().into_iter()
+                        /* Concat all properties */
+                        input.properties().into_iter()
                             .chain(version.iter().flat_map(Diff::properties))
                             .chain(hashes.iter().flat_map(Diff::properties))
-			    .chain(frozen.properties());
-                        for (key, value) in properties {
-                            writeln!(fmt, "    {}: {}", key, value)?;
-                        }
-                        Ok(())
+                            .chain(frozen.properties())
+                            .collect()
                     }),*
                 }
             }
         }
 
+        impl std::fmt::Display for Pin {
+            fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+                for (key, value) in self.properties() {
+                    writeln!(fmt, "    {}: {}", key, value)?;
+                }
+                Ok(())
+            }
+        }
+
         // Each variant holds exactly one distinct type, so we can easily create convenient type wrappers that simply call the constructor
         $(
             impl From<$input_name> for Pin {
@@ -164,9 +194,28 @@ macro_rules! mk_pin {
 mk_pin! {
     (Git, git, "git repository", git::GitPin),
     (GitRelease, git_release, "git release tag", git::GitReleasePin),
+    (ReleaseAsset, release_asset, "release asset", release_asset::ReleaseAssetPin),
     (PyPi, pypi, "pypi package", pypi::Pin),
+    (Crate, rust_crate, "crate", crates::Pin),
+    (Npm, npm, "npm package", npm::Pin),
     (Channel, channel, "Nix channel", channel::Pin),
     (Url, url, "url", urlpin::UrlPin),
     (MutableUrl, mutable_url, "mutable url", urlpin::MutableUrlPin),
+    (PerSystemUrl, per_system_url, "per-system url", urlpin::PerSystemUrlPin),
     (Container, container, "OCI Container", container::Pin),
 }
+
+impl Pin {
+    /// When the pinned version was released or committed, if it has been recorded
+    pub fn date(&self) -> Option<crate::timestamp::Timestamp> {
+        match self {
+            Pin::Git { version, .. } => version.as_ref()?.date(),
+            Pin::GitRelease { version, .. }
+            | Pin::ReleaseAsset { version, .. }
+            | Pin::PyPi { version, .. }
+            | Pin::Crate { version, .. }
+            | Pin::Npm { version, .. } => version.as_ref()?.date,
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! Pin an npm package
+
+use anyhow::{Context, Result};
+use nix_compat::nixhash::{self, NixHash};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::constraint::Constraint;
+use crate::git::{self, TagFilter, latest_release, latest_release_until};
+use crate::timestamp::Timestamp;
+use crate::version_scheme::VersionScheme;
+use crate::{Config, GenericUrlHashes, GenericVersion, Updatable, diff, get_and_deserialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct Pin {
+    /// The package name, including the scope for scoped packages, e.g. `@types/node`
+    pub name: String,
+    /// Follow this dist-tag instead of `latest`, for example `next`
+    pub dist_tag: Option<String>,
+    /// Optionally restrict the releases to pin, for example `^1.4`. See [`crate::constraint`]
+    ///
+    /// If present, the latest matching release will be pinned and the dist-tags are not used.
+    pub constraint: Option<String>,
+    /// Minimum age in days of the releases to pin, overriding the global setting. `0` disables it
+    ///
+    /// If the version of the dist-tag is too new, the latest older version which is old enough is
+    /// pinned instead, according to the publication times of the registry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_age: Option<u32>,
+}
+
+impl diff::Diff for Pin {
+    fn properties(&self) -> Vec<(String, String)> {
+        [
+            Some(("name".into(), self.name.clone())),
+            self.dist_tag
+                .as_ref()
+                .map(|dist_tag| ("dist_tag".into(), dist_tag.clone())),
+            self.constraint
+                .as_ref()
+                .map(|constraint| ("constraint".into(), constraint.clone())),
+            self.min_age
+                .map(|min_age| ("min_age".into(), min_age.to_string())),
+        ]
+        .into_iter()
+        .flat_map(Option::into_iter)
+        .collect()
+    }
+}
+
+impl Pin {
+    /// Fetch the packument, i.e. the metadata of all versions of the package
+    ///
+    /// Url template: `https://registry.npmjs.org/$name`, with the `/` of scoped packages escaped.
+    async fn packument(&self, config: &Config) -> Result<Packument> {
+        let url = config
+            .hosts()
+            .npm
+            .join(&self.name.replacen('/', "%2F", 1))?;
+        get_and_deserialize(config, url)
+            .await
+            .with_context(|| format!("Could not fetch the npm metadata of {}", self.name))
+    }
+}
+
+#[async_trait::async_trait]
+impl Updatable for Pin {
+    type Version = GenericVersion;
+    type Hashes = GenericUrlHashes;
+
+    async fn update(
+        &self,
+        config: &Config,
+        old: Option<&GenericVersion>,
+    ) -> Result<GenericVersion> {
+        let constraint = self
+            .constraint
+            .as_deref()
+            .map(|constraint| Constraint::parse(constraint, VersionScheme::Semver))
+            .transpose()
+            .context("Field `constraint` is invalid")?;
+
+        let mut packument = self.packument(config).await?;
+        let version = match &constraint {
+            Some(constraint) => {
+                latest_release(
+                    packument.versions.keys().map(String::as_str),
+                    false,
+                    Some(constraint),
+                    &TagFilter::All,
+                    VersionScheme::Semver,
+                )
+                .context("No matching versions found")?
+                .tag
+            },
+            None => {
+                let dist_tag = self.dist_tag.as_deref().unwrap_or("latest");
+                packument
+                    .dist_tags
+                    .remove(dist_tag)
+                    .with_context(|| format!("Package has no dist-tag `{dist_tag}`"))?
+            },
+        };
+
+        let (version, date) = match config.min_age(self.min_age) {
+            None => (version, None),
+            Some(min_age) => {
+                if packument.time.is_empty() {
+                    anyhow::bail!(
+                        "The registry does not report when versions were published, which is needed to know their age"
+                    );
+                }
+                /* Fall back to older versions of the same kind, i.e. only to pre-releases if a dist-tag points to one */
+                let upper_bound = format!("<={version}");
+                let constraint = match constraint {
+                    Some(constraint) => constraint,
+                    None => Constraint::parse(&upper_bound, VersionScheme::Semver)?,
+                };
+                let pre_releases = VersionScheme::Semver
+                    .parse(&version)
+                    .is_some_and(|version| version.is_pre_release());
+                let releases = git::releases(
+                    packument.versions.keys().map(String::as_str),
+                    pre_releases,
+                    Some(&constraint),
+                    &TagFilter::All,
+                    VersionScheme::Semver,
+                );
+                match latest_release_until(
+                    releases,
+                    |tag| std::future::ready(Ok(packument.time.get(&tag).copied())),
+                    old,
+                    VersionScheme::Semver,
+                    min_age,
+                )
+                .await?
+                {
+                    Some((latest, date)) => (latest.tag, Some(date)),
+                    None => return Ok(old.expect("Only the old version can be newer").clone()),
+                }
+            },
+        };
+
+        if let Some(old) = old {
+            old.ensure_monotonic(&version, VersionScheme::Semver)?;
+        }
+
+        Ok(GenericVersion { version, date })
+    }
+
+    async fn fetch(&self, config: &Config, version: &GenericVersion) -> Result<GenericUrlHashes> {
+        let dist = self
+            .packument(config)
+            .await?
+            .versions
+            .remove(&version.version)
+            .with_context(|| format!("Could not find requested version {}", version.version))?
+            .dist;
+
+        /* `integrity` may contain multiple hashes separated by whitespace, they are equally valid.
+         * Very old packages only have the SHA-1 `shasum`.
+         */
+        let hash = match (dist.integrity, dist.shasum) {
+            (Some(integrity), _) => integrity
+                .split_whitespace()
+                .find_map(|hash| NixHash::from_sri(hash).ok())
+                .with_context(|| format!("Unsupported integrity value {integrity}"))?,
+            (None, Some(shasum)) => NixHash::from_str(&shasum, Some(nixhash::HashAlgo::Sha1))
+                .context("failed to convert to NixHash")?,
+            (None, None) => anyhow::bail!("Version {} has no hash", version.version),
+        };
+
+        Ok(GenericUrlHashes {
+            url: dist.tarball.parse().context("Invalid tarball URL")?,
+            hash,
+        })
+    }
+
+    fn host(&self, config: &Config) -> Option<String> {
+        config.hosts().npm.host_str().map(Into::into)
+    }
+}
+
+/// The abbreviated metadata would suffice, but not all registries support it.
+/// JSON API specification: <https://github.com/npm/registry/blob/main/docs/REGISTRY-API.md#package>
+#[derive(Debug, Deserialize)]
+struct Packument {
+    #[serde(rename = "dist-tags")]
+    dist_tags: HashMap<String, String>,
+    versions: HashMap<String, PackumentVersion>,
+    /// When each version was published, besides the `created` and `modified` times of the package
+    #[serde(default)]
+    time: HashMap<String, Timestamp>,
+}
+
+// Again, this is not complete
+#[derive(Debug, Deserialize)]
+struct PackumentVersion {
+    dist: PackumentDist,
+}
+
+#[derive(Debug, Deserialize)]
+struct PackumentDist {
+    tarball: String,
+    /// SHA-1 of the tarball, in hex
+    shasum: Option<String>,
+    /// Subresource Integrity string
+    integrity: Option<String>,
+}
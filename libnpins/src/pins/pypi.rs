@@ -1,40 +1,77 @@
 //! Pin a PyPi package
+//!
+//! Packages are looked up through the [JSON API] of PyPi, or the [JSON simple API] of another
+//! index such as devpi or Artifactory. Either the source distribution or a wheel is pinned.
+//!
+//! [JSON API]: https://warehouse.pypa.io/api-reference/json.html
+//! [JSON simple API]: https://peps.python.org/pep-0691/
 
 use anyhow::{Context, Result};
-use lenient_version::Version;
 use nix_compat::nixhash::{self, NixHash};
+use reqwest::header;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use url::Url;
 
-use crate::{GenericUrlHashes, GenericVersion, Updatable, diff, get_and_deserialize};
+use crate::constraint::Constraint;
+use crate::git::{LatestRelease, latest_release_until};
+use crate::release_asset::glob_match;
+use crate::retry::{self, Operation};
+use crate::timestamp::Timestamp;
+use crate::version_scheme::{ParsedVersion, VersionScheme};
+use crate::{Config, GenericUrlHashes, GenericVersion, Updatable, diff, get_and_deserialize};
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub struct Pin {
     pub name: String,
-    /// Optionally restrict to only pin older releases
+    /// The base URL of a package index with the JSON simple API (PEP 691), for example
+    /// `https://pypi.org/simple/`
     ///
-    /// Sometimes, we want to track an older major version separately. For example, set it to
-    /// 2.0 to track 1.* releases. (Note that the bound is exclusive. In mathematical terms, it
-    /// is the infimum and not a maximum, because the set of compatible releases is not closed.)
+    /// If not set, the JSON API of PyPi is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<Url>,
+    /// Pin a wheel instead of the source distribution
     ///
-    /// If present, only versions < version_upper_bound will be pinned. This is a restricted
-    /// syntax compared to the "version requirement grammar" with multiple different operators
-    /// that are common in dependency resolution tools. The reason is, that we aren't interested
-    /// in providing lower bounds for releases, so all we need is a "less than" operator.
+    /// This is either a compatibility tag like `py3-none-any` or `cp312-cp312-manylinux*_x86_64`,
+    /// or a file name ending in `.whl`. Both may contain `*` to match any sequence of characters,
+    /// and `{version}` in a file name is replaced with the version. It must match exactly one
+    /// file of the release.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wheel: Option<String>,
+    /// Also track pre-releases, i.e. alpha, beta, release candidate and development releases
+    #[serde(default)]
+    pub pre_releases: bool,
+    /// Optionally restrict the releases to pin
     ///
-    /// Versions will be parsed the in the same rather lenient way as the tags themselves.
-    pub version_upper_bound: Option<String>,
+    /// Sometimes, we want to track an older major version separately, or skip a broken release.
+    /// For example, `>=1.4, <2, !=1.4.3` or `~=1.4`. See [`crate::constraint`] for the syntax.
+    ///
+    /// Versions are parsed and ordered according to PEP 440, like the releases themselves.
+    pub constraint: Option<String>,
+    /// Minimum age in days of the releases to pin, overriding the global setting. `0` disables it
+    ///
+    /// Newer releases are skipped in favor of the latest one which is old enough, according to
+    /// the upload time of their first file. Indexes must thus report upload times (PEP 700).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_age: Option<u32>,
 }
 
 impl diff::Diff for Pin {
     fn properties(&self) -> Vec<(String, String)> {
         [
             Some(("name".into(), self.name.clone())),
-            self.version_upper_bound
+            self.index
+                .as_ref()
+                .map(|index| ("index".into(), index.to_string())),
+            self.wheel
                 .as_ref()
-                .map(|version_upper_bound| {
-                    ("version_upper_bound".into(), version_upper_bound.clone())
-                }),
+                .map(|wheel| ("wheel".into(), wheel.clone())),
+            Some(("pre_releases".into(), self.pre_releases.to_string())),
+            self.constraint
+                .as_ref()
+                .map(|constraint| ("constraint".into(), constraint.clone())),
+            self.min_age
+                .map(|min_age| ("min_age".into(), min_age.to_string())),
         ]
         .into_iter()
         .flat_map(Option::into_iter)
@@ -42,90 +79,214 @@ impl diff::Diff for Pin {
     }
 }
 
+impl Pin {
+    /// All releases which may be pinned, the latest first
+    ///
+    /// Yanked releases and those without a matching file are skipped, as well as pre-releases
+    /// unless they are tracked.
+    fn releases<'a>(
+        &self,
+        metadata: &'a PyPiMetadata,
+        constraint: Option<&Constraint>,
+        selector: &FileSelector,
+    ) -> Vec<(&'a String, ParsedVersion<'a>, &'a [PyPiUrlMetadata])> {
+        let mut releases: Vec<_> = metadata
+            .releases
+            .iter()
+            /* Try to parse as version, ignore those that are invalid (not every tag will be a release) */
+            .filter_map(|(name, files)| {
+                VersionScheme::Pep440
+                    .parse(name)
+                    .map(|version| (name, version, files.as_slice()))
+            })
+            .filter(|(name, _, files)| {
+                files
+                    .iter()
+                    .any(|file| !file.yanked && selector.matches(name, file))
+            })
+            /* Optionally filter out pre-releases */
+            .filter(|(_, version, _)| self.pre_releases || !version.is_pre_release())
+            /* Filter against our constraint */
+            .filter(|(_, version, _)| constraint.is_none_or(|constraint| constraint.matches(version)))
+            .collect();
+        releases.sort_by(|(_, version_a, _), (_, version_b, _)| version_b.cmp(version_a));
+        releases
+    }
+
+    /// Fetch the files of all releases, from the JSON API of PyPi or the simple API of the index
+    ///
+    /// Both `update` and `fetch` need them, so they are cached in the request cache of the
+    /// configuration, if it has one.
+    async fn metadata(&self, config: &Config) -> Result<PyPiMetadata> {
+        let index = self.index.as_ref().unwrap_or(&config.hosts().pypi);
+        let key = format!("pypi {index} {}", self.name);
+        config.cached(key, self.fetch_metadata(config)).await
+    }
+
+    async fn fetch_metadata(&self, config: &Config) -> Result<PyPiMetadata> {
+        let Some(index) = &self.index else {
+            /* Url template: `https://pypi.org/pypi/$pname/json` */
+            let url = config
+                .hosts()
+                .pypi
+                .join(&format!("pypi/{}/json", self.name))?;
+            return get_and_deserialize(config, url)
+                .await
+                .context("Could not fetch Pypi metadata");
+        };
+
+        /* Url template: `$index/$normalized_name/`. Without a trailing slash, the last path segment of the index would be replaced */
+        let mut index = index.clone();
+        if !index.path().ends_with('/') {
+            index.set_path(&format!("{}/", index.path()));
+        }
+        let url = index.join(&format!("{}/", normalize_name(&self.name)))?;
+        let request = config
+            .get(url.clone())
+            .header(header::ACCEPT, SIMPLE_CONTENT_TYPE);
+        let response = retry::send(config, Operation::Request, request)
+            .await
+            .with_context(|| format!("Could not fetch the index page {url}"))?
+            .text()
+            .await?;
+        let project: SimpleProject = serde_json::from_str(&response).with_context(|| {
+            format!("Could not parse the index page {url}, does the index support the JSON simple API (PEP 691)?")
+        })?;
+        project.into_metadata(&url)
+    }
+}
+
 #[async_trait::async_trait]
 impl Updatable for Pin {
     type Version = GenericVersion;
     type Hashes = GenericUrlHashes;
 
-    async fn update(&self, old: Option<&GenericVersion>) -> Result<GenericVersion> {
-        let version_upper_bound: Option<Version<'_>> = self
-            .version_upper_bound
+    async fn update(
+        &self,
+        config: &Config,
+        old: Option<&GenericVersion>,
+    ) -> Result<GenericVersion> {
+        let constraint = self
+            .constraint
             .as_deref()
-            .map(lenient_semver_parser::parse::<Version>)
+            .map(|constraint| Constraint::parse(constraint, VersionScheme::Pep440))
             .transpose()
-            .map_err(|err| err.owned())
-            .context("Field `version_upper_bound` is invalid")?;
+            .context("Field `constraint` is invalid")?;
+        let selector = FileSelector::new(self.wheel.as_deref())?;
 
-        /* Fetch the JSON metadata for a Pypi package.
-         * Url template: `https://pypi.org/pypi/$pname/json`
-         * JSON schema (as in the returned value): https://warehouse.pypa.io/api-reference/json.html
-         */
-        let metadata: PyPiMetadata =
-            get_and_deserialize(format!("https://pypi.org/pypi/{}/json", &self.name))
-                .await
-                .context("Could not fetch Pypi metadata")?;
-        let version = match version_upper_bound {
-            Some(version_upper_bound) => {
-                metadata.releases.keys()
-                    /* Try to parse as version, ignore those that are invalid (not every tag will be a release) */
-                    .filter_map(|version| lenient_semver_parser::parse::<Version>(version).ok())
-                    /* Pre-releases are not supported at the moment */
-                    .filter(|version| !version.is_pre_release())
-                    /* Filter against our upper bound */
-                    .filter(|version| version < &version_upper_bound)
-                    /* Get the latest version */
-                    .max()
-                    .context("No matching versions found")?
-                    .to_string()
-            },
-            /* Simply take latest */
-            None => metadata.info.version,
-        };
+        let metadata = self.metadata(config).await?;
 
-        if let Some(old) = old {
-            let old_version =
-                lenient_semver_parser::parse::<lenient_version::Version>(&old.version);
-            let version = lenient_semver_parser::parse::<lenient_version::Version>(&version);
-            match (old_version, version) {
-                (Ok(old_version), Ok(version)) => {
-                    anyhow::ensure!(
-                        version >= old_version,
-                        "Failed to ensure version monotonicity, latest found version is {} but current is {}",
-                        version,
-                        old_version,
-                    );
-                },
-                _ => {
-                    log::warn!(
-                        "This repository does not appear to be following SemVer, so no guarantees on monotonicity can be made."
-                    );
+        /* Yanks usually signal security or packaging problems, so never silently keep such a version */
+        if let Some(old) = old
+            && let Some(files) = metadata.releases.get(&old.version)
+            && let Some(reason) = yanked(files)
+        {
+            anyhow::bail!(
+                "The pinned version {} of {} has been yanked ({}). Pin a different version with `npins add pypi {} --at <version>`",
+                old.version,
+                self.name,
+                reason.unwrap_or("no reason given"),
+                self.name,
+            );
+        }
+
+        if let Some(min_age) = config.min_age(self.min_age) {
+            let releases = self
+                .releases(&metadata, constraint.as_ref(), &selector)
+                .into_iter()
+                .map(|(name, _, _)| LatestRelease {
+                    tag: name.clone(),
+                    name: name.clone(),
+                })
+                .collect();
+            /* The upload time of a release is the one of its first file */
+            let date = |version: String| {
+                std::future::ready(Ok(metadata.releases[&version]
+                    .iter()
+                    .filter_map(|file| file.upload_time_iso_8601)
+                    .min()))
+            };
+            return Ok(
+                match latest_release_until(releases, date, old, VersionScheme::Pep440, min_age)
+                    .await?
+                {
+                    Some((latest, date)) => GenericVersion {
+                        version: latest.tag,
+                        date: Some(date),
+                    },
+                    None => old.expect("Only the old version can be newer").clone(),
                 },
-            }
+            );
         }
 
-        Ok(GenericVersion { version })
+        let version = self
+            .releases(&metadata, constraint.as_ref(), &selector)
+            .into_iter()
+            .next()
+            .context("No matching versions found")?
+            .0
+            .clone();
+
+        if let Some(old) = old {
+            old.ensure_monotonic(&version, VersionScheme::Pep440)?;
+        }
+
+        Ok(GenericVersion {
+            version,
+            date: None,
+        })
     }
 
-    async fn fetch(&self, version: &GenericVersion) -> Result<GenericUrlHashes> {
-        /* Fetch the JSON metadata for a Pypi package.
-         * Url template: `https://pypi.org/pypi/$pname/json`
-         * JSON schema (as in the returned value): https://warehouse.pypa.io/api-reference/json.html
-         */
-        let mut metadata: PyPiMetadata =
-            get_and_deserialize(format!("https://pypi.org/pypi/{}/json", &self.name))
-                .await
-                .context("Could not fetch Pypi metadata")?;
+    async fn fetch(&self, config: &Config, version: &GenericVersion) -> Result<GenericUrlHashes> {
+        let selector = FileSelector::new(self.wheel.as_deref())?;
+        let mut metadata = self.metadata(config).await?;
 
-        let mut latest_source: PyPiUrlMetadata = metadata
+        let files = metadata
             .releases
             .remove(&version.version)
             .with_context(|| {
                 anyhow::format_err!("Could not find requested version {}", version.version)
-            })?
+            })?;
+        if let Some(reason) = yanked(&files) {
+            log::warn!(
+                "Version {} of {} has been yanked ({})",
+                version.version,
+                self.name,
+                reason.unwrap_or("no reason given")
+            );
+        }
+        let mut files: Vec<PyPiUrlMetadata> = files
             .into_iter()
-            /* Of all files for the latest release, we only care about source tarballs */
-            .find(|file_meta| file_meta.python_version == "source")
-            .context("Unsupported package: must contain some \"source\" download")?;
+            .filter(|file| selector.matches(&version.version, file))
+            .collect();
+        /* A yanked file is only used if the whole version was yanked and is pinned explicitly */
+        if files.iter().any(|file| !file.yanked) {
+            files.retain(|file| !file.yanked);
+        }
+        let mut latest_source = match (&selector, files.len()) {
+            (_, 0) => match selector {
+                FileSelector::Source => {
+                    anyhow::bail!("Unsupported package: must contain some \"source\" download")
+                },
+                _ => anyhow::bail!(
+                    "Version {} has no wheel matching {}",
+                    version.version,
+                    self.wheel.as_deref().unwrap_or_default()
+                ),
+            },
+            /* Some old releases have multiple source distributions, e.g. both a .tar.gz and a .zip */
+            (FileSelector::Source, _) | (_, 1) => files.swap_remove(0),
+            _ => anyhow::bail!(
+                "Multiple wheels of version {} match {}: {}",
+                version.version,
+                self.wheel.as_deref().unwrap_or_default(),
+                files
+                    .iter()
+                    .map(|file| file.filename.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
 
         let hash_str = latest_source
             .digests
@@ -140,59 +301,256 @@ impl Updatable for Pin {
             url: latest_source.url.parse()?,
         })
     }
+
+    fn host(&self, config: &Config) -> Option<String> {
+        self.index
+            .as_ref()
+            .unwrap_or(&config.hosts().pypi)
+            .host_str()
+            .map(Into::into)
+    }
+}
+
+/// Which file of a release is pinned, see [`Pin::wheel`]
+enum FileSelector<'a> {
+    Source,
+    Filename(&'a str),
+    /// Python, ABI and platform tag
+    Tag([&'a str; 3]),
+}
+
+impl<'a> FileSelector<'a> {
+    fn new(wheel: Option<&'a str>) -> Result<Self> {
+        Ok(match wheel {
+            None => Self::Source,
+            Some(filename) if filename.ends_with(".whl") => Self::Filename(filename),
+            Some(tag) => {
+                let tag = tag
+                    .splitn(3, '-')
+                    .collect::<Vec<_>>()
+                    .try_into()
+                    .ok()
+                    .with_context(|| {
+                        format!("Field `wheel` is invalid: `{tag}` is neither a file name ending in .whl nor a tag like py3-none-any")
+                    })?;
+                Self::Tag(tag)
+            },
+        })
+    }
+
+    fn matches(&self, version: &str, file: &PyPiUrlMetadata) -> bool {
+        match self {
+            Self::Source => file.python_version == "source",
+            Self::Filename(pattern) => {
+                glob_match(&pattern.replace("{version}", version), &file.filename)
+            },
+            Self::Tag(tag) => wheel_tags(&file.filename).iter().any(|wheel_tag| {
+                tag.iter()
+                    .zip(wheel_tag)
+                    .all(|(pattern, tag)| glob_match(pattern, tag))
+            }),
+        }
+    }
+}
+
+/// All compatibility tags of a wheel, expanding compressed tag sets like `py2.py3-none-any`
+///
+/// See <https://packaging.python.org/en/latest/specifications/binary-distribution-format/>
+fn wheel_tags(filename: &str) -> Vec<[&str; 3]> {
+    let Some(stem) = filename.strip_suffix(".whl") else {
+        return Vec::new();
+    };
+    /* name-version(-build)?-python-abi-platform */
+    let parts: Vec<&str> = stem.split('-').collect();
+    let ([_, _, python, abi, platform] | [_, _, _, python, abi, platform]) = parts.as_slice()
+    else {
+        return Vec::new();
+    };
+    let mut tags = Vec::new();
+    for python in python.split('.') {
+        for abi in abi.split('.') {
+            for platform in platform.split('.') {
+                tags.push([python, abi, platform]);
+            }
+        }
+    }
+    tags
+}
+
+/// Normalize a project name for the simple API, see
+/// <https://packaging.python.org/en/latest/specifications/name-normalization/>
+fn normalize_name(name: &str) -> String {
+    name.split(['-', '_', '.'])
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+        .to_lowercase()
 }
 
 /// The actual JSON file is rather large, we only deserialize what we are interested in,
 /// and only up to the granularity we are interested in.
 /// JSON API specification: <https://warehouse.pypa.io/api-reference/json.html>
 #[allow(unused)]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 struct PyPiMetadata {
-    /// The latest release
-    pub info: PyPiInfoMetadata,
     /// This contains releases
     pub releases: HashMap<String, Vec<PyPiUrlMetadata>>,
-    /// This contains all data for the latest release
-    pub urls: Vec<PyPiUrlMetadata>,
 }
 
 // Again, this is not complete
 #[allow(unused)]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 struct PyPiUrlMetadata {
     digests: HashMap<String, String>,
     filename: String,
     python_version: String,
     url: String,
+    #[serde(default)]
+    upload_time_iso_8601: Option<Timestamp>,
+    #[serde(default)]
+    yanked: bool,
+    #[serde(default)]
+    yanked_reason: Option<String>,
+}
+
+const SIMPLE_CONTENT_TYPE: &str = "application/vnd.pypi.simple.v1+json";
+
+/// A project page of the JSON simple API, again only what we need
+///
+/// Specification: <https://packaging.python.org/en/latest/specifications/simple-repository-api/>
+#[derive(Debug, Deserialize)]
+struct SimpleProject {
+    files: Vec<SimpleFile>,
 }
 
 #[derive(Debug, Deserialize)]
-struct PyPiInfoMetadata {
-    version: String,
+struct SimpleFile {
+    filename: String,
+    url: String,
+    hashes: HashMap<String, String>,
+    /// Either a flag, or the reason of the yank
+    #[serde(default)]
+    yanked: SimpleYanked,
+    /// Only since PEP 700
+    #[serde(default, rename = "upload-time")]
+    upload_time: Option<Timestamp>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(untagged)]
+enum SimpleYanked {
+    #[default]
+    No,
+    Flag(bool),
+    Reason(String),
+}
+
+impl SimpleProject {
+    /// Group the files by the version in their name, like the JSON API does
+    ///
+    /// Relative URLs are resolved against the URL of the page.
+    fn into_metadata(self, url: &Url) -> Result<PyPiMetadata> {
+        let mut releases: HashMap<String, Vec<PyPiUrlMetadata>> = HashMap::new();
+        for file in self.files {
+            /* Wheels are `name-version-...-python-abi-platform.whl`, source distributions `name-version.tar.gz` or `.zip` */
+            let (version, python_version) = if let Some(stem) = file.filename.strip_suffix(".whl") {
+                let parts: Vec<&str> = stem.split('-').collect();
+                match parts.as_slice() {
+                    [_, version, .., python, _, _] => (version.to_string(), python.to_string()),
+                    _ => continue,
+                }
+            } else {
+                let Some(version) = [".tar.gz", ".zip"]
+                    .iter()
+                    .find_map(|extension| file.filename.strip_suffix(extension))
+                    .and_then(|stem| stem.rsplit_once('-'))
+                    .map(|(_, version)| version.to_string())
+                else {
+                    /* Eggs and other legacy formats */
+                    continue;
+                };
+                (version, "source".to_string())
+            };
+            let (yanked, yanked_reason) = match file.yanked {
+                SimpleYanked::No | SimpleYanked::Flag(false) => (false, None),
+                SimpleYanked::Flag(true) => (true, None),
+                SimpleYanked::Reason(reason) => (true, Some(reason)),
+            };
+            releases.entry(version).or_default().push(PyPiUrlMetadata {
+                digests: file.hashes,
+                python_version,
+                url: url
+                    .join(&file.url)
+                    .with_context(|| format!("Invalid URL of file {}", file.filename))?
+                    .to_string(),
+                filename: file.filename,
+                upload_time_iso_8601: file.upload_time,
+                yanked,
+                yanked_reason,
+            });
+        }
+        Ok(PyPiMetadata { releases })
+    }
+}
+
+/// Whether a release has been yanked, i.e. all of its files, with the reason if one was given
+fn yanked(files: &[PyPiUrlMetadata]) -> Option<Option<&str>> {
+    (!files.is_empty() && files.iter().all(|file| file.yanked))
+        .then(|| files.iter().find_map(|file| file.yanked_reason.as_deref()))
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    fn config() -> Config {
+        Config::builder().build().unwrap()
+    }
+
+    #[test]
+    fn test_wheel_tags() {
+        assert_eq!(
+            wheel_tags("foo-1.0-py2.py3-none-any.whl"),
+            vec![["py2", "none", "any"], ["py3", "none", "any"]]
+        );
+        assert_eq!(
+            wheel_tags("foo-1.0-1-cp312-abi3-win_amd64.whl"),
+            vec![["cp312", "abi3", "win_amd64"]]
+        );
+        assert!(wheel_tags("foo-1.0.tar.gz").is_empty());
+        assert!(wheel_tags("foo-1.0-any.whl").is_empty());
+    }
+
+    #[test]
+    fn test_normalize_name() {
+        assert_eq!(normalize_name("Foo.Bar__baz-"), "foo-bar-baz");
+        assert_eq!(normalize_name("black"), "black");
+    }
+
     #[tokio::test]
+    #[ignore = "requires network access"]
     async fn test_pypi_update() -> Result<()> {
         /* Last release has been in 2016, there are separate packages for major releases.
          * There's no way this will get an update anymore.
          */
         let pin = Pin {
             name: "gaiatest".into(),
-            version_upper_bound: None,
+            index: None,
+            wheel: None,
+            pre_releases: false,
+            constraint: None,
+            min_age: None,
         };
-        let version = pin.update(None).await?;
+        let version = pin.update(&config(), None).await?;
         assert_eq!(
             version,
             GenericVersion {
                 version: "0.34".into(),
+                date: None,
             }
         );
         assert_eq!(
-            pin.fetch(&version).await?,
+            pin.fetch(&config(), &version).await?,
             GenericUrlHashes {
                 hash: NixHash::from_sri("sha256-OVOxWLe2kGQtaM1r6x1Z9uEFJvLuEKb7RjapE8yV5xg=").unwrap(),
                 url: "https://files.pythonhosted.org/packages/d1/d5/0c270c22d61ff6b883d0f24956f13e904b131b5ac2829e0af1cda99d70b1/gaiatest-0.34.tar.gz".parse().unwrap(),
@@ -202,23 +560,29 @@ mod test {
     }
 
     #[tokio::test]
+    #[ignore = "requires network access"]
     async fn test_pypi_max_version() -> Result<()> {
         /* Last release has been in 2016, there are separate packages for major releases.
          * There's no way this will get an update anymore.
          */
         let pin = Pin {
             name: "streamlit".into(),
-            version_upper_bound: Some("1.0.0".into()),
+            index: None,
+            wheel: None,
+            pre_releases: false,
+            constraint: Some("<1.0.0".into()),
+            min_age: None,
         };
-        let version = pin.update(None).await?;
+        let version = pin.update(&config(), None).await?;
         assert_eq!(
             version,
             GenericVersion {
                 version: "0.89.0".into(),
+                date: None,
             }
         );
         assert_eq!(
-            pin.fetch(&version).await?,
+            pin.fetch(&config(), &version).await?,
             GenericUrlHashes {
 
                 hash: NixHash::from_sri("sha256-OdCcZiclX885yTiTeZVmW2N3eZxPoUH2tIG8teamiKw=").unwrap(),
@@ -0,0 +1,452 @@
+//! Pin a binary asset attached to a release on a forge
+//!
+//! Releases are listed through the GitHub, Forgejo and GitLab releases APIs. Plain git repositories
+//! have no notion of releases and are thus not supported.
+
+use anyhow::{Context, Result};
+use reqwest::header::{HeaderMap, LINK};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use url::Url;
+
+use crate::config::Hosts;
+use crate::constraint::Constraint;
+use crate::git::{LatestRelease, Repository, TagFilter, latest_release};
+use crate::retry::{self, Operation};
+use crate::version_scheme::VersionScheme;
+use crate::{Config, GenericUrlHashes, GenericVersion, Updatable, diff, prefetch};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct ReleaseAssetPin {
+    pub repository: Repository,
+    /// Name of the asset to pin, for example `foo-{version}-x86_64-linux.tar.gz`
+    ///
+    /// `*` matches any sequence of characters. `{tag}` is replaced with the tag of the release,
+    /// and `{version}` with the tag without the release prefix and without a leading `v`.
+    /// It must match exactly one asset of the release.
+    pub asset: String,
+    /// Whether to unpack the asset (use fetchTarball) or not (use fetchurl)
+    #[serde(default)]
+    pub unpack: bool,
+    /// Also track pre-releases.
+    #[serde(default)]
+    pub pre_releases: bool,
+    /// Optionally restrict the releases to pin, see [`crate::constraint`]
+    pub constraint: Option<String>,
+    /// Optionally filter the considered releases by a prefix of their tag,
+    /// see [`GitReleasePin`](crate::git::GitReleasePin)
+    pub release_prefix: Option<String>,
+    /// Optionally filter the considered releases by a regular expression with capture groups
+    /// extracting the version, see [`GitReleasePin`](crate::git::GitReleasePin)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag_pattern: Option<String>,
+    /// How the versions are parsed and ordered, see [`GitReleasePin`](crate::git::GitReleasePin)
+    #[serde(default, skip_serializing_if = "VersionScheme::is_default")]
+    pub version_scheme: VersionScheme,
+}
+
+impl diff::Diff for ReleaseAssetPin {
+    fn properties(&self) -> Vec<(String, String)> {
+        [
+            Some((
+                "repository".into(),
+                self.repository
+                    .git_url(&Hosts::default())
+                    .unwrap()
+                    .to_string(),
+            )),
+            Some(("asset".into(), self.asset.clone())),
+            Some(("unpack".into(), self.unpack.to_string())),
+            Some(("pre_releases".into(), self.pre_releases.to_string())),
+            self.constraint
+                .as_ref()
+                .map(|constraint| ("constraint".into(), constraint.clone())),
+            self.release_prefix
+                .as_ref()
+                .map(|release_prefix| ("release_prefix".into(), release_prefix.clone())),
+            self.tag_pattern
+                .as_ref()
+                .map(|tag_pattern| ("tag_pattern".into(), tag_pattern.clone())),
+            (!self.version_scheme.is_default())
+                .then(|| ("version_scheme".into(), self.version_scheme.to_string())),
+        ]
+        .into_iter()
+        .flat_map(Option::into_iter)
+        .collect()
+    }
+}
+
+impl ReleaseAssetPin {
+    /// Fetch the published releases of the repository, newest first
+    ///
+    /// The forges paginate the releases. Pages are only fetched until `done` returns `true` for
+    /// the releases so far, to not use up the rate limit on repositories with many releases.
+    async fn releases(
+        &self,
+        config: &Config,
+        mut done: impl FnMut(&[Release]) -> bool,
+    ) -> Result<Vec<Release>> {
+        let config = &self.repository.authenticated(config).await?;
+        let mut url = Some(self.repository.releases_url(config.hosts())?.context(
+            "Release assets are only supported for GitHub, GitLab and Forgejo repositories",
+        )?);
+
+        let mut releases = Vec::new();
+        while let Some(page) = url.take() {
+            let response =
+                retry::send(config, Operation::Request, config.get(page.clone())).await?;
+            url = next_page(&page, response.headers());
+            let body = response.text().await?;
+            match &self.repository {
+                Repository::GitLab { .. } => {
+                    let page: Vec<GitLabRelease> = serde_json::from_str(&body)?;
+                    releases.extend(
+                        page.into_iter()
+                            .filter(|release| !release.upcoming_release)
+                            .map(|release| Release {
+                                tag: release.tag_name,
+                                pre_release: false,
+                                assets: release
+                                    .assets
+                                    .links
+                                    .into_iter()
+                                    .map(|link| Asset {
+                                        name: link.name,
+                                        url: link.direct_asset_url.unwrap_or(link.url),
+                                    })
+                                    .collect(),
+                            }),
+                    );
+                },
+                /* Forgejo copied the GitHub API */
+                _ => {
+                    let page: Vec<GitHubRelease> = serde_json::from_str(&body)?;
+                    releases.extend(page.into_iter().filter(|release| !release.draft).map(
+                        |release| {
+                            Release {
+                                tag: release.tag_name,
+                                pre_release: release.prerelease,
+                                assets: release
+                                    .assets
+                                    .into_iter()
+                                    .map(|asset| Asset {
+                                        name: asset.name,
+                                        url: asset.browser_download_url,
+                                    })
+                                    .collect(),
+                            }
+                        },
+                    ));
+                },
+            }
+            if done(&releases) {
+                break;
+            }
+        }
+        Ok(releases)
+    }
+
+    /// The latest release with a matching asset
+    fn latest(
+        &self,
+        releases: &[Release],
+        constraint: Option<&Constraint>,
+        filter: &TagFilter,
+    ) -> Option<LatestRelease> {
+        /* Assets are often uploaded some time after the release has been created. Ambiguous
+         * matches are only reported when fetching, as they may only affect older releases.
+         */
+        let candidates = releases
+            .iter()
+            .filter(|release| {
+                (self.pre_releases || !release.pre_release)
+                    && !matches!(self.find_asset(filter, release), Ok(None))
+            })
+            .map(|release| release.tag.as_str());
+        latest_release(
+            candidates,
+            self.pre_releases,
+            constraint,
+            filter,
+            self.version_scheme,
+        )
+    }
+
+    /// Find the asset matching the pattern in a release
+    ///
+    /// Returns `None` if there is no match, and an error if the match is ambiguous.
+    fn find_asset<'a>(
+        &self,
+        filter: &TagFilter,
+        release: &'a Release,
+    ) -> Result<Option<&'a Asset>> {
+        let version = filter
+            .version(&release.tag)
+            .unwrap_or(Cow::Borrowed(&release.tag));
+        let version = version.strip_prefix('v').unwrap_or(&version);
+        let pattern = self
+            .asset
+            .replace("{tag}", &release.tag)
+            .replace("{version}", version);
+
+        let matches: Vec<&Asset> = release
+            .assets
+            .iter()
+            .filter(|asset| glob_match(&pattern, &asset.name))
+            .collect();
+        match matches.as_slice() {
+            [] => Ok(None),
+            [asset] => Ok(Some(asset)),
+            _ => anyhow::bail!(
+                "Asset name {pattern} is ambiguous for release {}, it matches {}",
+                release.tag,
+                matches
+                    .iter()
+                    .map(|asset| asset.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Updatable for ReleaseAssetPin {
+    type Version = GenericVersion;
+    type Hashes = GenericUrlHashes;
+
+    async fn update(
+        &self,
+        config: &Config,
+        old: Option<&GenericVersion>,
+    ) -> Result<GenericVersion> {
+        let constraint = self
+            .constraint
+            .as_deref()
+            .map(|constraint| Constraint::parse(constraint, self.version_scheme))
+            .transpose()
+            .context("Field `constraint` is invalid")?;
+        let filter = TagFilter::new(self.release_prefix.as_deref(), self.tag_pattern.as_deref())?;
+
+        /* Releases are listed newest first, so the latest matching one is usually on the first
+         * page. Only pins following older versions need to look further back.
+         */
+        let releases = self
+            .releases(config, |releases| {
+                self.latest(releases, constraint.as_ref(), &filter)
+                    .is_some()
+            })
+            .await
+            .context("Couldn't fetch the releases")?;
+        let latest = self
+            .latest(&releases, constraint.as_ref(), &filter)
+            .with_context(|| {
+                format!(
+                    "Repository has no matching releases with an asset {}",
+                    self.asset
+                )
+            })?;
+
+        if let Some(old) = old {
+            let version = filter
+                .version(&old.version)
+                .unwrap_or(Cow::Borrowed(&old.version));
+            GenericVersion {
+                version: version.into_owned(),
+                date: None,
+            }
+            .ensure_monotonic(&latest.name, self.version_scheme)?;
+        }
+
+        Ok(GenericVersion {
+            version: latest.tag,
+            date: None,
+        })
+    }
+
+    async fn fetch(&self, config: &Config, version: &GenericVersion) -> Result<GenericUrlHashes> {
+        let releases = self
+            .releases(config, |releases| {
+                releases
+                    .iter()
+                    .any(|release| release.tag == version.version)
+            })
+            .await
+            .context("Couldn't fetch the releases")?;
+        let release = releases
+            .iter()
+            .find(|release| release.tag == version.version)
+            .with_context(|| format!("Could not find release {}", version.version))?;
+        let filter = TagFilter::new(self.release_prefix.as_deref(), self.tag_pattern.as_deref())?;
+        let asset = self
+            .find_asset(&filter, release)?
+            .with_context(|| format!("Release {} has no asset {}", release.tag, self.asset))?;
+
+        let url: Url = asset.url.parse().context("Invalid asset URL")?;
+        let config = &self.repository.authenticated(config).await?;
+        let hash = prefetch::prefetch_url(config, &url, self.unpack).await?;
+        Ok(GenericUrlHashes { url, hash })
+    }
+
+    fn host(&self, config: &Config) -> Option<String> {
+        self.repository.host(config.hosts())
+    }
+}
+
+/// Match a name against a pattern where `*` matches any (possibly empty) sequence of characters
+pub(crate) fn glob_match(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let Some(name) = name.strip_prefix(prefix) else {
+                return false;
+            };
+            name.char_indices()
+                .map(|(i, _)| i)
+                .chain(std::iter::once(name.len()))
+                .any(|i| glob_match(rest, &name[i..]))
+        },
+    }
+}
+
+/// The URL of the next page of a paginated API response, if there is one
+///
+/// GitHub and Forgejo send a `Link` header with the `next` relation. GitLab also sends the number of
+/// the next page in `X-Next-Page`, which is empty on the last one.
+fn next_page(url: &Url, headers: &HeaderMap) -> Option<Url> {
+    if let Some(link) = headers.get(LINK).and_then(|link| link.to_str().ok()) {
+        return link.split(',').find_map(|link| {
+            let (target, params) = link.trim().split_once(';')?;
+            params
+                .split(';')
+                .any(|param| param.trim() == r#"rel="next""#)
+                .then(|| {
+                    target
+                        .trim()
+                        .strip_prefix('<')?
+                        .strip_suffix('>')?
+                        .parse()
+                        .ok()
+                })
+                .flatten()
+        });
+    }
+    let page = headers
+        .get("x-next-page")
+        .and_then(|page| page.to_str().ok())
+        .filter(|page| !page.trim().is_empty())?;
+    let mut next = url.clone();
+    let query: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(key, _)| key != "page")
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+    next.query_pairs_mut()
+        .clear()
+        .extend_pairs(query)
+        .append_pair("page", page.trim());
+    Some(next)
+}
+
+/// A release, independent of the forge
+struct Release {
+    tag: String,
+    pre_release: bool,
+    assets: Vec<Asset>,
+}
+
+struct Asset {
+    name: String,
+    url: String,
+}
+
+/// Used by GitHub and Forgejo, again this is not complete
+#[derive(Debug, Deserialize)]
+struct GitHubRelease {
+    tag_name: String,
+    #[serde(default)]
+    draft: bool,
+    #[serde(default)]
+    prerelease: bool,
+    assets: Vec<GitHubAsset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubAsset {
+    name: String,
+    browser_download_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitLabRelease {
+    tag_name: String,
+    #[serde(default)]
+    upcoming_release: bool,
+    assets: GitLabAssets,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitLabAssets {
+    links: Vec<GitLabAssetLink>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitLabAssetLink {
+    name: String,
+    url: String,
+    direct_asset_url: Option<String>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("foo.tar.gz", "foo.tar.gz"));
+        assert!(!glob_match("foo.tar.gz", "foo.tar.xz"));
+        assert!(glob_match("foo-*-linux.tar.gz", "foo-x86_64-linux.tar.gz"));
+        assert!(glob_match("foo-*", "foo-"));
+        assert!(glob_match("*-linux*", "foo-linux.tar.gz"));
+        assert!(!glob_match(
+            "foo-*-linux.tar.gz",
+            "foo-x86_64-darwin.tar.gz"
+        ));
+        assert!(glob_match("*", ""));
+    }
+
+    #[test]
+    fn test_next_page() {
+        let url: Url = "https://gitlab.com/api/v4/projects/1/releases?per_page=100&page=2"
+            .parse()
+            .unwrap();
+        let headers = |headers: &[(&'static str, &'static str)]| -> HeaderMap {
+            headers
+                .iter()
+                .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+                .collect()
+        };
+
+        let github = headers(&[(
+            "link",
+            r#"<https://api.github.com/repositories/1/releases?page=3>; rel="next", <https://api.github.com/repositories/1/releases?page=9>; rel="last""#,
+        )]);
+        assert_eq!(
+            next_page(&url, &github).unwrap().as_str(),
+            "https://api.github.com/repositories/1/releases?page=3"
+        );
+        let last = headers(&[(
+            "link",
+            r#"<https://api.github.com/repositories/1/releases?page=1>; rel="first""#,
+        )]);
+        assert_eq!(next_page(&url, &last), None);
+
+        let gitlab = headers(&[("x-next-page", "3")]);
+        assert_eq!(
+            next_page(&url, &gitlab).unwrap().as_str(),
+            "https://gitlab.com/api/v4/projects/1/releases?per_page=100&page=3"
+        );
+        assert_eq!(next_page(&url, &headers(&[("x-next-page", "")])), None);
+        assert_eq!(next_page(&url, &HeaderMap::new()), None);
+    }
+}
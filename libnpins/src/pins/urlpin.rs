@@ -11,9 +11,11 @@
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use url::Url;
 
-use crate::{GenericHash, Updatable, build_client, diff, nix};
+use crate::retry::{self, Operation};
+use crate::{Config, GenericHash, PerSystemHashes, Updatable, diff, prefetch};
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub struct UrlPin {
@@ -37,15 +39,19 @@ impl Updatable for UrlPin {
     type Version = ();
     type Hashes = GenericHash;
 
-    async fn update(&self, _old: Option<&()>) -> Result<()> {
+    async fn update(&self, _config: &Config, _old: Option<&()>) -> Result<()> {
         // Static URL, no versioning needed
         Ok(())
     }
 
-    async fn fetch(&self, _version: &()) -> Result<Self::Hashes> {
-        let hash = nix::nix_prefetch_url(&self.url, self.unpack).await?;
+    async fn fetch(&self, config: &Config, _version: &()) -> Result<Self::Hashes> {
+        let hash = prefetch::prefetch_url(config, &self.url, self.unpack).await?;
         Ok(Self::Hashes { hash })
     }
+
+    fn host(&self, _config: &Config) -> Option<String> {
+        self.url.host_str().map(Into::into)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
@@ -82,19 +88,71 @@ impl Updatable for MutableUrlPin {
     type Version = LockedTarballVersion;
     type Hashes = GenericHash;
 
-    async fn update(&self, _old: Option<&LockedTarballVersion>) -> Result<LockedTarballVersion> {
+    async fn update(
+        &self,
+        config: &Config,
+        _old: Option<&LockedTarballVersion>,
+    ) -> Result<LockedTarballVersion> {
         // HEAD our "mutable" url and follow all redirects to get our actual, "locked" url
-        let url = build_client()?
-            .head(self.update_url.clone())
-            .send()
+        let request = config.head(self.update_url.clone());
+        let url = retry::send_unchecked(config, Operation::Request, request)
             .await?
             .url()
             .clone();
         Ok(LockedTarballVersion { url })
     }
 
-    async fn fetch(&self, version: &LockedTarballVersion) -> Result<Self::Hashes> {
-        let hash = nix::nix_prefetch_url(&version.url, self.unpack).await?;
+    async fn fetch(&self, config: &Config, version: &LockedTarballVersion) -> Result<Self::Hashes> {
+        let hash = prefetch::prefetch_url(config, &version.url, self.unpack).await?;
         Ok(Self::Hashes { hash })
     }
+
+    fn host(&self, _config: &Config) -> Option<String> {
+        self.update_url.host_str().map(Into::into)
+    }
+}
+
+/// Like [`UrlPin`], but with a different URL for each Nix system
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct PerSystemUrlPin {
+    /// The static URL for each system, e.g. `x86_64-linux`
+    pub urls: BTreeMap<String, Url>,
+    /// Whether to unpack it (use fetchTarball) or not (use fetchurl)
+    pub unpack: bool,
+}
+
+impl diff::Diff for PerSystemUrlPin {
+    fn properties(&self) -> Vec<(String, String)> {
+        self.urls
+            .iter()
+            .map(|(system, url)| (format!("{system}.url"), url.to_string()))
+            .chain([("unpack".into(), self.unpack.to_string())])
+            .collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl Updatable for PerSystemUrlPin {
+    type Version = ();
+    type Hashes = PerSystemHashes;
+
+    async fn update(&self, _config: &Config, _old: Option<&()>) -> Result<()> {
+        // Static URLs, no versioning needed
+        Ok(())
+    }
+
+    async fn fetch(&self, config: &Config, _version: &()) -> Result<Self::Hashes> {
+        PerSystemHashes::fetch_all(&self.urls, |url| {
+            prefetch::prefetch_url(config, url, self.unpack)
+        })
+        .await
+    }
+
+    /* The artifacts of all systems are usually hosted together */
+    fn host(&self, _config: &Config) -> Option<String> {
+        self.urls
+            .values()
+            .find_map(|url| url.host_str())
+            .map(Into::into)
+    }
 }
@@ -0,0 +1,434 @@
+//! Prefetching of URLs and tarballs
+//!
+//! By default, this is done natively: the file is downloaded with reqwest, archives are unpacked
+//! into a temporary directory and the result is serialized to a NAR in order to hash it. The
+//! resulting hashes are the same as those given by `nix-prefetch-url [--unpack]`, which is still
+//! available as an alternative backend.
+
+use anyhow::{Context, Result};
+use nix_compat::nar;
+use nix_compat::nixhash::NixHash;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, BufReader, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::str::FromStr;
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+use crate::retry::{self, Operation};
+use crate::{Config, nix};
+
+/// The environment variable used to select the [`PrefetchBackend`]
+pub const PREFETCH_BACKEND_ENV: &str = "NPINS_PREFETCH_BACKEND";
+
+/// The implementation used to prefetch URLs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PrefetchBackend {
+    /// Download, unpack and hash in-process, without requiring Nix
+    #[default]
+    Native,
+    /// Shell out to `nix-prefetch-url`, which additionally adds the result to the Nix store
+    NixPrefetchUrl,
+}
+
+impl FromStr for PrefetchBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "native" => Ok(Self::Native),
+            "nix-prefetch-url" => Ok(Self::NixPrefetchUrl),
+            other => anyhow::bail!(
+                "Unknown prefetch backend '{}', expected 'native' or 'nix-prefetch-url'",
+                other
+            ),
+        }
+    }
+}
+
+impl PrefetchBackend {
+    /// Select the backend from the `NPINS_PREFETCH_BACKEND` environment variable, if set
+    pub fn from_env() -> Result<Self> {
+        match std::env::var(PREFETCH_BACKEND_ENV) {
+            Ok(value) => value
+                .parse()
+                .with_context(|| format!("Invalid value for {}", PREFETCH_BACKEND_ENV)),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+}
+
+/// Prefetch a tarball, returning the hash of its unpacked contents
+pub async fn prefetch_tarball(config: &Config, url: impl AsRef<str>) -> Result<NixHash> {
+    prefetch_url(config, url, true).await
+}
+
+/// Prefetch an URL, returning either its flat hash or the NAR hash of its unpacked contents
+pub async fn prefetch_url(config: &Config, url: impl AsRef<str>, unpack: bool) -> Result<NixHash> {
+    match config.prefetch_backend() {
+        PrefetchBackend::Native => native_prefetch_url(config, url.as_ref(), unpack).await,
+        PrefetchBackend::NixPrefetchUrl => nix::nix_prefetch_url(config, url, unpack).await,
+    }
+}
+
+async fn native_prefetch_url(config: &Config, url: &str, unpack: bool) -> Result<NixHash> {
+    let result = async {
+        let parsed: Url = url.parse().context("Failed to parse URL")?;
+        let workdir = tempfile::tempdir().context("Failed to create temporary directory")?;
+
+        let file = if parsed.scheme() == "file" {
+            parsed
+                .to_file_path()
+                .map_err(|()| anyhow::format_err!("Invalid file URL"))?
+        } else {
+            let file = workdir.path().join("download");
+            download(config, parsed, &file).await?;
+            file
+        };
+
+        tokio::task::spawn_blocking(move || {
+            if unpack {
+                let destination = workdir.path().join("unpacked");
+                let result = hash_unpacked(&file, &destination);
+                /* Make sure the temporary directory can be cleaned up again */
+                make_writable(&destination)?;
+                result
+            } else {
+                hash_flat(&file)
+            }
+        })
+        .await
+        .context("Prefetching task panicked")?
+    };
+    result
+        .await
+        .with_context(|| format!("failed to prefetch url: {}", url))
+}
+
+/// Stream the response body into a file, starting over if the download fails
+async fn download(config: &Config, url: Url, destination: &Path) -> Result<()> {
+    log::debug!("Downloading {}", url);
+    let mut attempts = config.retry_policy().attempts(format!("Downloading {url}"));
+    loop {
+        let result = async {
+            let mut response =
+                retry::attempt(config, Operation::Prefetch, config.get(url.clone()), true).await?;
+            let mut file = tokio::fs::File::create(destination).await?;
+            while let Some(chunk) = response.chunk().await? {
+                file.write_all(&chunk).await?;
+            }
+            file.flush().await?;
+            Ok(())
+        };
+        match result.await {
+            Ok(()) => return Ok(()),
+            Err(err) => attempts.failed(err).await?,
+        }
+    }
+}
+
+fn hash_flat(file: &Path) -> Result<NixHash> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(file)?, &mut hasher)?;
+    Ok(NixHash::Sha256(hasher.finalize().into()))
+}
+
+/// Unpack an archive and hash its only top-level entry, like `nix-prefetch-url --unpack` does
+fn hash_unpacked(archive: &Path, destination: &Path) -> Result<NixHash> {
+    fs::create_dir(destination)?;
+    unpack(archive, destination).context("Failed to unpack archive")?;
+
+    let mut entries = fs::read_dir(destination)?.collect::<io::Result<Vec<_>>>()?;
+    let entry = match entries.len() {
+        1 => entries.remove(0),
+        0 => anyhow::bail!("Archive is empty"),
+        n => anyhow::bail!(
+            "Archive must contain exactly one top-level entry, but it contains {}",
+            n
+        ),
+    };
+
+    nar_hash(&entry.path())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveFormat {
+    Tar,
+    TarGz,
+    TarXz,
+    TarBz2,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Detect the format from the magic bytes at the start of the file
+    ///
+    /// We can't rely on file extensions, since many download URLs don't have any.
+    fn detect(header: &[u8]) -> Self {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            Self::TarGz
+        } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Self::TarXz
+        } else if header.starts_with(b"BZh") {
+            Self::TarBz2
+        } else if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+            Self::Zip
+        } else {
+            /* Not all tar variants have a magic value, so simply give it a try */
+            Self::Tar
+        }
+    }
+}
+
+fn unpack(archive: &Path, destination: &Path) -> Result<()> {
+    let mut header = Vec::with_capacity(6);
+    fs::File::open(archive)?.take(6).read_to_end(&mut header)?;
+    let format = ArchiveFormat::detect(&header);
+    log::debug!("Unpacking {} as {:?}", archive.display(), format);
+
+    let file = BufReader::new(fs::File::open(archive)?);
+    match format {
+        ArchiveFormat::Tar => unpack_tar(file, destination),
+        ArchiveFormat::TarGz => unpack_tar(flate2::bufread::MultiGzDecoder::new(file), destination),
+        ArchiveFormat::TarXz => unpack_tar(xz2::bufread::XzDecoder::new(file), destination),
+        ArchiveFormat::TarBz2 => unpack_tar(bzip2::bufread::MultiBzDecoder::new(file), destination),
+        ArchiveFormat::Zip => {
+            zip::ZipArchive::new(file)?.extract(destination)?;
+            Ok(())
+        },
+    }
+}
+
+fn unpack_tar(reader: impl Read, destination: &Path) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    archive.set_preserve_mtime(false);
+    archive.unpack(destination)?;
+    Ok(())
+}
+
+/// Compute the SHA-256 hash over the NAR serialization of a path
+fn nar_hash(path: &Path) -> Result<NixHash> {
+    let mut hasher = Sha256::new();
+    let node = nar::writer::open(&mut hasher)?;
+    dump_path(node, path).with_context(|| format!("Failed to serialize {}", path.display()))?;
+    Ok(NixHash::Sha256(hasher.finalize().into()))
+}
+
+fn dump_path(node: nar::writer::Node<'_, '_>, path: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    let file_type = metadata.file_type();
+
+    if file_type.is_symlink() {
+        node.symlink(fs::read_link(path)?.as_os_str().as_bytes())
+    } else if file_type.is_dir() {
+        /* NAR requires the entries to be sorted by their name's bytes */
+        let mut names = fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<io::Result<Vec<_>>>()?;
+        names.sort();
+
+        let mut directory = node.directory()?;
+        for name in names {
+            dump_path(directory.entry(name.as_bytes())?, &path.join(&name))?;
+        }
+        directory.close()
+    } else if file_type.is_file() {
+        let executable = metadata.permissions().mode() & 0o100 != 0;
+        let mut reader = BufReader::new(fs::File::open(path)?);
+        node.file(executable, metadata.len(), &mut reader)
+    } else {
+        Err(io::Error::other(format!(
+            "Unsupported file type at {}",
+            path.display()
+        )))
+    }
+}
+
+/// Archives may contain read-only directories, which would prevent their deletion
+fn make_writable(path: &Path) -> io::Result<()> {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return Ok(());
+    };
+    if metadata.is_dir() {
+        let mut permissions = metadata.permissions();
+        permissions.set_mode(permissions.mode() | 0o700);
+        fs::set_permissions(path, permissions)?;
+        for entry in fs::read_dir(path)? {
+            make_writable(&entry?.path())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    /// Build a small source tree with all kinds of NAR nodes
+    fn build_tar() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut add = |path: &str, mode: u32, entry_type: tar::EntryType, data: &[u8]| {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(entry_type);
+            header.set_mode(mode);
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder.append_data(&mut header, path, data).unwrap();
+        };
+        add("source-1.0/", 0o755, tar::EntryType::Directory, b"");
+        add(
+            "source-1.0/README",
+            0o644,
+            tar::EntryType::Regular,
+            b"Hello, world!\n",
+        );
+        add("source-1.0/bin/", 0o755, tar::EntryType::Directory, b"");
+        add(
+            "source-1.0/bin/run",
+            0o755,
+            tar::EntryType::Regular,
+            b"#!/bin/sh\necho hi\n",
+        );
+        add("source-1.0/empty/", 0o555, tar::EntryType::Directory, b"");
+
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "source-1.0/link", "bin/run")
+            .unwrap();
+
+        builder.into_inner().unwrap()
+    }
+
+    fn expected_hash() -> NixHash {
+        NixHash::from_sri("sha256-CAj1ubhDfQ5awcCPrjPt6PuKP5KPXRdKtOZCa6g4N2M=").unwrap()
+    }
+
+    fn prefetch_bytes(data: &[u8], unpack: bool) -> Result<NixHash> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("archive");
+        fs::write(&path, data).unwrap();
+        let url = Url::from_file_path(&path).unwrap();
+
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(native_prefetch_url(
+                &Config::builder().build()?,
+                url.as_str(),
+                unpack,
+            ))
+    }
+
+    #[test]
+    fn test_tar() {
+        assert_eq!(prefetch_bytes(&build_tar(), true).unwrap(), expected_hash());
+    }
+
+    #[test]
+    fn test_compressed_tar() {
+        let tar = build_tar();
+
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(&tar).unwrap();
+        assert_eq!(
+            prefetch_bytes(&gz.finish().unwrap(), true).unwrap(),
+            expected_hash()
+        );
+
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(&tar).unwrap();
+        assert_eq!(
+            prefetch_bytes(&xz.finish().unwrap(), true).unwrap(),
+            expected_hash()
+        );
+
+        let mut bz2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        bz2.write_all(&tar).unwrap();
+        assert_eq!(
+            prefetch_bytes(&bz2.finish().unwrap(), true).unwrap(),
+            expected_hash()
+        );
+    }
+
+    #[test]
+    fn test_zip() {
+        let mut zip = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+        zip.add_directory("source-1.0/", options.unix_permissions(0o755))
+            .unwrap();
+        zip.start_file("source-1.0/README", options.unix_permissions(0o644))
+            .unwrap();
+        zip.write_all(b"Hello, world!\n").unwrap();
+        zip.add_directory("source-1.0/bin/", options.unix_permissions(0o755))
+            .unwrap();
+        zip.start_file("source-1.0/bin/run", options.unix_permissions(0o755))
+            .unwrap();
+        zip.write_all(b"#!/bin/sh\necho hi\n").unwrap();
+        zip.add_directory("source-1.0/empty/", options.unix_permissions(0o555))
+            .unwrap();
+        zip.add_symlink("source-1.0/link", "bin/run", options)
+            .unwrap();
+        let zip = zip.finish().unwrap().into_inner();
+
+        assert_eq!(prefetch_bytes(&zip, true).unwrap(), expected_hash());
+    }
+
+    #[test]
+    fn test_flat() {
+        /* `echo -n "Hello, world!" | sha256sum` */
+        assert_eq!(
+            prefetch_bytes(b"Hello, world!", false).unwrap(),
+            NixHash::from_sri("sha256-MV9b23bQeMQ7isAGTkoBZGErH853yGk0W/yUx1iU7dM=").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_single_file() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(14);
+        builder
+            .append_data(&mut header, "README", &b"Hello, world!\n"[..])
+            .unwrap();
+
+        assert_eq!(
+            prefetch_bytes(&builder.into_inner().unwrap(), true).unwrap(),
+            NixHash::from_sri("sha256-WEk3mouMv8QTHQwPia0CDqJw2F8WHiV7fVITW1gzfs4=").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_multiple_top_level_entries() {
+        let mut builder = tar::Builder::new(Vec::new());
+        for name in ["a", "b"] {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(0o644);
+            header.set_size(0);
+            builder.append_data(&mut header, name, io::empty()).unwrap();
+        }
+
+        let error = prefetch_bytes(&builder.into_inner().unwrap(), true).unwrap_err();
+        assert!(format!("{:#}", error).contains("exactly one top-level entry"));
+    }
+
+    #[test]
+    fn test_backend_from_str() {
+        assert_eq!(
+            "native".parse::<PrefetchBackend>().unwrap(),
+            PrefetchBackend::Native
+        );
+        assert_eq!(
+            "nix-prefetch-url".parse::<PrefetchBackend>().unwrap(),
+            PrefetchBackend::NixPrefetchUrl
+        );
+        assert!("curl".parse::<PrefetchBackend>().is_err());
+    }
+}
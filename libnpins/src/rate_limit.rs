@@ -0,0 +1,148 @@
+//! Rate limits of the forge APIs
+//!
+//! GitHub, GitLab and Forgejo limit the number of API requests per client, anonymous ones severely
+//! (GitHub allows 60 per hour). They report the quota in response headers, and how long to wait
+//! once it is exceeded. Requests are retried if the limit resets soon, and otherwise fail with an
+//! error reporting the quota, see [`crate::retry`].
+
+use reqwest::StatusCode;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use std::time::{Duration, SystemTime};
+
+/// The quota of a client, as reported by the server
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Requests allowed in the current window
+    pub limit: Option<u64>,
+    /// Requests left in the current window
+    pub remaining: Option<u64>,
+    /// When the window ends and the quota is reset
+    pub reset: Option<SystemTime>,
+    /// How long to wait before retrying, from the `Retry-After` header
+    pub retry_after: Option<Duration>,
+}
+
+impl RateLimit {
+    /// Read the `X-RateLimit-*` (GitHub, Forgejo), `RateLimit-*` (GitLab) and `Retry-After` headers
+    pub fn from_headers(headers: &HeaderMap, now: SystemTime) -> Self {
+        let number = |names: &[&str]| {
+            names.iter().find_map(|name| {
+                headers
+                    .get(*name)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.trim().parse::<u64>().ok())
+            })
+        };
+        Self {
+            limit: number(&["x-ratelimit-limit", "ratelimit-limit"]),
+            remaining: number(&["x-ratelimit-remaining", "ratelimit-remaining"]),
+            /* Usually a Unix timestamp, but some servers send the number of seconds until then */
+            reset: number(&["x-ratelimit-reset", "ratelimit-reset"]).map(|reset| {
+                if reset > 1_000_000_000 {
+                    SystemTime::UNIX_EPOCH + Duration::from_secs(reset)
+                } else {
+                    now + Duration::from_secs(reset)
+                }
+            }),
+            /* The HTTP date format is not supported, none of the forges use it */
+            retry_after: number(&[RETRY_AFTER.as_str()]).map(Duration::from_secs),
+        }
+    }
+
+    /// Whether a response with this status was rejected because of the rate limit
+    ///
+    /// GitHub also answers with `403 Forbidden` instead of `429 Too Many Requests`.
+    pub fn exceeded(&self, status: StatusCode) -> bool {
+        status == StatusCode::TOO_MANY_REQUESTS
+            || (status == StatusCode::FORBIDDEN
+                && (self.remaining == Some(0) || self.retry_after.is_some()))
+    }
+
+    /// How long the server asked to wait before retrying, if it did
+    pub fn wait(&self, now: SystemTime) -> Option<Duration> {
+        self.retry_after.or_else(|| {
+            self.reset
+                .map(|reset| reset.duration_since(now).unwrap_or_default())
+        })
+    }
+
+    /// Whether the server reported anything about the quota
+    pub fn is_known(&self) -> bool {
+        self.remaining.is_some() || self.reset.is_some()
+    }
+
+    /// A human readable summary of the quota, e.g. `0 of 60 requests left, resets in 50 minutes`
+    pub fn describe(&self, now: SystemTime) -> String {
+        let mut description = match (self.remaining, self.limit) {
+            (Some(remaining), Some(limit)) => format!("{remaining} of {limit} requests left"),
+            (Some(remaining), None) => format!("{remaining} requests left"),
+            (None, _) => "quota unknown".into(),
+        };
+        if let Some(reset) = self.reset {
+            let seconds = reset.duration_since(now).unwrap_or_default().as_secs();
+            if seconds >= 120 {
+                description += &format!(", resets in {} minutes", seconds / 60);
+            } else {
+                description += &format!(", resets in {seconds} seconds");
+            }
+        }
+        description
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn headers(headers: &[(&'static str, &'static str)]) -> HeaderMap {
+        headers
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        /* GitHub */
+        let github = RateLimit::from_headers(
+            &headers(&[
+                ("x-ratelimit-limit", "60"),
+                ("x-ratelimit-remaining", "0"),
+                ("x-ratelimit-reset", "1700003000"),
+            ]),
+            now,
+        );
+        assert!(github.exceeded(StatusCode::FORBIDDEN));
+        assert_eq!(github.wait(now), Some(Duration::from_secs(3000)));
+        assert_eq!(
+            github.describe(now),
+            "0 of 60 requests left, resets in 50 minutes"
+        );
+
+        /* GitLab */
+        let gitlab = RateLimit::from_headers(
+            &headers(&[
+                ("RateLimit-Limit", "600"),
+                ("RateLimit-Remaining", "0"),
+                ("RateLimit-Reset", "1700000030"),
+                ("Retry-After", "30"),
+            ]),
+            now,
+        );
+        assert!(gitlab.exceeded(StatusCode::TOO_MANY_REQUESTS));
+        assert_eq!(gitlab.wait(now), Some(Duration::from_secs(30)));
+        assert_eq!(
+            gitlab.describe(now),
+            "0 of 600 requests left, resets in 30 seconds"
+        );
+
+        /* Neither */
+        let none = RateLimit::from_headers(&HeaderMap::new(), now);
+        assert!(!none.exceeded(StatusCode::FORBIDDEN));
+        assert!(none.exceeded(StatusCode::TOO_MANY_REQUESTS));
+        assert_eq!(none.wait(now), None);
+    }
+}
@@ -0,0 +1,485 @@
+//! Retries and timeouts of network operations
+//!
+//! Every HTTP request and every spawned process which accesses the network goes through this
+//! module. Each attempt is bounded by the [`Timeouts`] of its [`Operation`], and failed attempts
+//! are retried according to the [`RetryPolicy`] if the failure is transient: timeouts, connection
+//! errors, server errors and rate limits. Everything else, like a missing file or a rejected
+//! token, fails right away.
+
+use anyhow::{Context, Result};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use std::hash::{BuildHasher, Hasher};
+use std::process::Output;
+use std::time::{Duration, SystemTime};
+use tokio::process::Command;
+
+use crate::Config;
+use crate::rate_limit::RateLimit;
+
+/// How often and how fast to retry failed operations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Number of attempts, including the first one. `1` disables retries
+    pub attempts: u32,
+    /// Wait before the first retry, doubled for every further one
+    pub initial_backoff: Duration,
+    /// Longest wait between two attempts
+    ///
+    /// Rate limits which reset later than this are not waited for.
+    pub max_backoff: Duration,
+    /// Wait a random fraction between half and all of the backoff, so that parallel operations
+    /// don't retry in lockstep
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retry
+    pub fn none() -> Self {
+        Self {
+            attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// The wait before the given retry, counting from zero
+    fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff);
+        if self.jitter {
+            /* A random number without pulling in a dependency, the hasher is seeded randomly */
+            let random = std::collections::hash_map::RandomState::new()
+                .build_hasher()
+                .finish();
+            backoff.mul_f64(0.5 + (random % 1000) as f64 / 2000.0)
+        } else {
+            backoff
+        }
+    }
+
+    /// Start counting the attempts of an operation, see [`Attempts`]
+    pub(crate) fn attempts(&self, what: impl Into<String>) -> Attempts<'_> {
+        Attempts {
+            policy: self,
+            what: what.into(),
+            retry: 0,
+        }
+    }
+}
+
+/// The kinds of operations, which have separate timeouts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Requests to APIs, package indices and other metadata
+    Request,
+    /// Listing the refs of git repositories
+    Git,
+    /// Downloading and hashing sources, natively or with the `nix-prefetch-*` tools
+    Prefetch,
+}
+
+/// Timeouts of single attempts per kind of operation, `None` waits forever
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    pub request: Option<Duration>,
+    pub git: Option<Duration>,
+    pub prefetch: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            request: Some(Duration::from_secs(2 * 60)),
+            git: Some(Duration::from_secs(5 * 60)),
+            prefetch: Some(Duration::from_secs(60 * 60)),
+        }
+    }
+}
+
+impl Timeouts {
+    pub fn get(&self, operation: Operation) -> Option<Duration> {
+        match operation {
+            Operation::Request => self.request,
+            Operation::Git => self.git,
+            Operation::Prefetch => self.prefetch,
+        }
+    }
+}
+
+/// A failure which is worth retrying, optionally after a wait requested by the server
+#[derive(Debug)]
+pub(crate) struct Transient {
+    message: String,
+    wait: Option<Duration>,
+}
+
+impl Transient {
+    pub(crate) fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            wait: None,
+        }
+    }
+}
+
+impl std::fmt::Display for Transient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Transient {}
+
+/// Whether a failure is transient, and if so how long the server asked to wait
+fn classify(err: &anyhow::Error) -> Option<Option<Duration>> {
+    err.chain().find_map(|cause| {
+        if let Some(transient) = cause.downcast_ref::<Transient>() {
+            return Some(transient.wait);
+        }
+        if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+            let retryable = match err.status() {
+                Some(status) => {
+                    status.is_server_error()
+                        || status == StatusCode::REQUEST_TIMEOUT
+                        || status == StatusCode::TOO_MANY_REQUESTS
+                },
+                None => err.is_timeout() || err.is_connect() || err.is_request() || err.is_body(),
+            };
+            return retryable.then_some(None);
+        }
+        if let Some(err) = cause.downcast_ref::<std::io::Error>() {
+            use std::io::ErrorKind::*;
+            return matches!(
+                err.kind(),
+                TimedOut
+                    | ConnectionReset
+                    | ConnectionAborted
+                    | ConnectionRefused
+                    | BrokenPipe
+                    | UnexpectedEof
+                    | Interrupted
+            )
+            .then_some(None);
+        }
+        None
+    })
+}
+
+/// Messages of git, curl and Nix about failures which are worth retrying
+const TRANSIENT_MESSAGES: &[&str] = &[
+    "could not resolve host",
+    "couldn't resolve host",
+    "temporary failure in name resolution",
+    "failed to connect",
+    "connection refused",
+    "connection reset",
+    "connection timed out",
+    "operation timed out",
+    "timeout was reached",
+    "the remote end hung up unexpectedly",
+    "early eof",
+    "rpc failed",
+    "returned error: 5",
+    "returned error: 429",
+    "http error 5",
+    "http error 429",
+];
+
+fn is_transient_message(stderr: &[u8]) -> bool {
+    let stderr = String::from_utf8_lossy(stderr).to_lowercase();
+    TRANSIENT_MESSAGES
+        .iter()
+        .any(|message| stderr.contains(message))
+}
+
+/// Counts the attempts of an operation
+///
+/// Failed attempts are passed to [`failed`](Self::failed), which waits before the next one or
+/// gives up:
+///
+/// ```ignore
+/// let mut attempts = config.retry_policy().attempts("Fetching foo");
+/// loop {
+///     match attempt().await {
+///         Ok(result) => return Ok(result),
+///         Err(err) => attempts.failed(err).await?,
+///     }
+/// }
+/// ```
+pub(crate) struct Attempts<'a> {
+    policy: &'a RetryPolicy,
+    what: String,
+    retry: u32,
+}
+
+impl Attempts<'_> {
+    /// Wait for the next attempt if the failure is transient and attempts are left, otherwise
+    /// return the error
+    pub(crate) async fn failed(&mut self, err: anyhow::Error) -> Result<()> {
+        let Some(wait) = classify(&err) else {
+            return Err(err);
+        };
+        if self.retry + 1 >= self.policy.attempts {
+            return Err(err.context(format!(
+                "{} failed after {} attempts",
+                self.what, self.policy.attempts
+            )));
+        }
+        let wait = wait.unwrap_or_else(|| self.policy.backoff(self.retry));
+        self.retry += 1;
+        log::warn!(
+            "{} failed, retrying in {:.1}s: {err:#}",
+            self.what,
+            wait.as_secs_f32()
+        );
+        tokio::time::sleep(wait).await;
+        Ok(())
+    }
+}
+
+/// Send a request and check its status, retrying transient failures
+///
+/// Errors report the remaining quota if the server sent it.
+pub(crate) async fn send(
+    config: &Config,
+    operation: Operation,
+    request: RequestBuilder,
+) -> Result<Response> {
+    send_with(config, operation, request, true).await
+}
+
+/// Like [`send`], but only transient error statuses are failures, others are returned as is
+pub(crate) async fn send_unchecked(
+    config: &Config,
+    operation: Operation,
+    request: RequestBuilder,
+) -> Result<Response> {
+    send_with(config, operation, request, false).await
+}
+
+async fn send_with(
+    config: &Config,
+    operation: Operation,
+    request: RequestBuilder,
+    check_status: bool,
+) -> Result<Response> {
+    let what = match request.try_clone().and_then(|request| request.build().ok()) {
+        Some(request) => format!("{} {}", request.method(), request.url()),
+        None => "Request".into(),
+    };
+    let mut attempts = config.retry_policy().attempts(what);
+    loop {
+        let request = request
+            .try_clone()
+            .context("Streaming requests can't be retried")?;
+        match attempt(config, operation, request, check_status).await {
+            Ok(response) => return Ok(response),
+            Err(err) => attempts.failed(err).await?,
+        }
+    }
+}
+
+/// A single attempt of [`send`], for operations which retry more than just the request
+pub(crate) async fn attempt(
+    config: &Config,
+    operation: Operation,
+    mut request: RequestBuilder,
+    check_status: bool,
+) -> Result<Response> {
+    if let Some(timeout) = config.timeouts().get(operation) {
+        request = request.timeout(timeout);
+    }
+    let response = request.send().await?;
+    let now = SystemTime::now();
+    let rate_limit = RateLimit::from_headers(response.headers(), now);
+    let host = response.url().host_str().unwrap_or_default().to_owned();
+
+    if rate_limit.exceeded(response.status()) {
+        let wait = rate_limit.wait(now);
+        if wait.is_some_and(|wait| wait > config.retry_policy().max_backoff) {
+            anyhow::bail!(
+                "Rate limit of {host} exceeded ({}). Authenticated requests have a higher limit, see the README on how to configure a token",
+                rate_limit.describe(now)
+            );
+        }
+        return Err(Transient {
+            message: format!(
+                "Rate limit of {host} exceeded ({})",
+                rate_limit.describe(now)
+            ),
+            wait,
+        }
+        .into());
+    }
+
+    let status = response.status();
+    let transient = status.is_server_error()
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS;
+    match response.error_for_status_ref() {
+        Ok(_) => Ok(response),
+        Err(_) if !check_status && !transient => Ok(response),
+        Err(err) if rate_limit.is_known() => Err(err)
+            .with_context(|| format!("Request to {host} failed ({})", rate_limit.describe(now))),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Run a command to completion, retrying transient failures
+///
+/// Unsuccessful exits are returned as is, unless the error messages suggest a transient failure.
+/// Note that the command is not logged here, as it may contain credentials in its environment.
+pub(crate) async fn output(
+    config: &Config,
+    operation: Operation,
+    command: &mut Command,
+) -> Result<Output> {
+    let program = command
+        .as_std()
+        .get_program()
+        .to_string_lossy()
+        .into_owned();
+    /* Kill the process when it times out */
+    command.kill_on_drop(true);
+    let timeout = config.timeouts().get(operation);
+
+    let mut attempts = config.retry_policy().attempts(&program);
+    loop {
+        let result = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, command.output())
+                .await
+                .map_err(|_| Transient::new(format!("{program} timed out after {timeout:?}"))),
+            None => Ok(command.output().await),
+        };
+        let result = result.map_err(anyhow::Error::from).and_then(|output| {
+            let output = output.with_context(|| format!("Failed to spawn {program}"))?;
+            if !output.status.success() && is_transient_message(&output.stderr) {
+                return Err(Transient::new(format!(
+                    "{program} failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ))
+                .into());
+            }
+            Ok(output)
+        });
+        match result {
+            Ok(output) => return Ok(output),
+            Err(err) => attempts.failed(err).await?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(3),
+            jitter: false,
+        };
+        assert_eq!(policy.backoff(0), Duration::from_secs(1));
+        assert_eq!(policy.backoff(1), Duration::from_secs(2));
+        assert_eq!(policy.backoff(2), Duration::from_secs(3));
+        assert_eq!(policy.backoff(40), Duration::from_secs(3));
+
+        let policy = RetryPolicy {
+            jitter: true,
+            ..policy
+        };
+        for _ in 0..100 {
+            let backoff = policy.backoff(1);
+            assert!(backoff >= Duration::from_secs(1) && backoff <= Duration::from_secs(2));
+        }
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify(&Transient::new("flaky").into()), Some(None));
+        assert_eq!(
+            classify(&anyhow::Error::from(Transient::new("flaky")).context("while updating")),
+            Some(None)
+        );
+        assert_eq!(
+            classify(&std::io::Error::from(std::io::ErrorKind::ConnectionReset).into()),
+            Some(None)
+        );
+        assert_eq!(
+            classify(&std::io::Error::from(std::io::ErrorKind::NotFound).into()),
+            None
+        );
+        assert_eq!(classify(&anyhow::anyhow!("invalid hash")), None);
+
+        assert!(is_transient_message(
+            b"fatal: unable to access 'https://example.org/': Could not resolve host: example.org"
+        ));
+        assert!(is_transient_message(
+            b"error: unable to download 'https://example.org/': HTTP error 503"
+        ));
+        assert!(!is_transient_message(
+            b"fatal: repository 'https://example.org/' not found"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_output() {
+        let config = Config::builder()
+            .retry_policy(RetryPolicy {
+                initial_backoff: Duration::ZERO,
+                ..RetryPolicy::default()
+            })
+            .timeouts(Timeouts {
+                git: Some(Duration::from_millis(200)),
+                ..Timeouts::default()
+            })
+            .build()
+            .unwrap();
+
+        /* Fails twice with a transient error, then succeeds */
+        let dir = tempfile::tempdir().unwrap();
+        let counter = dir.path().join("counter");
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(format!(
+            r#"echo >> {0}; if [ $(wc -l < {0}) -lt 3 ]; then echo "Connection reset by peer" >&2; exit 1; fi; echo done"#,
+            counter.display()
+        ));
+        let output = super::output(&config, Operation::Git, &mut command)
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"done\n");
+
+        /* Permanent failures are returned */
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("echo not found >&2; exit 1");
+        let output = super::output(&config, Operation::Git, &mut command)
+            .await
+            .unwrap();
+        assert!(!output.status.success());
+
+        /* Timeouts are retried, and eventually fail */
+        let mut command = Command::new("sleep");
+        command.arg("10");
+        let err = super::output(&config, Operation::Git, &mut command)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "sleep failed after 3 attempts: sleep timed out after 200ms"
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! Limiting the number of concurrent operations, in total and per host
+//!
+//! Updating many pins at once would otherwise send all requests to whichever host most pins
+//! happen to be on, while the others sit idle. Some servers also ban clients making too many
+//! parallel requests. The [`Scheduler`] groups operations by the host they talk to, see
+//! [`Updatable::host`](crate::Updatable::host), and only lets a limited number of them run for
+//! each host, on top of the total limit.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+
+/// Limits the concurrency of operations, in total and per host
+#[derive(Debug)]
+pub struct Scheduler {
+    total: Semaphore,
+    per_host: usize,
+    host_limits: HashMap<String, usize>,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl Scheduler {
+    /// Run at most `total` operations at once, and at most `per_host` of them for the same host
+    ///
+    /// Limits of zero are treated as one.
+    pub fn new(total: usize, per_host: usize) -> Self {
+        Self {
+            total: Semaphore::new(total.max(1)),
+            per_host: per_host.max(1),
+            host_limits: HashMap::new(),
+            hosts: Mutex::default(),
+        }
+    }
+
+    /// Use a different limit for the given host, e.g. for a server that bans parallel clones
+    pub fn host_limit(mut self, host: &str, limit: usize) -> Self {
+        self.host_limits
+            .insert(host.to_ascii_lowercase(), limit.max(1));
+        self
+    }
+
+    /// The number of operations that may run at once for the given host
+    pub fn limit(&self, host: &str) -> usize {
+        self.host_limits
+            .get(&host.to_ascii_lowercase())
+            .copied()
+            .unwrap_or(self.per_host)
+    }
+
+    fn semaphore(&self, host: &str) -> Arc<Semaphore> {
+        let host = host.to_ascii_lowercase();
+        let limit = self.limit(&host);
+        self.hosts
+            .lock()
+            .unwrap()
+            .entry(host)
+            .or_insert_with(|| Arc::new(Semaphore::new(limit)))
+            .clone()
+    }
+
+    /// Run `operation` once both a slot for its host and a global one are free
+    ///
+    /// Operations without a host are only subject to the total limit. The slot of the host is
+    /// acquired first, so that operations waiting for a busy host don't block the others.
+    pub fn run<F: Future>(
+        &self,
+        host: Option<&str>,
+        operation: F,
+    ) -> impl Future<Output = F::Output> + use<'_, F> {
+        let host = host.map(|host| self.semaphore(host));
+        async move {
+            let _host_permit = match &host {
+                Some(semaphore) => Some(
+                    semaphore
+                        .acquire()
+                        .await
+                        .expect("the semaphores are never closed"),
+                ),
+                None => None,
+            };
+            let _permit = self
+                .total
+                .acquire()
+                .await
+                .expect("the semaphores are never closed");
+            operation.await
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures_util::future;
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct Running {
+        current: HashMap<Option<&'static str>, usize>,
+        max: HashMap<Option<&'static str>, usize>,
+        total: usize,
+        max_total: usize,
+    }
+
+    #[tokio::test]
+    async fn test_scheduler() {
+        let scheduler = Scheduler::new(4, 2).host_limit("GitLab.example.org", 1);
+        assert_eq!(scheduler.limit("gitlab.example.org"), 1);
+        assert_eq!(scheduler.limit("github.com"), 2);
+
+        let running = RefCell::new(Running::default());
+        let operation = |host: Option<&'static str>| {
+            let running = &running;
+            scheduler.run(host, async move {
+                {
+                    let mut running = running.borrow_mut();
+                    let current = running.current.entry(host).or_default();
+                    *current += 1;
+                    let current = *current;
+                    let max = running.max.entry(host).or_default();
+                    *max = current.max(*max);
+                    running.total += 1;
+                    running.max_total = running.total.max(running.max_total);
+                }
+                for _ in 0..5 {
+                    tokio::task::yield_now().await;
+                }
+                let mut running = running.borrow_mut();
+                *running.current.get_mut(&host).unwrap() -= 1;
+                running.total -= 1;
+            })
+        };
+
+        let hosts = [
+            Some("github.com"),
+            Some("gitlab.example.org"),
+            Some("pypi.org"),
+            None,
+        ];
+        future::join_all(hosts.iter().cycle().take(40).map(|&host| operation(host))).await;
+
+        let running = running.into_inner();
+        assert_eq!(running.max[&Some("github.com")], 2);
+        assert_eq!(running.max[&Some("gitlab.example.org")], 1);
+        assert_eq!(running.max[&Some("pypi.org")], 2);
+        assert_eq!(running.max_total, 4);
+    }
+}
@@ -0,0 +1,210 @@
+//! The optional, hand-written pin specification (`npins.toml`)
+//!
+//! The specification only contains the user's intent, i.e. the input of every pin. It is resolved
+//! into the lock file (`sources.json`) with [`Spec::lock`], similar to how `cargo generate-lockfile`
+//! works. Each pin uses the same fields as in the lock file, minus the resolved ones:
+//!
+//! ```toml
+//! [pins.nixpkgs]
+//! type = "Channel"
+//! name = "nixos-unstable"
+//!
+//! [pins.home-manager]
+//! type = "Git"
+//! repository = { type = "GitHub", owner = "nix-community", repo = "home-manager" }
+//! branch = "master"
+//! frozen = true
+//! ```
+//!
+//! A version (e.g. `revision` for git pins) may be given to lock a pin to it.
+
+use anyhow::Context;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use crate::{NixPins, Pin};
+
+/// File name of the specification, it lives next to `sources.json`
+pub const SPEC_FILE: &str = "npins.toml";
+
+#[derive(Debug, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Spec {
+    #[serde(default)]
+    pub pins: BTreeMap<String, Pin>,
+}
+
+impl Spec {
+    /// Parse and validate a specification
+    pub fn from_toml(spec: &str) -> anyhow::Result<Self> {
+        let spec: Self = toml::from_str(spec).context("Failed to parse the pin specification")?;
+        for (name, pin) in &spec.pins {
+            anyhow::ensure!(
+                !pin.has_hashes(),
+                "Pin {name} must not specify hashes, they are only part of the lock file"
+            );
+        }
+        Ok(spec)
+    }
+
+    /// Compute the new lock file from the old one
+    ///
+    /// Pins whose specification did not change are taken over from `old`, only their frozen state
+    /// is adapted. All other pins are taken from the specification and need to be resolved by
+    /// the caller, they are the ones without hashes. Pins that are not specified are dropped.
+    pub fn lock(&self, old: &NixPins) -> NixPins {
+        let pins = self
+            .pins
+            .iter()
+            .map(|(name, spec)| {
+                let pin = match old.pins.get(name) {
+                    Some(old) if old.has_hashes() && old.matches_spec(spec) => {
+                        let mut pin = old.clone();
+                        if spec.is_frozen() {
+                            pin.freeze();
+                        } else {
+                            pin.unfreeze();
+                        }
+                        pin
+                    },
+                    _ => spec.clone(),
+                };
+                (name.clone(), pin)
+            })
+            .collect();
+        NixPins { pins }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SPEC: &str = r#"
+        [pins.nixpkgs]
+        type = "Channel"
+        name = "nixos-unstable"
+
+        [pins.home-manager]
+        type = "Git"
+        repository = { type = "GitHub", owner = "nix-community", repo = "home-manager" }
+        branch = "release-25.05"
+        frozen = true
+
+        [pins.npins]
+        type = "Git"
+        repository = { type = "GitHub", owner = "andir", repo = "npins" }
+        branch = "master"
+        revision = "8d8c1fa5b412c223ffa47410867813290cdedfef"
+    "#;
+
+    fn git_pin(repo: &str, branch: &str, revision: &str) -> Pin {
+        serde_json::from_value(serde_json::json!({
+            "type": "Git",
+            "repository": {
+                "type": "GitHub",
+                "owner": repo.split_once('/').unwrap().0,
+                "repo": repo.split_once('/').unwrap().1,
+            },
+            "branch": branch,
+            "revision": revision,
+            "url": format!("https://github.com/{repo}/archive/{revision}.tar.gz"),
+            "hash": "sha256-J0dZU4atgcfo4QvM9D92uQ0Oe1eLTxBVXjJzdEMQpD0=",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let spec = Spec::from_toml(SPEC).unwrap();
+        assert_eq!(
+            spec.pins.keys().collect::<Vec<_>>(),
+            ["home-manager", "nixpkgs", "npins"]
+        );
+        assert!(spec.pins["home-manager"].is_frozen());
+        assert!(!spec.pins["nixpkgs"].has_version());
+        assert!(spec.pins["npins"].has_version());
+
+        assert!(Spec::from_toml("[pin.nixpkgs]").is_err());
+        assert!(
+            Spec::from_toml(
+                r#"
+                [pins.nixpkgs]
+                type = "Channel"
+                name = "nixos-unstable"
+                url = "https://releases.nixos.org/nixos/unstable/nixos-25.11pre1/nixexprs.tar.xz"
+                hash = "sha256-J0dZU4atgcfo4QvM9D92uQ0Oe1eLTxBVXjJzdEMQpD0="
+                "#
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_lock() {
+        let spec = Spec::from_toml(SPEC).unwrap();
+        let old = NixPins {
+            pins: [
+                (
+                    "home-manager".to_owned(),
+                    git_pin(
+                        "nix-community/home-manager",
+                        "release-25.05",
+                        "4e0eb042b67d863b1b34b3f64d52ceb9cd926735",
+                    ),
+                ),
+                (
+                    "npins".to_owned(),
+                    git_pin(
+                        "andir/npins",
+                        "master",
+                        "4e0eb042b67d863b1b34b3f64d52ceb9cd926735",
+                    ),
+                ),
+                (
+                    "niv".to_owned(),
+                    git_pin(
+                        "nmattia/niv",
+                        "master",
+                        "4e0eb042b67d863b1b34b3f64d52ceb9cd926735",
+                    ),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        };
+
+        let new = spec.lock(&old);
+        assert_eq!(
+            new.pins.keys().collect::<Vec<_>>(),
+            ["home-manager", "nixpkgs", "npins"]
+        );
+        /* Unchanged, only the frozen state is taken over */
+        let mut home_manager = old.pins["home-manager"].clone();
+        home_manager.freeze();
+        assert_eq!(new.pins["home-manager"], home_manager);
+        /* New */
+        assert_eq!(new.pins["nixpkgs"], spec.pins["nixpkgs"]);
+        /* The version in the specification differs */
+        assert_eq!(new.pins["npins"], spec.pins["npins"]);
+        assert!(!new.pins["npins"].has_hashes());
+
+        /* Locking again does not change anything */
+        let mut resolved = new.clone();
+        resolved
+            .pins
+            .insert("nixpkgs".to_owned(), old.pins["home-manager"].clone());
+        resolved.pins.insert(
+            "npins".to_owned(),
+            git_pin(
+                "andir/npins",
+                "master",
+                "8d8c1fa5b412c223ffa47410867813290cdedfef",
+            ),
+        );
+        let relocked = spec.lock(&resolved);
+        assert_eq!(relocked.pins["npins"], resolved.pins["npins"]);
+        /* The pin type changed */
+        assert_eq!(relocked.pins["nixpkgs"], spec.pins["nixpkgs"]);
+    }
+}
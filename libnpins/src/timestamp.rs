@@ -0,0 +1,211 @@
+//! Points in time, as reported by the forges and registries
+//!
+//! This only covers what npins needs: parsing RFC 3339 timestamps, printing them in UTC and
+//! computing ages. The calendar arithmetic follows <https://howardhinnant.github.io/date_algorithms.html>.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::time::{Duration, SystemTime};
+
+/// Seconds since the Unix epoch, serialized as RFC 3339 in UTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(i64);
+
+impl Timestamp {
+    pub fn now() -> Self {
+        let seconds = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(err) => -(err.duration().as_secs() as i64),
+        };
+        Self(seconds)
+    }
+
+    pub fn from_unix(seconds: i64) -> Self {
+        Self(seconds)
+    }
+
+    pub fn unix(&self) -> i64 {
+        self.0
+    }
+
+    /// Time elapsed since this timestamp, zero if it lies in the future
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(Self::now().0.saturating_sub(self.0).max(0) as u64)
+    }
+}
+
+impl std::ops::Sub<Duration> for Timestamp {
+    type Output = Timestamp;
+
+    fn sub(self, duration: Duration) -> Timestamp {
+        Timestamp(self.0.saturating_sub(duration.as_secs() as i64))
+    }
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Inverse of [`days_from_civil`]
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+impl std::fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (year, month, day) = civil_from_days(self.0.div_euclid(86400));
+        let seconds = self.0.rem_euclid(86400);
+        write!(
+            f,
+            "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    }
+}
+
+impl std::str::FromStr for Timestamp {
+    type Err = anyhow::Error;
+
+    /// Parse an RFC 3339 timestamp like `2024-05-01T12:00:00.123+02:00`
+    ///
+    /// Fractional seconds are ignored.
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        let parse = || -> Option<Timestamp> {
+            let number = |digits: &str| -> Option<i64> {
+                digits
+                    .bytes()
+                    .all(|c| c.is_ascii_digit())
+                    .then(|| digits.parse().ok())?
+            };
+            let (date, time) = value.split_once(['T', 't', ' '])?;
+            let mut date = date.splitn(3, '-');
+            let (year, month, day) = (
+                number(date.next()?)?,
+                number(date.next()?)?,
+                number(date.next()?)?,
+            );
+
+            let (time, offset) = if let Some(time) = time.strip_suffix(['Z', 'z']) {
+                (time, 0)
+            } else {
+                let split = time.rfind(['+', '-'])?;
+                let (time, offset) = time.split_at(split);
+                let (hours, minutes) = offset[1..].split_once(':')?;
+                let seconds = number(hours)? * 3600 + number(minutes)? * 60;
+                (
+                    time,
+                    if offset.starts_with('-') {
+                        -seconds
+                    } else {
+                        seconds
+                    },
+                )
+            };
+            let time = time.split_once('.').map_or(time, |(time, _)| time);
+            let mut time = time.splitn(3, ':');
+            let (hour, minute, second) = (
+                number(time.next()?)?,
+                number(time.next()?)?,
+                number(time.next()?)?,
+            );
+
+            if !(1..=12).contains(&month)
+                || !(1..=31).contains(&day)
+                || hour > 23
+                || minute > 59
+                || second > 60
+            {
+                return None;
+            }
+            let days = days_from_civil(year, month, day);
+            Some(Timestamp(
+                days * 86400 + hour * 3600 + minute * 60 + second - offset,
+            ))
+        };
+        parse().ok_or_else(|| anyhow::format_err!("'{value}' is not a valid RFC 3339 timestamp"))
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let parse = |value: &str| value.parse::<Timestamp>().unwrap().unix();
+        assert_eq!(parse("1970-01-01T00:00:00Z"), 0);
+        assert_eq!(parse("2011-04-14T16:00:49Z"), 1302796849);
+        /* GitLab */
+        assert_eq!(parse("2011-04-14T18:00:49.000+02:00"), 1302796849);
+        assert_eq!(parse("2011-04-14T13:30:49-02:30"), 1302796849);
+        /* PyPI */
+        assert_eq!(parse("2011-04-14T16:00:49.123456Z"), 1302796849);
+        assert_eq!(parse("2000-02-29 00:00:00z"), 951782400);
+
+        for invalid in [
+            "",
+            "2011-04-14",
+            "2011-04-14T16:00:49",
+            "2011-13-14T16:00:49Z",
+            "2011-04-14T16:00:49+0200",
+            "yesterday",
+        ] {
+            assert!(invalid.parse::<Timestamp>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Timestamp::from_unix(0).to_string(), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            Timestamp::from_unix(951782400 + 86399).to_string(),
+            "2000-02-29T23:59:59Z"
+        );
+        assert_eq!(Timestamp::from_unix(-1).to_string(), "1969-12-31T23:59:59Z");
+        for value in ["2011-04-14T16:00:49Z", "2024-12-31T00:00:00Z"] {
+            assert_eq!(value.parse::<Timestamp>().unwrap().to_string(), value);
+        }
+    }
+
+    #[test]
+    fn test_age() {
+        let day = Duration::from_secs(86400);
+        let yesterday = Timestamp::now() - day;
+        assert!(yesterday.age() >= day && yesterday.age() < day * 2);
+        assert_eq!(Timestamp::from_unix(i64::MAX).age(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,574 @@
+//! Orderings of release versions
+//!
+//! Release tags are ordered leniently as SemVer by default. Projects with other conventions can
+//! select a different [`VersionScheme`] per pin, PyPI packages always use [PEP 440].
+//!
+//! [PEP 440]: https://peps.python.org/pep-0440/
+
+use lenient_version::Version;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+/// How versions are parsed and ordered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VersionScheme {
+    /// Semantic versioning, parsed leniently: `v1.2`, `1.2.3-rc.1` or `1.2.3.4`
+    #[default]
+    Semver,
+    /// Calendar versioning like `2024.01.15` or `24.04.1`: any number of numeric components,
+    /// separated by `.`, `-` or `_`. A non-numeric suffix marks a pre-release
+    Calver,
+    /// Dates like `2024-01-15`, `2024.01.15` or `20240115`, optionally followed by more
+    /// numeric components such as a time or a serial number
+    Date,
+    /// Plain string comparison, there are no pre-releases
+    Lexical,
+    /// Debian package versions (`[epoch:]upstream[-revision]`), where `~` sorts before anything
+    /// and marks a pre-release, e.g. `2.0~rc1`
+    Debian,
+    /// Python package versions, with `.devN`, `aN`/`bN`/`rcN` and `.postN` releases
+    Pep440,
+}
+
+impl VersionScheme {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Parse a version, `None` if it is not valid in this scheme (e.g. a tag which is no release)
+    pub fn parse<'a>(&self, version: &'a str) -> Option<ParsedVersion<'a>> {
+        match self {
+            Self::Semver => lenient_semver_parser::parse::<Version>(version)
+                .ok()
+                .map(ParsedVersion::Semver),
+            Self::Calver => Numeric::parse(version, &['.', '-', '_']).map(ParsedVersion::Numeric),
+            Self::Date => Numeric::parse_date(version).map(ParsedVersion::Numeric),
+            Self::Lexical => Some(ParsedVersion::Lexical(version)),
+            Self::Debian => Debian::parse(version).map(ParsedVersion::Debian),
+            Self::Pep440 => Pep440::parse(version).map(ParsedVersion::Pep440),
+        }
+    }
+}
+
+impl std::fmt::Display for VersionScheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Semver => "semver",
+            Self::Calver => "calver",
+            Self::Date => "date",
+            Self::Lexical => "lexical",
+            Self::Debian => "debian",
+            Self::Pep440 => "pep440",
+        })
+    }
+}
+
+impl std::str::FromStr for VersionScheme {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        Ok(match value {
+            "semver" => Self::Semver,
+            "calver" => Self::Calver,
+            "date" => Self::Date,
+            "lexical" => Self::Lexical,
+            "debian" => Self::Debian,
+            "pep440" => Self::Pep440,
+            _ => anyhow::bail!(
+                "Unknown version scheme '{value}', expected one of semver, calver, date, lexical, debian or pep440"
+            ),
+        })
+    }
+}
+
+/// A version parsed with a [`VersionScheme`]
+///
+/// Only versions of the same scheme can be compared meaningfully.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ParsedVersion<'a> {
+    Semver(Version<'a>),
+    Numeric(Numeric<'a>),
+    Lexical(&'a str),
+    Debian(Debian<'a>),
+    Pep440(Pep440),
+}
+
+impl ParsedVersion<'_> {
+    pub fn is_pre_release(&self) -> bool {
+        match self {
+            Self::Semver(version) => version.is_pre_release(),
+            Self::Numeric(version) => version.suffix.is_some(),
+            Self::Lexical(_) => false,
+            Self::Debian(version) => version.upstream.contains('~'),
+            Self::Pep440(version) => version.pre.is_some() || version.dev.is_some(),
+        }
+    }
+
+    /// The leading numeric components, e.g. `[1, 4, 3]` for `1.4.3`
+    ///
+    /// `None` if the scheme has no notion of them.
+    pub fn release(&self) -> Option<Vec<u64>> {
+        match self {
+            Self::Semver(version) => Some(
+                [version.major, version.minor, version.patch]
+                    .into_iter()
+                    .chain(version.additional.iter().copied())
+                    .collect(),
+            ),
+            Self::Numeric(version) => Some(version.components.clone()),
+            Self::Lexical(_) => None,
+            Self::Debian(version) => Some(
+                version
+                    .upstream
+                    .split('.')
+                    .map_while(|component| component.parse().ok())
+                    .collect(),
+            ),
+            Self::Pep440(version) => Some(version.release.clone()),
+        }
+    }
+}
+
+/// Compare numeric components, missing ones count as zero
+fn cmp_components(a: &[u64], b: &[u64]) -> Ordering {
+    (0..a.len().max(b.len()))
+        .map(|i| {
+            let component = |v: &[u64]| v.get(i).copied().unwrap_or(0);
+            component(a).cmp(&component(b))
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// A calendar version or date
+#[derive(Debug, Clone)]
+pub struct Numeric<'a> {
+    components: Vec<u64>,
+    /// Anything after the numeric components, marking a pre-release
+    suffix: Option<&'a str>,
+}
+
+impl<'a> Numeric<'a> {
+    fn parse(version: &'a str, separators: &[char]) -> Option<Self> {
+        let mut rest = version.strip_prefix(['v', 'V']).unwrap_or(version);
+        let mut components = Vec::new();
+        loop {
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            components.push(rest[..end].parse().ok()?);
+            rest = &rest[end..];
+            /* Continue only if a separator is followed by another number */
+            match rest.strip_prefix(separators) {
+                Some(next) if next.starts_with(|c: char| c.is_ascii_digit()) => rest = next,
+                _ => break,
+            }
+        }
+        let suffix = rest.trim_start_matches(['.', '-', '_', '+']);
+        Some(Self {
+            components,
+            suffix: (!suffix.is_empty()).then_some(suffix),
+        })
+    }
+
+    fn parse_date(version: &'a str) -> Option<Self> {
+        let mut date = Self::parse(version, &['.', '-', '_', ':', 'T', ' '])?;
+        /* A compact date like 20240115 */
+        if let Some(compact) = date.components.first().copied()
+            && (10_000_000..100_000_000).contains(&compact)
+        {
+            date.components
+                .splice(0..1, [compact / 10_000, compact / 100 % 100, compact % 100]);
+        }
+        match date.components[..] {
+            [year, month, day, ..]
+                if year >= 1000 && (1..=12).contains(&month) && (1..=31).contains(&day) =>
+            {
+                Some(date)
+            },
+            _ => None,
+        }
+    }
+}
+
+impl Ord for Numeric<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        cmp_components(&self.components, &other.components).then_with(|| {
+            match (self.suffix, other.suffix) {
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some(a), Some(b)) => a.cmp(b),
+            }
+        })
+    }
+}
+
+impl PartialOrd for Numeric<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Numeric<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Numeric<'_> {}
+
+/// A Debian package version, compared like `dpkg --compare-versions`
+#[derive(Debug, Clone)]
+pub struct Debian<'a> {
+    epoch: u64,
+    upstream: &'a str,
+    revision: &'a str,
+}
+
+impl<'a> Debian<'a> {
+    fn parse(version: &'a str) -> Option<Self> {
+        let (epoch, rest) = match version.split_once(':') {
+            Some((epoch, rest)) => (epoch.parse().ok()?, rest),
+            None => (0, version),
+        };
+        let (upstream, revision) = rest.rsplit_once('-').unwrap_or((rest, ""));
+        if !upstream.starts_with(|c: char| c.is_ascii_digit())
+            || !rest
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || ".+-~:".contains(c))
+        {
+            return None;
+        }
+        Some(Self {
+            epoch,
+            upstream,
+            revision,
+        })
+    }
+
+    /// The `verrevcmp` algorithm of dpkg: alternating non-numeric parts, compared character-wise
+    /// with `~` sorting before the end and letters before other characters, and numeric parts
+    fn compare_part(a: &str, b: &str) -> Ordering {
+        fn order(c: Option<u8>) -> i32 {
+            match c {
+                Some(b'~') => -1,
+                None => 0,
+                Some(c) if c.is_ascii_digit() => 0,
+                Some(c) if c.is_ascii_alphabetic() => c as i32,
+                Some(c) => c as i32 + 256,
+            }
+        }
+        let is_digit = |s: &[u8]| s.first().is_some_and(u8::is_ascii_digit);
+
+        let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+        while !a.is_empty() || !b.is_empty() {
+            while (!a.is_empty() && !is_digit(a)) || (!b.is_empty() && !is_digit(b)) {
+                let (ac, bc) = (order(a.first().copied()), order(b.first().copied()));
+                if ac != bc {
+                    return ac.cmp(&bc);
+                }
+                a = a.get(1..).unwrap_or_default();
+                b = b.get(1..).unwrap_or_default();
+            }
+            let digits = |s: &[u8]| s.iter().take_while(|c| c.is_ascii_digit()).count();
+            let (a_len, b_len) = (digits(a), digits(b));
+            let trim = |s: &[u8]| s.iter().take_while(|c| **c == b'0').count();
+            let (a_num, b_num) = (&a[trim(&a[..a_len])..a_len], &b[trim(&b[..b_len])..b_len]);
+            let ordering = a_num.len().cmp(&b_num.len()).then_with(|| a_num.cmp(b_num));
+            if ordering.is_ne() {
+                return ordering;
+            }
+            a = &a[a_len..];
+            b = &b[b_len..];
+        }
+        Ordering::Equal
+    }
+}
+
+impl Ord for Debian<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.epoch
+            .cmp(&other.epoch)
+            .then_with(|| Self::compare_part(self.upstream, other.upstream))
+            .then_with(|| Self::compare_part(self.revision, other.revision))
+    }
+}
+
+impl PartialOrd for Debian<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Debian<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Debian<'_> {}
+
+/// A Python package version as specified by PEP 440, including the normalizations
+#[derive(Debug, Clone)]
+pub struct Pep440 {
+    epoch: u64,
+    release: Vec<u64>,
+    /// `a`, `b` or `rc` (as 0, 1, 2) with their number
+    pre: Option<(u8, u64)>,
+    post: Option<u64>,
+    dev: Option<u64>,
+    local: Option<String>,
+}
+
+impl Pep440 {
+    fn parse(version: &str) -> Option<Self> {
+        let version = version.trim().to_ascii_lowercase();
+        let mut rest = version.strip_prefix('v').unwrap_or(&version);
+
+        /* Consume a number, if there is one */
+        fn number(rest: &mut &str) -> Option<u64> {
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let number = rest[..end].parse().ok()?;
+            *rest = &rest[end..];
+            Some(number)
+        }
+        /* Consume one of the labels, optionally preceded by a separator, and its optional number */
+        fn label(rest: &mut &str, labels: &[&str]) -> Option<(usize, u64)> {
+            let trimmed = rest.trim_start_matches(['.', '-', '_']);
+            let (index, after) = labels.iter().enumerate().find_map(|(index, label)| {
+                trimmed.strip_prefix(label).map(|after| (index, after))
+            })?;
+            let mut after = after;
+            let mut numbered = after.trim_start_matches(['.', '-', '_']);
+            let value = number(&mut numbered);
+            if value.is_some() {
+                after = numbered;
+            }
+            *rest = after;
+            Some((index, value.unwrap_or(0)))
+        }
+
+        let epoch = match rest.split_once('!') {
+            Some((epoch, after)) => {
+                rest = after;
+                epoch.parse().ok()?
+            },
+            None => 0,
+        };
+        let mut release = vec![number(&mut rest)?];
+        while let Some(after) = rest.strip_prefix('.')
+            && after.starts_with(|c: char| c.is_ascii_digit())
+        {
+            rest = after;
+            release.push(number(&mut rest)?);
+        }
+
+        /* Longer labels first, so that `rc` isn't parsed as `r` */
+        let pre = label(
+            &mut rest,
+            &["alpha", "beta", "preview", "pre", "rc", "a", "b", "c"],
+        )
+        .map(|(index, number)| ([0, 1, 2, 2, 2, 0, 1, 2][index], number));
+        let post = label(&mut rest, &["post", "rev", "r"])
+            .map(|(_, number)| number)
+            .or_else(|| {
+                /* The implicit post release `1.0-1` */
+                let mut after = rest.strip_prefix('-')?;
+                let number = number(&mut after)?;
+                rest = after;
+                Some(number)
+            });
+        let dev = label(&mut rest, &["dev"]).map(|(_, number)| number);
+        let local = match rest.strip_prefix('+') {
+            Some(local)
+                if !local.is_empty()
+                    && local
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || ".-_".contains(c)) =>
+            {
+                rest = "";
+                Some(local.replace(['-', '_'], "."))
+            },
+            _ => None,
+        };
+
+        rest.is_empty().then_some(Self {
+            epoch,
+            release,
+            pre,
+            post,
+            dev,
+            local,
+        })
+    }
+}
+
+impl Ord for Pep440 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        /* The developmental releases of a final release sort before its pre-releases */
+        let pre = |v: &Self| match (v.pre, v.post, v.dev) {
+            (None, None, Some(_)) => (0, 0),
+            (None, _, _) => (4, 0),
+            (Some((label, number)), _, _) => (label + 1, number),
+        };
+        let post = |v: &Self| v.post.map_or((0, 0), |post| (1, post));
+        let dev = |v: &Self| v.dev.map_or((1, 0), |dev| (0, dev));
+        self.epoch
+            .cmp(&other.epoch)
+            .then_with(|| cmp_components(&self.release, &other.release))
+            .then_with(|| pre(self).cmp(&pre(other)))
+            .then_with(|| post(self).cmp(&post(other)))
+            .then_with(|| dev(self).cmp(&dev(other)))
+            .then_with(|| self.local.cmp(&other.local))
+    }
+}
+
+impl PartialOrd for Pep440 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Pep440 {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Pep440 {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Assert that the versions are valid and strictly ascending in the scheme
+    fn assert_ascending(scheme: VersionScheme, versions: &[&str]) {
+        let parsed: Vec<_> = versions
+            .iter()
+            .map(|version| {
+                scheme
+                    .parse(version)
+                    .unwrap_or_else(|| panic!("{version} is not a valid {scheme} version"))
+            })
+            .collect();
+        for (i, window) in parsed.windows(2).enumerate() {
+            assert!(
+                window[0] < window[1],
+                "{} < {} in {scheme}",
+                versions[i],
+                versions[i + 1]
+            );
+        }
+    }
+
+    #[test]
+    fn test_calver() {
+        let scheme = VersionScheme::Calver;
+        assert_ascending(
+            scheme,
+            &[
+                "23.12",
+                "2023.12.31",
+                "2024.01.15-rc1",
+                "2024.1.15",
+                "2024.02",
+                "v2024.10.1",
+            ],
+        );
+        assert_eq!(scheme.parse("2024.01"), scheme.parse("2024.1.0"));
+        assert!(scheme.parse("2024.01.15-rc1").unwrap().is_pre_release());
+        assert!(scheme.parse("stable").is_none());
+    }
+
+    #[test]
+    fn test_date() {
+        let scheme = VersionScheme::Date;
+        assert_ascending(
+            scheme,
+            &[
+                "2023-12-31",
+                "20240115",
+                "2024.01.15.1",
+                "2024-01-15T12:30",
+                "2024_02_01",
+            ],
+        );
+        assert_eq!(scheme.parse("20240115"), scheme.parse("2024-01-15"));
+        for invalid in ["1.2.3", "2024-13-01", "24.01.15", "2024"] {
+            assert!(scheme.parse(invalid).is_none(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_lexical() {
+        assert_ascending(VersionScheme::Lexical, &["1.10", "1.9", "a", "b"]);
+    }
+
+    #[test]
+    fn test_debian() {
+        let scheme = VersionScheme::Debian;
+        assert_ascending(
+            scheme,
+            &[
+                "1.0~~",
+                "1.0~rc1",
+                "1.0",
+                "1.0-1",
+                "1.0-1ubuntu1",
+                "1.0a",
+                "1.0+dfsg",
+                "1.00.1",
+                "1.10",
+                "1:0.9",
+            ],
+        );
+        assert_eq!(scheme.parse("1.01"), scheme.parse("1.1"));
+        assert!(scheme.parse("2.0~rc1").unwrap().is_pre_release());
+        assert!(scheme.parse("v1.0").is_none());
+    }
+
+    #[test]
+    fn test_pep440() {
+        let scheme = VersionScheme::Pep440;
+        assert_ascending(
+            scheme,
+            &[
+                "1.0.dev0",
+                "1.0a1",
+                "1.0b2.dev3",
+                "1.0b2",
+                "1.0rc1",
+                "1.0",
+                "1.0+local",
+                "1.0.post1.dev1",
+                "1.0.post1",
+                "1.0.1",
+                "1!0.5",
+            ],
+        );
+        /* Normalized forms */
+        assert_eq!(scheme.parse("2.0rc1"), scheme.parse("2.0-RC.1"));
+        assert_eq!(scheme.parse("1.0-1"), scheme.parse("1.0.post1"));
+        assert_eq!(scheme.parse("1.0alpha"), scheme.parse("1.0a0"));
+        assert_eq!(scheme.parse("v1.0"), scheme.parse("1.0.0"));
+
+        assert!(scheme.parse("1.0.dev1").unwrap().is_pre_release());
+        assert!(scheme.parse("1.0rc1").unwrap().is_pre_release());
+        assert!(!scheme.parse("1.0.post1").unwrap().is_pre_release());
+        for invalid in ["", "foo", "1.0-foo", "1.0+"] {
+            assert!(scheme.parse(invalid).is_none(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_semver() {
+        assert_ascending(
+            VersionScheme::Semver,
+            &["1.0", "v1.1", "2.0.0-rc.1", "2.0", "2.0.0.1"],
+        );
+    }
+}
@@ -9,7 +9,7 @@ use std::{collections::BTreeMap, path::Path};
 use crate::NixPins;
 
 /// The current format version
-pub const LATEST: u64 = 8;
+pub const LATEST: u64 = 10;
 
 /// Custom manual deserialize wrapper that checks the version
 pub fn from_value_versioned(value: Value) -> Result<NixPins> {
@@ -137,6 +137,18 @@ pub fn upgrade(mut pins_raw: Map<String, Value>, path: &Path) -> Result<Value> {
                 Ok(())
             }) as Upgrader<'_>,
         ),
+        (
+            8,
+            Box::new(|pins_raw: &mut Map<String, Value>| {
+                generic_upgrader(pins_raw, upgrade_v8_pin, path)
+            }) as Upgrader<'_>,
+        ),
+        (
+            9,
+            Box::new(|pins_raw: &mut Map<String, Value>| {
+                generic_upgrader(pins_raw, upgrade_v9_pin, path)
+            }) as Upgrader<'_>,
+        ),
     ]
     .into_iter()
     .collect();
@@ -340,6 +352,55 @@ fn upgrade_v7_pin(name: &str, raw_pin: &mut Map<String, Value>) -> Result<()> {
     Ok(())
 }
 
+/* v8→v9. This upgrade replaces the exclusive `version_upper_bound` of versioned pins with a
+ * `constraint`, which supports the full requirement grammar:
+ * - `version_upper_bound: "2.0"` → `constraint: "<2.0"`
+ */
+fn upgrade_v8_pin(name: &str, raw_pin: &mut Map<String, Value>) -> Result<()> {
+    log::debug!("Updating {} to v9", name);
+
+    if let Some(upper_bound) = raw_pin.remove("version_upper_bound") {
+        let upper_bound: Option<String> = serde_json::from_value(upper_bound)
+            .context("Field `version_upper_bound` must be a string")?;
+        raw_pin.insert(
+            "constraint".into(),
+            json!(upper_bound.map(|upper_bound| format!("<{upper_bound}"))),
+        );
+    }
+
+    Ok(())
+}
+
+/* v9→v10. This upgrade removes the access tokens of GitLab repositories from the lock file:
+ * - `private_token: "…"` → `credential: "<pin name>"`
+ * The token also made its way into the locked tarball URL, so `url` and `hash` are dropped
+ * and have to be fetched again, this time with the token in a header.
+ */
+fn upgrade_v9_pin(name: &str, raw_pin: &mut Map<String, Value>) -> Result<()> {
+    log::debug!("Updating {} to v10", name);
+
+    let Some(repository) = raw_pin.get_mut("repository").and_then(Value::as_object_mut) else {
+        return Ok(());
+    };
+    match repository.remove("private_token") {
+        None | Some(Value::Null) => return Ok(()),
+        Some(_) => {
+            repository.insert("credential".into(), json!(name));
+        },
+    }
+    raw_pin.remove("url");
+    raw_pin.remove("hash");
+
+    log::warn!(
+        "Removed the GitLab access token of pin {name} from the lock file. It now uses the credential `{name}`, \
+        so set the token in the environment variable {} (or see the README for the alternatives) and run `npins update {name}`. \
+        The token is still in the history of the file, consider revoking it.",
+        crate::credentials::env_variable(name)
+    );
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -430,14 +491,14 @@ mod test {
                         frozen: Frozen::default(),
                     },
                     "streamlit".into() => Pin::PyPi {
-                        input: pypi::Pin { name: "streamlit".into(), version_upper_bound: None },
-                        version: Some(GenericVersion { version: "1.3.1".into() }),
+                        input: pypi::Pin { name: "streamlit".into(), index: None, wheel: None, pre_releases: false, constraint: None, min_age: None },
+                        version: Some(GenericVersion { version: "1.3.1".into(), date: None }),
                         hashes: Some(GenericUrlHashes { url: "https://files.pythonhosted.org/packages/c3/9d/ac871992617220442832af12c3808716f4349ab05ff939d695fe8b542f00/streamlit-1.3.1.tar.gz".parse().unwrap(), hash: NixHash::from_sri("sha256-rex5NcnPd0uRFbJFbPL0jE9JufZxWal9sP4ig1fBr98=").unwrap() } ),
                         frozen: Frozen::default(),
                     },
                     "youtube-dl".into() => Pin::GitRelease {
                         input: git::GitReleasePin::new(git::Repository::github("ytdl-org", "youtube-dl"), false, None, None, false),
-                        version: Some(GenericVersion { version: "youtube-dl 2021.12.17".into() }),
+                        version: Some(GenericVersion { version: "youtube-dl 2021.12.17".into(), date: None }),
                         hashes: None,
                         frozen: Frozen::default(),
                     }
@@ -525,4 +586,139 @@ mod test {
             }
         );
     }
+
+    #[test]
+    fn test_v8() {
+        init_logger();
+
+        let pins = match json!({
+            "pins": {
+                "youtube-dl": {
+                    "type": "GitRelease",
+                    "repository": { "type": "GitHub", "owner": "ytdl-org", "repo": "youtube-dl" },
+                    "pre_releases": false,
+                    "version_upper_bound": "2022",
+                    "release_prefix": null,
+                    "submodules": false,
+                    "version": "2021.12.17",
+                    "revision": "c7965b9fc2cae54f244f31f5373cb81a40e822ab",
+                    "url": "https://api.github.com/repos/ytdl-org/youtube-dl/tarball/2021.12.17",
+                    "hash": "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+                },
+                "streamlit": {
+                    "type": "PyPi",
+                    "name": "streamlit",
+                    "version_upper_bound": null,
+                    "version": "1.3.1",
+                    "url": "https://example.com/streamlit-1.3.1.tar.gz",
+                    "hash": "sha256-BBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB="
+                }
+            },
+            "version": 8
+        }) {
+            Value::Object(pins) => pins,
+            _ => unreachable!(),
+        };
+        let pins =
+            upgrade(pins, Path::new("in-memory-source.json")).expect("Failed to upgrade data");
+        let pins = serde_json::from_value::<NixPins>(pins)
+            .expect("Upgraded data failed to deserialize with newest code");
+
+        assert_eq!(
+            pins,
+            NixPins {
+                pins: btreemap![
+                    "youtube-dl".into() => Pin::GitRelease {
+                        input: git::GitReleasePin::new(git::Repository::github("ytdl-org", "youtube-dl"), false, Some("<2022".into()), None, false),
+                        version: Some(GenericVersion { version: "2021.12.17".into(), date: None }),
+                        hashes: Some(git::ReleasePinHashes {
+                            revision: "c7965b9fc2cae54f244f31f5373cb81a40e822ab".into(),
+                            url: Some("https://api.github.com/repos/ytdl-org/youtube-dl/tarball/2021.12.17".parse().unwrap()),
+                            hash: NixHash::from_sri("sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=").unwrap()
+                        }),
+                        frozen: Frozen::default(),
+                    },
+                    "streamlit".into() => Pin::PyPi {
+                        input: pypi::Pin { name: "streamlit".into(), index: None, wheel: None, pre_releases: false, constraint: None, min_age: None },
+                        version: Some(GenericVersion { version: "1.3.1".into(), date: None }),
+                        hashes: Some(GenericUrlHashes {
+                            url: "https://example.com/streamlit-1.3.1.tar.gz".parse().unwrap(),
+                            hash: NixHash::from_sri("sha256-BBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB=").unwrap()
+                        }),
+                        frozen: Frozen::default(),
+                    }
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_v9() {
+        init_logger();
+
+        let pins = match json!({
+            "pins": {
+                "secret": {
+                    "type": "Git",
+                    "repository": {
+                        "type": "GitLab",
+                        "repo_path": "my-org/secret",
+                        "server": "https://gitlab.example.org/",
+                        "private_token": "glpat-secret"
+                    },
+                    "branch": "main",
+                    "submodules": false,
+                    "revision": "5c6b0bc1d85a5e2b3f1d8a9e7c4f2b1a0d9e8c7b",
+                    "url": "https://gitlab.example.org/api/v4/projects/my-org%2Fsecret/repository/archive.tar.gz?private_token=glpat-secret",
+                    "hash": "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+                },
+                "public": {
+                    "type": "Git",
+                    "repository": {
+                        "type": "GitLab",
+                        "repo_path": "my-org/public",
+                        "server": "https://gitlab.example.org/"
+                    },
+                    "branch": "main",
+                    "submodules": false,
+                    "revision": "e7145078163692697b843915a665d4f41139a65c",
+                    "url": "https://gitlab.example.org/api/v4/projects/my-org%2Fpublic/repository/archive.tar.gz?sha=e7145078163692697b843915a665d4f41139a65c",
+                    "hash": "sha256-BBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB="
+                }
+            },
+            "version": 9
+        }) {
+            Value::Object(pins) => pins,
+            _ => unreachable!(),
+        };
+        let pins =
+            upgrade(pins, Path::new("in-memory-source.json")).expect("Failed to upgrade data");
+        assert!(!pins.to_string().contains("glpat-secret"));
+        let pins = serde_json::from_value::<NixPins>(pins)
+            .expect("Upgraded data failed to deserialize with newest code");
+
+        let server: url::Url = "https://gitlab.example.org/".parse().unwrap();
+        assert_eq!(
+            pins,
+            NixPins {
+                pins: btreemap![
+                    "secret".into() => Pin::Git {
+                        input: git::GitPin::new(git::Repository::gitlab("my-org/secret".into(), Some(server.clone()), Some("secret".into())), "main".into(), false),
+                        version: Some(git::GitRevision::new("5c6b0bc1d85a5e2b3f1d8a9e7c4f2b1a0d9e8c7b".into()).unwrap()),
+                        hashes: None,
+                        frozen: Frozen::default(),
+                    },
+                    "public".into() => Pin::Git {
+                        input: git::GitPin::new(git::Repository::gitlab("my-org/public".into(), Some(server), None), "main".into(), false),
+                        version: Some(git::GitRevision::new("e7145078163692697b843915a665d4f41139a65c".into()).unwrap()),
+                        hashes: Some(git::OptionalUrlHashes {
+                            url: Some("https://gitlab.example.org/api/v4/projects/my-org%2Fpublic/repository/archive.tar.gz?sha=e7145078163692697b843915a665d4f41139a65c".parse().unwrap()),
+                            hash: NixHash::from_sri("sha256-BBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB=").unwrap()
+                        }),
+                        frozen: Frozen::default(),
+                    }
+                ],
+            }
+        );
+    }
 }
@@ -0,0 +1,147 @@
+//! Compare links and commit lists against the emulated forges, see [`common`]
+
+mod common;
+
+use anyhow::Result;
+use libnpins::changelog::Changelog;
+use libnpins::git::{GitPin, GitReleasePin, GitRevision, Repository};
+use libnpins::{GenericVersion, Pin};
+
+#[tokio::test]
+async fn test_github_branch_changelog() -> Result<()> {
+    let services = common::setup();
+    let old = "c1d3d4a3e1f9d1c4e3bd22e9fe44f6a0e0c1ab47";
+    let new = "a8d8ab5f0e1d1ac9c1b1eb8e6e7cfb1a3e0c4d25";
+    let mut pin: Pin = (
+        GitPin::new(
+            Repository::github("jstutters", "MidiOSC"),
+            "master".into(),
+            false,
+        ),
+        GitRevision::new(old.into())?,
+    )
+        .into();
+    let diff = pin.update(&services.config).await?;
+
+    let mut changelog = Changelog::new(&services.config, &pin, &diff)?.unwrap();
+    assert_eq!(
+        changelog.compare_url,
+        services
+            .github
+            .join(&format!("jstutters/MidiOSC/compare/{old}...{new}"))?
+    );
+    assert_eq!(changelog.commits, None);
+
+    changelog.fetch_commits(&services.config).await?;
+    let commits = changelog.commits.unwrap();
+    assert_eq!(commits.len(), 1);
+    assert_eq!(commits[0].id, new);
+    assert_eq!(
+        commits[0].title,
+        format!("Update jstutters/MidiOSC to {new}")
+    );
+    assert_eq!(
+        commits[0].url,
+        Some(
+            services
+                .github
+                .join(&format!("jstutters/MidiOSC/commit/{new}"))?
+        )
+    );
+
+    /* Nothing changed */
+    let diff = pin.update(&services.config).await?;
+    assert_eq!(Changelog::new(&services.config, &pin, &diff)?, None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_gitlab_release_changelog() -> Result<()> {
+    let services = common::setup();
+    let mut pin: Pin = (
+        GitReleasePin::new(
+            Repository::gitlab(
+                "maxigaz/gitlab-dark".into(),
+                Some(services.gitlab.clone()),
+                None,
+            ),
+            false,
+            None,
+            None,
+            false,
+        ),
+        GenericVersion {
+            version: "v1.15.1".into(),
+            date: None,
+        },
+    )
+        .into();
+    let diff = pin.update(&services.config).await?;
+
+    let mut changelog = Changelog::new(&services.config, &pin, &diff)?.unwrap();
+    assert_eq!(
+        changelog.compare_url,
+        services
+            .gitlab
+            .join("maxigaz/gitlab-dark/-/compare/v1.15.1...v1.16.0")?
+    );
+
+    changelog.fetch_commits(&services.config).await?;
+    let commits = changelog.commits.unwrap();
+    assert_eq!(commits.len(), 1);
+    assert_eq!(commits[0].id, "d42ec2b04df9da97e465883fcd1f9a5d6e794027");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_forgejo_release_changelog() -> Result<()> {
+    let services = common::setup();
+    let mut pin: Pin = (
+        GitReleasePin::new(
+            Repository::forgejo(services.forgejo.clone(), "lix-project", "lix"),
+            false,
+            None,
+            None,
+            false,
+        ),
+        GenericVersion {
+            version: "2.90.0".into(),
+            date: None,
+        },
+    )
+        .into();
+    let diff = pin.update(&services.config).await?;
+
+    let mut changelog = Changelog::new(&services.config, &pin, &diff)?.unwrap();
+    assert_eq!(
+        changelog.compare_url,
+        services
+            .forgejo
+            .join("lix-project/lix/compare/2.90.0...2.90.1")?
+    );
+
+    changelog.fetch_commits(&services.config).await?;
+    let commits = changelog.commits.unwrap();
+    assert_eq!(commits.len(), 1);
+    /* The tag is annotated, the commit is the peeled one */
+    assert_eq!(commits[0].id, "0dd3a2d6fbeba0e2a3ad2f8c4c1cf6b27e9a5a33");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_plain_git_changelog() -> Result<()> {
+    let services = common::setup();
+    let mut pin: Pin = (
+        GitPin::new(
+            Repository::git(services.git.join("jstutters/MidiOSC.git")?),
+            "master".into(),
+            false,
+        ),
+        GitRevision::new("c1d3d4a3e1f9d1c4e3bd22e9fe44f6a0e0c1ab47".into())?,
+    )
+        .into();
+    let diff = pin.update(&services.config).await?;
+    assert!(!diff.is_empty());
+    assert_eq!(Changelog::new(&services.config, &pin, &diff)?, None);
+    Ok(())
+}
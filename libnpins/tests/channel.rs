@@ -0,0 +1,30 @@
+//! Channel pins against the emulated channel server, see [`common`]
+
+mod common;
+
+use anyhow::Result;
+use libnpins::Updatable;
+use libnpins::channel::{ChannelHash, ChannelVersion, NIXPKGS_ARTIFACT, Pin};
+use nix_compat::nixhash::NixHash;
+
+#[tokio::test]
+async fn test_channel_update() -> Result<()> {
+    let services = common::setup();
+    let pin = Pin::new("nixos-21.11", NIXPKGS_ARTIFACT);
+    let version = pin.update(&services.config, None).await?;
+    assert_eq!(
+        version,
+        ChannelVersion {
+            url: services
+                .files
+                .join("nixos/21.11/nixos-21.11.335807.df4f1f7cc3f/nixexprs.tar.xz")?,
+        }
+    );
+    assert_eq!(
+        pin.fetch(&services.config, &version).await?,
+        ChannelHash {
+            hash: NixHash::from_sri("sha256-5DS1Tv7DNOwNxnLQwRb4xeog0CRAKuhiObrkHkYjwrk=").unwrap(),
+        }
+    );
+    Ok(())
+}
@@ -0,0 +1,1508 @@
+//! Hermetic stand-ins for the forges and registries npins talks to
+//!
+//! Every service is emulated by a small HTTP server on localhost, serving the fixtures defined
+//! below. Call [`setup`] at the start of each test: it starts the servers once per test binary and
+//! returns a [`Config`] pointing npins at them in [`Services::config`]. The external tools which
+//! cannot be replaced with an HTTP server (`nix-prefetch-git` and `nix-prefetch-docker`) are
+//! replaced with scripts returning canned responses.
+//!
+//! Archives are generated on the fly. They contain a single `README` whose content depends on the
+//! repository and the resolved revision (or on the requested path for plain files), which keeps the
+//! expected hashes in the tests stable.
+
+#![allow(dead_code)]
+
+use libnpins::Config;
+use libnpins::config::Hosts;
+use libnpins::timestamp::Timestamp;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+use url::Url;
+
+/// A git repository, served by all forges
+pub struct Repo {
+    /// `owner/repo` or any other GitLab-style path
+    pub path: &'static str,
+    /// The default branch
+    pub head: &'static str,
+    /// `(name, oid, peeled)`, where `peeled` is only set for annotated tags
+    pub refs: &'static [(&'static str, &'static str, Option<&'static str>)],
+}
+
+pub const REPOS: &[Repo] = &[
+    Repo {
+        path: "oliverwatkins/swing_library",
+        head: "master",
+        refs: &[(
+            "refs/heads/master",
+            "1edb0a9cebe046cc915a218c57dbf7f40739aeee",
+            None,
+        )],
+    },
+    Repo {
+        path: "jstutters/MidiOSC",
+        head: "master",
+        refs: &[
+            (
+                "refs/heads/master",
+                "a8d8ab5f0e1d1ac9c1b1eb8e6e7cfb1a3e0c4d25",
+                None,
+            ),
+            (
+                "refs/tags/v1.0",
+                "c1d3d4a3e1f9d1c4e3bd22e9fe44f6a0e0c1ab47",
+                None,
+            ),
+            (
+                "refs/tags/v1.1",
+                "35be5b2b2c3431de1100996487d53134f658b866",
+                None,
+            ),
+            (
+                "refs/tags/v2.0-beta",
+                "f3b1e52c2e0d4a4d0fbb1c6b8b5f2d8c1a7f6e90",
+                None,
+            ),
+        ],
+    },
+    Repo {
+        path: "lix-project/lix",
+        head: "main",
+        refs: &[
+            (
+                "refs/heads/main",
+                "9b5ee1a4a5d2f4c4b34b0b8f3b2c8c7d1c0f1e2a",
+                None,
+            ),
+            (
+                "refs/heads/release-2.90",
+                "4bbdb2f5564b9b42bcaf0e1eec28325300f31c72",
+                None,
+            ),
+            (
+                "refs/tags/2.90.0",
+                "2a4376be20d70feaa2b0e640c5041fb66ddc67ed",
+                None,
+            ),
+            (
+                "refs/tags/2.90.1",
+                "6f2d0e5b1a3c4e8d9f7b2a1c0e3d5f4b6a8c9e71",
+                Some("0dd3a2d6fbeba0e2a3ad2f8c4c1cf6b27e9a5a33"),
+            ),
+            (
+                "refs/tags/2.91.0-rc1",
+                "e2c4a6b8d0f1e3a5c7b9d1f3e5a7c9b1d3f5a7c9",
+                None,
+            ),
+        ],
+    },
+    Repo {
+        path: "maxigaz/gitlab-dark",
+        head: "master",
+        refs: &[
+            (
+                "refs/heads/master",
+                "e7145078163692697b843915a665d4f41139a65c",
+                None,
+            ),
+            (
+                "refs/tags/v1.15.1",
+                "cb30bd2aca6dca7fc7d3007360ad326d0149e6b8",
+                None,
+            ),
+            (
+                "refs/tags/v1.16.0",
+                "d42ec2b04df9da97e465883fcd1f9a5d6e794027",
+                None,
+            ),
+            (
+                "refs/tags/v1.2",
+                "798f09bfdbc55b5752546d35da77d607c78b603b",
+                None,
+            ),
+        ],
+    },
+    Repo {
+        path: "rate-limited/popular",
+        head: "main",
+        refs: &[
+            (
+                "refs/heads/main",
+                "8e1f0c2d4b6a8c0e2f4a6b8d0c2e4f6a8b0d2c4e",
+                None,
+            ),
+            (
+                "refs/tags/v3.1.0",
+                "b7d9f1a3c5e7092b4d6f8a0c2e4b6d8f0a2c4e6b",
+                None,
+            ),
+        ],
+    },
+    Repo {
+        path: "private/secret-repo",
+        head: "main",
+        refs: &[(
+            "refs/heads/main",
+            "5c6b0bc1d85a5e2b3f1d8a9e7c4f2b1a0d9e8c7b",
+            None,
+        )],
+    },
+];
+
+/// Releases whose assets have not been uploaded yet, as `(repo, tag)`
+///
+/// All other tags have a release with the assets `{name}-{version}-{x86_64,aarch64}-linux.tar.gz`,
+/// where `name` is the last component of the repository path and `version` the tag without a
+/// leading `v`. Tags containing a `-` are marked as pre-releases.
+pub const RELEASES_WITHOUT_ASSETS: &[(&str, &str)] = &[("lix-project/lix", "2.90.1")];
+
+/// Age in days of some commits, all others are [`DEFAULT_COMMIT_AGE`] days old
+///
+/// The commit dates are computed relative to the current time, so that the results of the minimum
+/// age checks don't change over time.
+pub const COMMIT_AGES: &[(&str, u64)] = &[
+    /* jstutters/MidiOSC: master, v2.0-beta, v1.1 */
+    ("a8d8ab5f0e1d1ac9c1b1eb8e6e7cfb1a3e0c4d25", 1),
+    ("f3b1e52c2e0d4a4d0fbb1c6b8b5f2d8c1a7f6e90", 2),
+    ("35be5b2b2c3431de1100996487d53134f658b866", 30),
+    /* lix-project/lix: 2.90.1 */
+    ("0dd3a2d6fbeba0e2a3ad2f8c4c1cf6b27e9a5a33", 3),
+    /* maxigaz/gitlab-dark: v1.16.0 */
+    ("d42ec2b04df9da97e465883fcd1f9a5d6e794027", 2),
+];
+
+pub const DEFAULT_COMMIT_AGE: u64 = 400;
+
+/// A point in time relative to the start of the tests
+fn days_ago(days: u64) -> Timestamp {
+    static START: OnceLock<Timestamp> = OnceLock::new();
+    *START.get_or_init(Timestamp::now) - Duration::from_secs(days * 24 * 60 * 60)
+}
+
+/// The date of a commit, see [`COMMIT_AGES`]
+pub fn commit_date(oid: &str) -> Timestamp {
+    days_ago(
+        COMMIT_AGES
+            .iter()
+            .find(|(commit, _)| *commit == oid)
+            .map_or(DEFAULT_COMMIT_AGE, |(_, days)| *days),
+    )
+}
+
+/// The credentials GitLab requires for repositories below `private/`
+pub const GITLAB_PRIVATE_TOKEN: &str = "glpat-npins-test";
+
+/// The token which lifts the rate limit of the GitHub API for the repositories below `rate-limited/`
+///
+/// Anonymous requests for them fail as if the quota was used up. With the token, the first request
+/// hits the secondary rate limit, which asks to retry right away.
+pub const GITHUB_TOKEN: &str = "ghp_npinstest";
+
+/// `(name, versions)`, the last version is the latest one
+///
+/// The latest version was uploaded [`LATEST_UPLOAD_AGE`] days ago, every other one
+/// [`UPLOAD_INTERVAL`] days before the next.
+pub const PACKAGES: &[(&str, &[&str])] = &[
+    ("gaiatest", &["0.33", "0.34"]),
+    ("streamlit", &["0.88.0", "0.89.0", "1.0.0", "1.2.0"]),
+    (
+        "attrs",
+        &["23.1.0", "23.2.0", "24.1.0rc1", "24.1.0", "24.1.1.dev1"],
+    ),
+    ("black", &["24.1.0", "24.2.0"]),
+];
+
+/// `(name, tags)` of the [`PACKAGES`] which only publish wheels, one per compatibility tag
+pub const WHEELS: &[(&str, &[&str])] = &[(
+    "black",
+    &[
+        "py3-none-any",
+        "cp312-cp312-manylinux_2_17_x86_64.manylinux2014_x86_64",
+        "cp312-cp312-win_amd64",
+    ],
+)];
+
+/// `(name, version, reason)` of the yanked releases of [`PACKAGES`]
+pub const YANKED_PACKAGES: &[(&str, &str, &str)] =
+    &[("attrs", "24.1.0", "Breaks installation on Python 3.8")];
+
+pub const LATEST_UPLOAD_AGE: u64 = 2;
+pub const UPLOAD_INTERVAL: u64 = 100;
+
+/// `(name, [(version, yanked)])`
+///
+/// The versions were published like those of [`PACKAGES`], according to the web API. Only the
+/// default index has one.
+pub const CRATES: &[(&str, &[(&str, bool)])] = &[
+    (
+        "rustc-serialize",
+        &[
+            ("0.3.24", false),
+            ("0.3.25", false),
+            ("0.4.0-alpha.1", false),
+        ],
+    ),
+    (
+        "syn",
+        &[("1.0.0", false), ("2.0.0", false), ("2.0.1", true)],
+    ),
+];
+
+/// A package on the npm registry
+///
+/// The versions were published like those of [`PACKAGES`].
+pub struct NpmPackage {
+    pub name: &'static str,
+    pub versions: &'static [&'static str],
+    /// `(tag, version)`
+    pub dist_tags: &'static [(&'static str, &'static str)],
+}
+
+pub const NPM_PACKAGES: &[NpmPackage] = &[
+    NpmPackage {
+        name: "left-pad",
+        versions: &["1.0.0", "1.1.0", "1.3.0", "2.0.0-beta.1"],
+        dist_tags: &[("latest", "1.3.0"), ("next", "2.0.0-beta.1")],
+    },
+    NpmPackage {
+        name: "@types/node",
+        versions: &["20.1.0", "22.0.0", "22.4.1"],
+        dist_tags: &[("latest", "22.4.1")],
+    },
+];
+
+/// `(name, release)`
+pub const CHANNELS: &[(&str, &str)] =
+    &[("nixos-21.11", "nixos/21.11/nixos-21.11.335807.df4f1f7cc3f")];
+
+/// The hash returned by the `nix-prefetch-git` stand-in
+pub const GIT_HASH: &str = "sha256-zUM/evAqAwwjGXg67IVzqZvvwp2NjFG1HAUSdLv98Z0=";
+/// The same hash, in the nixbase32 format printed by `nix-prefetch-git`
+const GIT_HASH_NIXBASE32: &str = "17giznxp84h53jsm334dkp1fz6x9ff2yqfkq34ihq0ray1x3yhyd";
+/// The image digest returned by the `nix-prefetch-docker` stand-in
+pub const CONTAINER_DIGEST: &str =
+    "sha256:d8b5f1cf24f1b7a0aa334929a264b2606a107223dd0d51eb1cda8aae6fbeec53";
+/// The hash returned by the `nix-prefetch-docker` stand-in
+pub const CONTAINER_HASH: &str = "sha256-1js//EIumaRXILTRW2fp/uinV0dvfA7CzFPQM7neIUo=";
+
+/// The base URLs of all emulated services
+pub struct Services {
+    /// github.com
+    pub github: Url,
+    /// api.github.com
+    pub github_api: Url,
+    /// A GitLab instance, also configured as the default one
+    pub gitlab: Url,
+    /// A Forgejo instance
+    pub forgejo: Url,
+    /// A plain git server. Repositories below `/v0/` only speak the git protocol v0, and those
+    /// below `/dumb/` only the dumb HTTP protocol
+    pub git: Url,
+    /// pypi.org
+    pub pypi: Url,
+    /// index.crates.io. The index below `/custom/` uses a `dl` template with markers
+    pub crates_index: Url,
+    /// registry.npmjs.org
+    pub npm: Url,
+    /// channels.nixos.org
+    pub channels: Url,
+    /// A plain file server for everything else
+    pub files: Url,
+    /// Configuration using the services above instead of the public hosts
+    pub config: Config,
+}
+
+static SERVICES: OnceLock<Services> = OnceLock::new();
+
+fn services() -> &'static Services {
+    SERVICES.get().expect("Services must be set up first")
+}
+
+/// Start all services (once) and configure npins to use them
+pub fn setup() -> &'static Services {
+    SERVICES.get_or_init(|| {
+        let hosts = Hosts {
+            github: serve(github),
+            github_api: serve(github_api),
+            gitlab: serve(gitlab),
+            pypi: serve(pypi),
+            crates_index: serve(crates_index),
+            npm: serve(npm),
+            channels: serve(channels),
+        };
+        let services = Services {
+            github: hosts.github.clone(),
+            github_api: hosts.github_api.clone(),
+            gitlab: hosts.gitlab.clone(),
+            forgejo: serve(forgejo),
+            git: serve(git),
+            pypi: hosts.pypi.clone(),
+            crates_index: hosts.crates_index.clone(),
+            npm: hosts.npm.clone(),
+            channels: hosts.channels.clone(),
+            files: serve(files),
+            config: Config::builder().hosts(hosts).build().unwrap(),
+        };
+
+        let tools = tool_stubs();
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        let path = std::env::join_paths(std::iter::once(tools).chain(std::env::split_paths(&path)))
+            .unwrap();
+
+        // SAFETY: All tests call this function before doing anything else, so there are no other
+        // threads accessing the environment while it is being modified.
+        unsafe {
+            std::env::set_var("PATH", path);
+            std::env::remove_var("GITLAB_TOKEN");
+            std::env::remove_var("GITHUB_TOKEN");
+        }
+
+        services
+    })
+}
+
+/// Write the stand-ins for the external tools into a directory which is kept until the process exits
+fn tool_stubs() -> std::path::PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap().keep();
+    let write = |name: &str, script: String| {
+        let path = dir.join(name);
+        std::fs::write(&path, script).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    };
+
+    write(
+        "nix-prefetch-git",
+        format!(
+            r#"#!/bin/sh
+[ "$1" = "--fetch-submodules" ] && shift
+cat <<EOF
+{{"url": "$1", "rev": "$2", "date": "", "path": "/nix/store/00000000000000000000000000000000-source", "sha256": "{GIT_HASH_NIXBASE32}", "fetchSubmodules": false, "deepClone": false, "leaveDotGit": false}}
+EOF
+"#
+        ),
+    );
+    write(
+        "nix-prefetch-docker",
+        format!(
+            r#"#!/bin/sh
+name=$1
+tag=$2
+shift 2
+digest={CONTAINER_DIGEST}
+while [ $# -gt 0 ]; do
+  [ "$1" = "--image-digest" ] && digest=$2
+  shift
+done
+cat <<EOF
+{{"hash": "{CONTAINER_HASH}", "imageName": "$name", "imageDigest": "$digest", "finalImageName": "$name", "finalImageTag": "$tag"}}
+EOF
+"#
+        ),
+    );
+
+    dir
+}
+
+/// Header marking the requests of a test, see [`marked_requests`]
+pub const MARKER_HEADER: &str = "x-npins-test";
+
+/// `(marker, path)` of all requests with a [`MARKER_HEADER`]
+static MARKED_REQUESTS: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+/// The paths of all requests made so far with the given value of the [`MARKER_HEADER`]
+///
+/// Tests run concurrently, so this allows counting the requests of a single test.
+pub fn marked_requests(marker: &str) -> Vec<String> {
+    MARKED_REQUESTS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(m, _)| m == marker)
+        .map(|(_, path)| path.clone())
+        .collect()
+}
+
+/// A copy of the configuration which marks all requests with `marker`
+pub fn marked(config: &Config, marker: &'static str) -> Config {
+    config.with_header(
+        "localhost",
+        reqwest::header::HeaderName::from_static(MARKER_HEADER),
+        reqwest::header::HeaderValue::from_static(marker),
+    )
+}
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn query_param(&self, name: &str) -> Option<String> {
+        url::form_urlencoded::parse(self.query.as_bytes())
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn ok(content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status: 200,
+            headers: vec![("Content-Type", content_type.into())],
+            body: body.into(),
+        }
+    }
+
+    fn json(value: serde_json::Value) -> Self {
+        Self::ok("application/json", value.to_string())
+    }
+
+    fn redirect(location: Url) -> Self {
+        Self {
+            status: 302,
+            headers: vec![("Location", location.into())],
+            body: Vec::new(),
+        }
+    }
+
+    fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+}
+
+/// Start an HTTP server in the background
+fn serve(handler: fn(&Request) -> Response) -> Url {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            std::thread::spawn(move || handle(stream, handler));
+        }
+    });
+    /* Use a host name, as IP addresses are not recognized as forge domains */
+    format!("http://localhost:{port}/").parse().unwrap()
+}
+
+/// Serve a single HTTP/1.1 request, then close the connection
+fn handle(stream: TcpStream, handler: fn(&Request) -> Response) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Ok(());
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        match line.trim_end().split_once(':') {
+            Some((key, value)) => headers.push((key.to_owned(), value.trim().to_owned())),
+            None => break,
+        }
+    }
+
+    let mut request = Request {
+        method: method.to_owned(),
+        path: path.to_owned(),
+        query: query.to_owned(),
+        headers,
+        body: Vec::new(),
+    };
+    if let Some(length) = request.header("Content-Length") {
+        let length = length.parse().unwrap();
+        request.body.resize(length, 0);
+        reader.read_exact(&mut request.body)?;
+    }
+
+    if let Some(marker) = request.header(MARKER_HEADER) {
+        MARKED_REQUESTS
+            .lock()
+            .unwrap()
+            .push((marker.to_owned(), request.path.clone()));
+    }
+
+    let response = handler(&request);
+    let mut stream = stream;
+    write!(stream, "HTTP/1.1 {} Whatever\r\n", response.status)?;
+    for (key, value) in &response.headers {
+        write!(stream, "{}: {}\r\n", key, value)?;
+    }
+    write!(
+        stream,
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        response.body.len()
+    )?;
+    if request.method != "HEAD" {
+        stream.write_all(&response.body)?;
+    }
+    stream.flush()
+}
+
+fn find_repo(path: &str) -> Option<&'static Repo> {
+    REPOS.iter().find(|repo| repo.path == path)
+}
+
+impl Repo {
+    /// Resolve a commit, branch, tag or full ref name to a commit
+    fn resolve(&self, rev: &str) -> &'static str {
+        let candidates = [
+            rev.to_owned(),
+            format!("refs/tags/{rev}"),
+            format!("refs/heads/{rev}"),
+        ];
+        candidates
+            .iter()
+            .find_map(|name| {
+                self.refs
+                    .iter()
+                    .find(|(ref_, _, _)| ref_ == name)
+                    .map(|(_, oid, peeled)| peeled.unwrap_or(oid))
+            })
+            .unwrap_or_else(|| {
+                self.refs
+                    .iter()
+                    .find(|(_, oid, peeled)| *oid == rev || *peeled == Some(rev))
+                    .map(|(_, oid, peeled)| peeled.unwrap_or(oid))
+                    .unwrap_or_else(|| panic!("Unknown revision {rev} of {}", self.path))
+            })
+    }
+
+    fn head_oid(&self) -> &'static str {
+        self.resolve(self.head)
+    }
+
+    /// The releases in the format of the GitHub API, which Forgejo copied
+    ///
+    /// Like on the forges, the newest release comes first. The tags are assumed to be in order.
+    fn releases(&self) -> Vec<serde_json::Value> {
+        let name = self.path.rsplit('/').next().unwrap();
+        self.refs
+            .iter()
+            .rev()
+            .filter_map(|(ref_, _, _)| ref_.strip_prefix("refs/tags/"))
+            .map(|tag| {
+                let version = tag.strip_prefix('v').unwrap_or(tag);
+                let assets: Vec<_> = if RELEASES_WITHOUT_ASSETS.contains(&(self.path, tag)) {
+                    Vec::new()
+                } else {
+                    ["x86_64", "aarch64"]
+                        .iter()
+                        .map(|arch| {
+                            let asset = format!("{name}-{version}-{arch}-linux.tar.gz");
+                            let url = services()
+                                .files
+                                .join(&format!("releases/{}/{tag}/{asset}", self.path))
+                                .unwrap();
+                            serde_json::json!({
+                                "name": asset,
+                                "browser_download_url": url.as_str(),
+                            })
+                        })
+                        .collect()
+                };
+                serde_json::json!({
+                    "tag_name": tag,
+                    "draft": false,
+                    "prerelease": tag.contains('-'),
+                    "assets": assets,
+                })
+            })
+            .collect()
+    }
+
+    /// A commit in the format of the GitHub API, which Forgejo copied
+    fn commit(&self, web: &Url, oid: &str, message: &str) -> serde_json::Value {
+        serde_json::json!({
+            "sha": oid,
+            "html_url": web.join(&format!("{}/commit/{oid}", self.path)).unwrap().as_str(),
+            "commit": {
+                "message": message,
+                "committer": { "date": commit_date(oid).to_string() },
+            },
+        })
+    }
+
+    /// The commits between two revisions in the format of the GitHub API, which Forgejo copied
+    ///
+    /// The history is not modelled, there is a single commit: the new revision itself.
+    fn compare(&self, web: &Url, old: &str, new: &str) -> Vec<serde_json::Value> {
+        let (old, new) = (self.resolve(old), self.resolve(new));
+        if old == new {
+            return Vec::new();
+        }
+        vec![self.commit(
+            web,
+            new,
+            &format!("Update {} to {new}\n\nSince {old}", self.path),
+        )]
+    }
+
+    /// The commits of a branch which are not newer than `until`, the latest first
+    ///
+    /// The history of all branches is made of all commits of the repository that are not newer
+    /// than the head of the branch, see [`COMMIT_AGES`].
+    fn history(&self, branch: &str, until: Option<Timestamp>) -> Vec<&'static str> {
+        let head = commit_date(self.resolve(branch));
+        let mut commits: Vec<_> = self
+            .refs
+            .iter()
+            .map(|(_, oid, peeled)| peeled.unwrap_or(oid))
+            .filter(|oid| {
+                let date = commit_date(oid);
+                date <= head && until.is_none_or(|until| date <= until)
+            })
+            .collect();
+        commits.sort_by_key(|oid| (std::cmp::Reverse(commit_date(oid)), *oid));
+        commits.dedup();
+        commits
+    }
+
+    /// [`Repo::history`] in the format of the GitHub API, which Forgejo copied
+    fn commits(&self, web: &Url, request: &Request, limit: &str) -> Response {
+        let branch = request.query_param("sha").unwrap_or(self.head.into());
+        let until = request
+            .query_param("until")
+            .map(|until| until.parse().unwrap());
+        let limit = request
+            .query_param(limit)
+            .map_or(usize::MAX, |limit| limit.parse().unwrap());
+        Response::json(
+            self.history(&branch, until)
+                .into_iter()
+                .take(limit)
+                .map(|oid| self.commit(web, oid, &format!("Commit {oid}")))
+                .collect(),
+        )
+    }
+
+    /// A source archive of a revision, as served by the forges
+    fn archive(&self, rev: &str) -> Response {
+        let oid = self.resolve(rev);
+        let name = self.path.rsplit('/').next().unwrap();
+        Response::ok(
+            "application/x-gzip",
+            tarball(
+                &format!("{name}-{oid}"),
+                &format!("{} at {}\n", self.path, oid),
+                Compression::Gzip,
+            ),
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Compression {
+    Gzip,
+    Xz,
+}
+
+/// Build a tarball with a single top-level directory containing a `README`
+fn tarball(directory: &str, readme: &str, compression: Compression) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Directory);
+    header.set_mode(0o755);
+    header.set_size(0);
+    builder
+        .append_data(&mut header, format!("{directory}/"), std::io::empty())
+        .unwrap();
+    let mut header = tar::Header::new_gnu();
+    header.set_mode(0o644);
+    header.set_size(readme.len() as u64);
+    builder
+        .append_data(
+            &mut header,
+            format!("{directory}/README"),
+            readme.as_bytes(),
+        )
+        .unwrap();
+    let tar = builder.into_inner().unwrap();
+
+    match compression {
+        Compression::Gzip => {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&tar).unwrap();
+            encoder.finish().unwrap()
+        },
+        Compression::Xz => {
+            let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+            encoder.write_all(&tar).unwrap();
+            encoder.finish().unwrap()
+        },
+    }
+}
+
+fn pkt_line(out: &mut Vec<u8>, line: &str) {
+    out.extend_from_slice(format!("{:04x}{}", line.len() + 4, line).as_bytes());
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum GitProtocol {
+    /// Protocol v2 if the client asks for it, v0 otherwise
+    Smart,
+    /// Always protocol v0
+    V0,
+    /// The dumb HTTP protocol
+    Dumb,
+}
+
+/// Serve a fixture repository over HTTP
+///
+/// Only listing refs is supported, as fetching objects is done by `nix-prefetch-git`.
+fn git_repository(path: &str, request: &Request, protocol: GitProtocol) -> Option<Response> {
+    let (repo, endpoint) = path.split_once(".git/")?;
+    let repo = find_repo(repo)?;
+
+    const ADVERTISEMENT: &str = "application/x-git-upload-pack-advertisement";
+    match (request.method.as_str(), endpoint, protocol) {
+        ("GET", "info/refs", GitProtocol::Dumb) => {
+            let mut body = String::new();
+            for (name, oid, peeled) in repo.refs {
+                body += &format!("{oid}\t{name}\n");
+                if let Some(peeled) = peeled {
+                    body += &format!("{peeled}\t{name}^{{}}\n");
+                }
+            }
+            Some(Response::ok("text/plain", body))
+        },
+        ("GET", "HEAD", GitProtocol::Dumb) => Some(Response::ok(
+            "text/plain",
+            format!("ref: refs/heads/{}\n", repo.head),
+        )),
+        ("GET", "info/refs", _) if request.query == "service=git-upload-pack" => {
+            let mut body = Vec::new();
+            pkt_line(&mut body, "# service=git-upload-pack\n");
+            body.extend_from_slice(b"0000");
+            if protocol == GitProtocol::Smart && request.header("Git-Protocol") == Some("version=2")
+            {
+                pkt_line(&mut body, "version 2\n");
+                pkt_line(&mut body, "agent=git/npins-test\n");
+                pkt_line(&mut body, "ls-refs=unborn\n");
+                pkt_line(&mut body, "fetch=shallow\n");
+                pkt_line(&mut body, "object-format=sha1\n");
+            } else {
+                pkt_line(
+                    &mut body,
+                    &format!(
+                        "{} HEAD\0multi_ack symref=HEAD:refs/heads/{} agent=git/npins-test\n",
+                        repo.head_oid(),
+                        repo.head
+                    ),
+                );
+                for (name, oid, peeled) in repo.refs {
+                    pkt_line(&mut body, &format!("{oid} {name}\n"));
+                    if let Some(peeled) = peeled {
+                        pkt_line(&mut body, &format!("{peeled} {name}^{{}}\n"));
+                    }
+                }
+            }
+            body.extend_from_slice(b"0000");
+            Some(Response::ok(ADVERTISEMENT, body))
+        },
+        ("POST", "git-upload-pack", GitProtocol::Smart) => Some(ls_refs(repo, &request.body)),
+        _ => None,
+    }
+}
+
+/// Answer a protocol v2 `ls-refs` command
+fn ls_refs(repo: &Repo, request: &[u8]) -> Response {
+    let mut lines = Vec::new();
+    let mut rest = request;
+    while rest.len() >= 4 {
+        let length = usize::from_str_radix(std::str::from_utf8(&rest[..4]).unwrap(), 16).unwrap();
+        if length < 4 {
+            rest = &rest[4..];
+            continue;
+        }
+        lines.push(String::from_utf8(rest[4..length].to_vec()).unwrap());
+        rest = &rest[length..];
+    }
+    let lines: Vec<&str> = lines.iter().map(|line| line.trim_end()).collect();
+
+    if !lines.contains(&"command=ls-refs") {
+        return Response::status(400);
+    }
+    let peel = lines.contains(&"peel");
+    let symrefs = lines.contains(&"symrefs");
+    let prefixes: Vec<&str> = lines
+        .iter()
+        .filter_map(|line| line.strip_prefix("ref-prefix "))
+        .collect();
+    let matches =
+        |name: &str| prefixes.is_empty() || prefixes.iter().any(|prefix| name.starts_with(prefix));
+
+    let mut body = Vec::new();
+    if matches("HEAD") {
+        let mut line = format!("{} HEAD", repo.head_oid());
+        if symrefs {
+            line += &format!(" symref-target:refs/heads/{}", repo.head);
+        }
+        pkt_line(&mut body, &format!("{line}\n"));
+    }
+    for (name, oid, peeled) in repo.refs {
+        if !matches(name) {
+            continue;
+        }
+        let mut line = format!("{oid} {name}");
+        if let (true, Some(peeled)) = (peel, peeled) {
+            line += &format!(" peeled:{peeled}");
+        }
+        pkt_line(&mut body, &format!("{line}\n"));
+    }
+    body.extend_from_slice(b"0000");
+    Response::ok("application/x-git-upload-pack-result", body)
+}
+
+fn decode(segment: &str) -> String {
+    percent_encoding::percent_decode_str(segment)
+        .decode_utf8()
+        .unwrap()
+        .into_owned()
+}
+
+/// Split off the `{owner}/{repo}` part of a path
+fn split_repo(path: &str) -> Option<(&'static Repo, &str)> {
+    let mut parts = path.splitn(3, '/');
+    let (owner, repo, rest) = (parts.next()?, parts.next()?, parts.next()?);
+    Some((find_repo(&format!("{owner}/{repo}"))?, rest))
+}
+
+fn github(request: &Request) -> Response {
+    let path = request.path.trim_start_matches('/');
+    if let Some(response) = git_repository(path, request, GitProtocol::Smart) {
+        return response;
+    }
+    /* /{owner}/{repo}/archive/{rev}.tar.gz, where rev may be a full ref name */
+    if let Some((repo, rest)) = split_repo(path)
+        && let Some(rev) = rest
+            .strip_prefix("archive/")
+            .and_then(|rev| rev.strip_suffix(".tar.gz"))
+    {
+        return repo.archive(rev);
+    }
+    Response::status(404)
+}
+
+fn github_api(request: &Request) -> Response {
+    let path = request.path.trim_start_matches('/');
+    if path.starts_with("repos/rate-limited/") {
+        static SECONDARY_LIMIT_HIT: AtomicBool = AtomicBool::new(false);
+        if request.header("Authorization") != Some(format!("Bearer {GITHUB_TOKEN}").as_str()) {
+            let reset = SystemTime::now() + Duration::from_secs(3600);
+            return Response {
+                status: 403,
+                headers: vec![
+                    ("X-RateLimit-Limit", "60".into()),
+                    ("X-RateLimit-Remaining", "0".into()),
+                    (
+                        "X-RateLimit-Reset",
+                        reset
+                            .duration_since(SystemTime::UNIX_EPOCH)
+                            .unwrap()
+                            .as_secs()
+                            .to_string(),
+                    ),
+                ],
+                body: b"API rate limit exceeded".to_vec(),
+            };
+        }
+        if !SECONDARY_LIMIT_HIT.swap(true, Ordering::SeqCst) {
+            return Response {
+                status: 429,
+                headers: vec![("Retry-After", "0".into())],
+                body: Vec::new(),
+            };
+        }
+    }
+    /* /repos/{owner}/{repo}/tarball/refs/tags/{tag} redirects to the archive, like on GitHub */
+    if let Some((repo, rest)) = path.strip_prefix("repos/").and_then(split_repo)
+        && let Some(tag) = rest.strip_prefix("tarball/refs/tags/")
+    {
+        return Response::redirect(
+            services()
+                .github
+                .join(&format!("{}/archive/refs/tags/{tag}.tar.gz", repo.path))
+                .unwrap(),
+        );
+    }
+    /* /repos/{owner}/{repo}/releases */
+    if let Some((repo, "releases")) = path.strip_prefix("repos/").and_then(split_repo) {
+        return paginate(
+            request,
+            &services().github_api,
+            repo.releases(),
+            Pagination::Link,
+        );
+    }
+    /* /repos/{owner}/{repo}/compare/{old}...{new} */
+    if let Some((repo, rest)) = path.strip_prefix("repos/").and_then(split_repo)
+        && let Some((old, new)) = rest
+            .strip_prefix("compare/")
+            .and_then(|range| range.split_once("..."))
+    {
+        return Response::json(
+            serde_json::json!({ "commits": repo.compare(&services().github, old, new) }),
+        );
+    }
+    /* /repos/{owner}/{repo}/commits and /repos/{owner}/{repo}/commits/{rev} */
+    if let Some((repo, rest)) = path.strip_prefix("repos/").and_then(split_repo) {
+        if rest == "commits" {
+            return repo.commits(&services().github, request, "per_page");
+        }
+        if let Some(rev) = rest.strip_prefix("commits/") {
+            let oid = repo.resolve(rev);
+            return Response::json(repo.commit(&services().github, oid, &format!("Commit {oid}")));
+        }
+    }
+    Response::status(404)
+}
+
+fn gitlab(request: &Request) -> Response {
+    let path = request.path.trim_start_matches('/');
+
+    if path.starts_with("private/") {
+        let expected = format!(
+            "Basic {}",
+            base64_encode(format!("oauth2:{GITLAB_PRIVATE_TOKEN}").as_bytes())
+        );
+        if request.header("Authorization") != Some(expected.as_str()) {
+            return Response::status(401);
+        }
+    }
+    if let Some(response) = git_repository(path, request, GitProtocol::Smart) {
+        return response;
+    }
+
+    match path.split('/').collect::<Vec<_>>().as_slice() {
+        ["api", "v4", "projects"] => Response::json(serde_json::json!([])),
+        [
+            "api",
+            "v4",
+            "projects",
+            project,
+            "repository",
+            "archive.tar.gz",
+        ] => {
+            let project = decode(project);
+            let authorized = !project.starts_with("private/")
+                || request.header("PRIVATE-TOKEN") == Some(GITLAB_PRIVATE_TOKEN);
+            match (find_repo(&project), request.query_param("sha")) {
+                (Some(_), _) if !authorized => Response::status(401),
+                (Some(repo), Some(sha)) => repo.archive(&sha),
+                _ => Response::status(404),
+            }
+        },
+        ["api", "v4", "projects", project, "repository", "compare"] => {
+            match (
+                find_repo(&decode(project)),
+                request.query_param("from"),
+                request.query_param("to"),
+            ) {
+                (Some(repo), Some(from), Some(to)) => Response::json(serde_json::json!({
+                    "commits": repo
+                        .compare(&services().gitlab, &from, &to)
+                        .into_iter()
+                        .map(|commit| {
+                            let message = commit["commit"]["message"].as_str().unwrap();
+                            serde_json::json!({
+                                "id": commit["sha"],
+                                "title": message.lines().next().unwrap(),
+                                "message": message,
+                                "web_url": commit["html_url"],
+                            })
+                        })
+                        .collect::<Vec<_>>(),
+                })),
+                _ => Response::status(404),
+            }
+        },
+        [
+            "api",
+            "v4",
+            "projects",
+            project,
+            "repository",
+            "commits",
+            rest @ ..,
+        ] => {
+            let Some(repo) = find_repo(&decode(project)) else {
+                return Response::status(404);
+            };
+            let commit = |oid: &str| {
+                serde_json::json!({
+                    "id": oid,
+                    "title": format!("Commit {oid}"),
+                    "web_url": services().gitlab.join(&format!("{}/-/commit/{oid}", repo.path)).unwrap().as_str(),
+                    "committed_date": commit_date(oid).to_string(),
+                })
+            };
+            if rest.is_empty() {
+                let branch = request.query_param("ref_name").unwrap_or(repo.head.into());
+                let until = request
+                    .query_param("until")
+                    .map(|until| until.parse().unwrap());
+                let limit = request
+                    .query_param("per_page")
+                    .map_or(usize::MAX, |limit| limit.parse().unwrap());
+                Response::json(
+                    repo.history(&branch, until)
+                        .into_iter()
+                        .take(limit)
+                        .map(commit)
+                        .collect(),
+                )
+            } else {
+                Response::json(commit(repo.resolve(&decode(&rest.join("/")))))
+            }
+        },
+        ["api", "v4", "projects", project, "releases"] => match find_repo(&decode(project)) {
+            Some(repo) => paginate(
+                request,
+                &services().gitlab,
+                repo.releases()
+                    .into_iter()
+                    .map(|release| {
+                        let links: Vec<_> = release["assets"]
+                            .as_array()
+                            .unwrap()
+                            .iter()
+                            .map(|asset| {
+                                serde_json::json!({
+                                    "name": asset["name"],
+                                    "url": asset["browser_download_url"],
+                                    "direct_asset_url": asset["browser_download_url"],
+                                })
+                            })
+                            .collect();
+                        serde_json::json!({
+                            "tag_name": release["tag_name"],
+                            "upcoming_release": false,
+                            "assets": { "links": links },
+                        })
+                    })
+                    .collect(),
+                Pagination::NextPage,
+            ),
+            None => Response::status(404),
+        },
+        _ => Response::status(404),
+    }
+}
+
+/// At most this many items are served per page, to make npins follow the pagination
+const PAGE_SIZE: usize = 2;
+
+/// How the next page is announced
+enum Pagination {
+    /// A `Link` header with the `next` relation, like on GitHub and Forgejo
+    Link,
+    /// The `X-Next-Page` header of GitLab
+    NextPage,
+}
+
+/// Serve the page of `items` selected by the `page` query parameter
+fn paginate(
+    request: &Request,
+    base: &Url,
+    items: Vec<serde_json::Value>,
+    pagination: Pagination,
+) -> Response {
+    let page: usize = request
+        .query_param("page")
+        .map_or(1, |page| page.parse().unwrap());
+    let start = (page - 1) * PAGE_SIZE;
+    let mut response = Response::json(items.iter().skip(start).take(PAGE_SIZE).cloned().collect());
+    if start + PAGE_SIZE < items.len() {
+        let next = page + 1;
+        match pagination {
+            Pagination::Link => {
+                let mut url = base.join(request.path.trim_start_matches('/')).unwrap();
+                url.query_pairs_mut().append_pair("page", &next.to_string());
+                response
+                    .headers
+                    .push(("Link", format!(r#"<{url}>; rel="next""#)));
+            },
+            Pagination::NextPage => response.headers.push(("X-Next-Page", next.to_string())),
+        }
+    } else if let Pagination::NextPage = pagination {
+        response.headers.push(("X-Next-Page", String::new()));
+    }
+    response
+}
+
+/// Minimal base64 encoder for the basic auth header
+fn base64_encode(input: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn forgejo(request: &Request) -> Response {
+    let path = request.path.trim_start_matches('/');
+    if let Some(response) = git_repository(path, request, GitProtocol::Smart) {
+        return response;
+    }
+    if path == "api/v1/settings/api" {
+        return Response::json(serde_json::json!({ "max_response_items": 50 }));
+    }
+
+    /* /{owner}/{repo}/archive/{rev}.tar.gz and /api/v1/repos/{owner}/{repo}/archive/{tag}.tar.gz */
+    if let Some((repo, rest)) = split_repo(path.strip_prefix("api/v1/repos/").unwrap_or(path))
+        && let Some(rev) = rest
+            .strip_prefix("archive/")
+            .and_then(|rev| rev.strip_suffix(".tar.gz"))
+    {
+        return repo.archive(rev);
+    }
+    /* /api/v1/repos/{owner}/{repo}/releases */
+    if let Some((repo, "releases")) = path.strip_prefix("api/v1/repos/").and_then(split_repo) {
+        return paginate(
+            request,
+            &services().forgejo,
+            repo.releases(),
+            Pagination::Link,
+        );
+    }
+    /* /api/v1/repos/{owner}/{repo}/compare/{old}...{new} */
+    if let Some((repo, rest)) = path.strip_prefix("api/v1/repos/").and_then(split_repo)
+        && let Some((old, new)) = rest
+            .strip_prefix("compare/")
+            .and_then(|range| range.split_once("..."))
+    {
+        return Response::json(
+            serde_json::json!({ "commits": repo.compare(&services().forgejo, old, new) }),
+        );
+    }
+    /* /api/v1/repos/{owner}/{repo}/commits and /api/v1/repos/{owner}/{repo}/git/commits/{rev} */
+    if let Some((repo, rest)) = path.strip_prefix("api/v1/repos/").and_then(split_repo) {
+        if rest == "commits" {
+            return repo.commits(&services().forgejo, request, "limit");
+        }
+        if let Some(rev) = rest.strip_prefix("git/commits/") {
+            let oid = repo.resolve(rev);
+            return Response::json(repo.commit(&services().forgejo, oid, &format!("Commit {oid}")));
+        }
+    }
+    Response::status(404)
+}
+
+fn git(request: &Request) -> Response {
+    let path = request.path.trim_start_matches('/');
+    let response = if let Some(path) = path.strip_prefix("v0/") {
+        git_repository(path, request, GitProtocol::V0)
+    } else if let Some(path) = path.strip_prefix("dumb/") {
+        git_repository(path, request, GitProtocol::Dumb)
+    } else {
+        git_repository(path, request, GitProtocol::Smart)
+    };
+    response.unwrap_or_else(|| Response::status(404))
+}
+
+/// PyPi with its JSON API below `/pypi/`, and the JSON simple API (PEP 691) below `/simple/`
+fn pypi(request: &Request) -> Response {
+    let path = request.path.trim_start_matches('/');
+    let (simple, name) = match path.strip_prefix("simple/") {
+        Some(path) => (true, path.strip_suffix('/')),
+        None => (
+            false,
+            path.strip_prefix("pypi/")
+                .and_then(|path| path.strip_suffix("/json")),
+        ),
+    };
+    let Some((name, versions)) =
+        name.and_then(|name| PACKAGES.iter().find(|(package, _)| *package == name))
+    else {
+        return Response::status(404);
+    };
+
+    /* Only the parts of https://warehouse.pypa.io/api-reference/json.html that npins needs */
+    let files = |version: &str| {
+        let uploaded = uploaded(versions, version);
+        let yanked = YANKED_PACKAGES
+            .iter()
+            .find(|(package, v, _)| package == name && *v == version)
+            .map(|(_, _, reason)| reason);
+        let files: Vec<(String, &str)> = match WHEELS.iter().find(|(package, _)| package == name) {
+            Some((_, tags)) => tags
+                .iter()
+                .map(|tag| {
+                    let python = tag.split('-').next().unwrap();
+                    (format!("{name}-{version}-{tag}.whl"), python)
+                })
+                .collect(),
+            None => vec![(format!("{name}-{version}.tar.gz"), "source")],
+        };
+        files
+            .into_iter()
+            .map(|(filename, python_version)| {
+                serde_json::json!({
+                    "upload_time_iso_8601": uploaded.to_string(),
+                    "digests": { "sha256": sha256_hex(&format!("{filename}\n")) },
+                    "filename": filename,
+                    "python_version": python_version,
+                    "url": services().files.join(&format!("packages/{filename}")).unwrap().as_str(),
+                    "yanked": yanked.is_some(),
+                    "yanked_reason": yanked,
+                })
+            })
+            .collect::<Vec<_>>()
+    };
+
+    if simple {
+        /* https://packaging.python.org/en/latest/specifications/simple-repository-api/ */
+        if request.header("accept") != Some("application/vnd.pypi.simple.v1+json") {
+            return Response::ok("text/html", "<!DOCTYPE html>");
+        }
+        let files = versions
+            .iter()
+            .flat_map(|version| files(version))
+            .map(|file| {
+                serde_json::json!({
+                    "filename": file["filename"],
+                    /* Relative to the page, like PyPi itself does */
+                    "url": format!("../../files/{}", file["filename"].as_str().unwrap()),
+                    "hashes": file["digests"],
+                    "yanked": file["yanked_reason"].as_str().map_or(serde_json::Value::Bool(false), Into::into),
+                    "upload-time": file["upload_time_iso_8601"],
+                })
+            })
+            .collect::<Vec<_>>();
+        return Response::json(serde_json::json!({
+            "meta": { "api-version": "1.1" },
+            "name": name,
+            "files": files,
+            "versions": versions,
+        }));
+    }
+
+    let latest = versions.last().unwrap();
+    Response::json(serde_json::json!({
+        "info": { "version": latest },
+        "releases": versions
+            .iter()
+            .map(|version| (version.to_string(), files(version).into()))
+            .collect::<serde_json::Map<_, _>>(),
+        "urls": files(latest),
+    }))
+}
+
+/// When a version was uploaded, see [`PACKAGES`]
+fn uploaded(versions: &[&str], version: &str) -> Timestamp {
+    let position = versions.iter().rev().position(|v| *v == version).unwrap() as u64;
+    days_ago(LATEST_UPLOAD_AGE + position * UPLOAD_INTERVAL)
+}
+
+/// A sparse registry index. The crates themselves are served by [`files`].
+///
+/// The default index also serves the `versions` endpoint of the crates.io web API below `/api/`.
+fn crates_index(request: &Request) -> Response {
+    let path = request.path.trim_start_matches('/');
+    if let Some(name) = path
+        .strip_prefix("api/v1/crates/")
+        .and_then(|path| path.strip_suffix("/versions"))
+    {
+        let Some((_, versions)) = CRATES.iter().find(|(crate_name, _)| *crate_name == name) else {
+            return Response::status(404);
+        };
+        let numbers: Vec<&str> = versions.iter().map(|(version, _)| *version).collect();
+        return Response::json(serde_json::json!({
+            "versions": versions
+                .iter()
+                .map(|(version, yanked)| serde_json::json!({
+                    "num": version,
+                    "yanked": yanked,
+                    "created_at": uploaded(&numbers, version).to_string(),
+                }))
+                .collect::<Vec<_>>(),
+        }));
+    }
+
+    let (root, path) = match path.strip_prefix("custom/") {
+        Some(path) => ("custom/", path),
+        None => ("", path),
+    };
+    let download_path = |name: &str, version: &str| match root {
+        "" => format!("crates/{name}/{version}/download"),
+        _ => format!("custom/{name}-{version}.crate"),
+    };
+
+    if path == "config.json" {
+        let dl = match root {
+            "" => services().files.join("crates").unwrap().to_string(),
+            _ => format!("{}custom/{{crate}}-{{version}}.crate", services().files),
+        };
+        return Response::json(match root {
+            "" => serde_json::json!({ "dl": dl, "api": services().crates_index.as_str() }),
+            _ => serde_json::json!({ "dl": dl }),
+        });
+    }
+
+    /* Only the parts of https://doc.rust-lang.org/cargo/reference/registry-index.html that npins needs */
+    let index_path = |name: &str| match name.len() {
+        1 | 2 => format!("{}/{name}", name.len()),
+        3 => format!("3/{}/{name}", &name[..1]),
+        _ => format!("{}/{}/{name}", &name[..2], &name[2..4]),
+    };
+    let Some((name, versions)) = CRATES.iter().find(|(name, _)| index_path(name) == path) else {
+        return Response::status(404);
+    };
+    let lines: Vec<String> = versions
+        .iter()
+        .map(|(version, yanked)| {
+            serde_json::json!({
+                "name": name,
+                "vers": version,
+                "deps": [],
+                "cksum": sha256_hex(&format!("{}\n", download_path(name, version))),
+                "features": {},
+                "yanked": yanked,
+            })
+            .to_string()
+        })
+        .collect();
+    Response::ok("text/plain", lines.join("\n"))
+}
+
+/// The npm registry. Tarballs are served by [`files`].
+fn npm(request: &Request) -> Response {
+    use sha2::Digest;
+
+    let name = decode(request.path.trim_start_matches('/'));
+    let Some(NpmPackage {
+        name,
+        versions,
+        dist_tags,
+    }) = NPM_PACKAGES.iter().find(|package| package.name == name)
+    else {
+        return Response::status(404);
+    };
+
+    /* Only the parts of https://github.com/npm/registry/blob/main/docs/REGISTRY-API.md that npins needs */
+    let basename = name.rsplit('/').next().unwrap();
+    let version = |version: &str| {
+        let path = format!("npm/{name}/-/{basename}-{version}.tgz");
+        let content = format!("{path}\n");
+        serde_json::json!({
+            "name": name,
+            "version": version,
+            "dist": {
+                "tarball": services().files.join(&path).unwrap().as_str(),
+                "integrity": format!("sha512-{}", base64_encode(&sha2::Sha512::digest(content.as_bytes()))),
+            },
+        })
+    };
+    Response::json(serde_json::json!({
+        "name": name,
+        "dist-tags": dist_tags
+            .iter()
+            .map(|(tag, version)| (tag.to_string(), serde_json::json!(version)))
+            .collect::<serde_json::Map<_, _>>(),
+        "versions": versions
+            .iter()
+            .map(|v| (v.to_string(), version(v)))
+            .collect::<serde_json::Map<_, _>>(),
+        "time": versions
+            .iter()
+            .map(|v| (v.to_string(), serde_json::json!(uploaded(versions, v).to_string())))
+            .chain([
+                ("created".into(), serde_json::json!(uploaded(versions, versions[0]).to_string())),
+                ("modified".into(), serde_json::json!(days_ago(1).to_string())),
+            ])
+            .collect::<serde_json::Map<_, _>>(),
+    }))
+}
+
+fn sha256_hex(data: &str) -> String {
+    use sha2::Digest;
+    sha2::Sha256::digest(data.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn channels(request: &Request) -> Response {
+    let path = request.path.trim_start_matches('/');
+    let Some((name, artifact)) = path.split_once('/') else {
+        return Response::status(404);
+    };
+    match CHANNELS.iter().find(|(channel, _)| *channel == name) {
+        Some((_, release)) => Response::redirect(
+            services()
+                .files
+                .join(&format!("{release}/{artifact}"))
+                .unwrap(),
+        ),
+        None => Response::status(404),
+    }
+}
+
+/// Serve generated files. Tarballs contain a `README` with their path, everything else is served as text.
+///
+/// `/latest.tar.gz` redirects to `/v1.0.0.tar.gz`.
+fn files(request: &Request) -> Response {
+    let path = request.path.trim_start_matches('/');
+    if path == "latest.tar.gz" {
+        return Response::redirect(services().files.join("v1.0.0.tar.gz").unwrap());
+    }
+
+    let directory = path.rsplit('/').next().unwrap().replace('.', "-");
+    let readme = format!("{path}\n");
+    if path.ends_with(".tar.gz") {
+        Response::ok(
+            "application/x-gzip",
+            tarball(&directory, &readme, Compression::Gzip),
+        )
+    } else if path.ends_with(".tar.xz") {
+        Response::ok(
+            "application/x-xz",
+            tarball(&directory, &readme, Compression::Xz),
+        )
+    } else {
+        Response::ok("text/plain", readme)
+    }
+}
@@ -0,0 +1,31 @@
+//! Container pins against the `nix-prefetch-docker` stand-in, see [`common`]
+
+mod common;
+
+use anyhow::Result;
+use libnpins::Updatable;
+use libnpins::container::{ContainerHash, ContainerVersion, Pin};
+
+#[tokio::test]
+async fn test_container_update() -> Result<()> {
+    let services = common::setup();
+    let pin = Pin {
+        image_name: "docker.io/dperson/torproxy".into(),
+        image_tag: "latest".into(),
+        arch: None,
+    };
+    let version = pin.update(&services.config, None).await?;
+    assert_eq!(
+        version,
+        ContainerVersion {
+            image_digest: common::CONTAINER_DIGEST.into(),
+        }
+    );
+    assert_eq!(
+        pin.fetch(&services.config, &version).await?,
+        ContainerHash {
+            hash: common::CONTAINER_HASH.into(),
+        }
+    );
+    Ok(())
+}
@@ -0,0 +1,110 @@
+//! Crate pins against the emulated sparse index, see [`common`]
+
+mod common;
+
+use anyhow::Result;
+use libnpins::crates::Pin;
+use libnpins::{GenericUrlHashes, GenericVersion, Updatable};
+use nix_compat::nixhash::NixHash;
+
+fn pin(name: &str) -> Pin {
+    Pin {
+        name: name.into(),
+        index: None,
+        pre_releases: false,
+        constraint: None,
+        min_age: None,
+    }
+}
+
+#[tokio::test]
+async fn test_crate_update() -> Result<()> {
+    let services = common::setup();
+    let pin = pin("rustc-serialize");
+    let version = pin.update(&services.config, None).await?;
+    assert_eq!(
+        version,
+        GenericVersion {
+            version: "0.3.25".into(),
+            date: None,
+        }
+    );
+    assert_eq!(
+        pin.fetch(&services.config, &version).await?,
+        GenericUrlHashes {
+            url: services
+                .files
+                .join("crates/rustc-serialize/0.3.25/download")?,
+            hash: NixHash::from_sri("sha256-REmErJMq74wnDdCUw1uwLKzvFJR5kg3XDNfRXml1vUs=").unwrap(),
+        }
+    );
+
+    /* Versions must not go backwards */
+    let newer = GenericVersion {
+        version: "0.3.26".into(),
+        date: None,
+    };
+    assert!(pin.update(&services.config, Some(&newer)).await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_crate_versions() -> Result<()> {
+    let services = common::setup();
+    let version = |pin: Pin| async move {
+        pin.update(&services.config, None)
+            .await
+            .map(|version| version.version)
+    };
+
+    assert_eq!(
+        version(Pin {
+            pre_releases: true,
+            ..pin("rustc-serialize")
+        })
+        .await?,
+        "0.4.0-alpha.1"
+    );
+    assert_eq!(
+        version(Pin {
+            constraint: Some("<0.3.25".into()),
+            ..pin("rustc-serialize")
+        })
+        .await?,
+        "0.3.24"
+    );
+    /* Yanked releases are skipped */
+    assert_eq!(version(pin("syn")).await?, "2.0.0");
+    assert!(version(pin("serde")).await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_crate_custom_index() -> Result<()> {
+    let services = common::setup();
+    let pin = Pin {
+        index: Some(services.crates_index.join("custom/")?),
+        ..pin("syn")
+    };
+    let version = pin.update(&services.config, None).await?;
+    assert_eq!(
+        pin.fetch(&services.config, &version).await?.url,
+        services.files.join("custom/syn-2.0.0.crate")?,
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_crate_name_spelling() -> Result<()> {
+    let services = common::setup();
+    /* Index paths are lowercase, but the download URL uses the name from the index */
+    let capitalized = pin("Syn");
+    let version = capitalized.update(&services.config, None).await?;
+    assert_eq!(
+        capitalized.fetch(&services.config, &version).await?.url,
+        services.files.join("crates/syn/2.0.0/download")?,
+    );
+
+    assert!(pin("sÿn").update(&services.config, None).await.is_err());
+    Ok(())
+}
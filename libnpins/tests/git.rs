@@ -0,0 +1,529 @@
+//! Git pins against the emulated forges, see [`common`]
+
+mod common;
+
+use anyhow::Result;
+use libnpins::cache::RequestCache;
+use libnpins::credentials::Credentials;
+use libnpins::git::{
+    GitPin, GitReleasePin, GitRevision, OptionalUrlHashes, ReleasePinHashes, RemoteInfo,
+    Repository, fetch_branch_head, fetch_default_branch, fetch_ref, fetch_tags,
+};
+use libnpins::version_scheme::VersionScheme;
+use libnpins::{Config, GenericVersion, Updatable};
+use nix_compat::nixhash::NixHash;
+
+/// Run a check against the same repository served over all supported protocols
+async fn for_all_protocols<F, Fut>(repo: &str, check: F) -> Result<()>
+where
+    F: Fn(&'static Config, url::Url) -> Fut,
+    Fut: std::future::Future<Output = Result<()>>,
+{
+    let services = common::setup();
+    for protocol in ["", "v0/", "dumb/"] {
+        let url = services.git.join(&format!("{protocol}{repo}.git"))?;
+        check(&services.config, url.clone())
+            .await
+            .map_err(|err| err.context(format!("Failed for {url}")))?;
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_fetch_branch() -> Result<()> {
+    for_all_protocols("oliverwatkins/swing_library", |config, url| async move {
+        assert_eq!(
+            fetch_branch_head(config, &url, "master").await?,
+            RemoteInfo::new(
+                "1edb0a9cebe046cc915a218c57dbf7f40739aeee",
+                "refs/heads/master"
+            ),
+        );
+        assert!(fetch_branch_head(config, &url, "main").await.is_err());
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_fetch_tags() -> Result<()> {
+    for_all_protocols("lix-project/lix", |config, url| async move {
+        /* Annotated tags resolve to the tag object, like `git ls-remote --refs` */
+        assert_eq!(
+            fetch_tags(config, &url).await?,
+            vec![
+                RemoteInfo::new(
+                    "2a4376be20d70feaa2b0e640c5041fb66ddc67ed",
+                    "refs/tags/2.90.0"
+                ),
+                RemoteInfo::new(
+                    "6f2d0e5b1a3c4e8d9f7b2a1c0e3d5f4b6a8c9e71",
+                    "refs/tags/2.90.1"
+                ),
+                RemoteInfo::new(
+                    "e2c4a6b8d0f1e3a5c7b9d1f3e5a7c9b1d3f5a7c9",
+                    "refs/tags/2.91.0-rc1"
+                ),
+            ],
+        );
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_fetch_ref() -> Result<()> {
+    for_all_protocols("jstutters/MidiOSC", |config, url| async move {
+        assert_eq!(
+            fetch_ref(config, &url, "refs/tags/v1.1").await?,
+            RemoteInfo::new("35be5b2b2c3431de1100996487d53134f658b866", "refs/tags/v1.1"),
+        );
+        /* Only exact matches count */
+        assert!(fetch_ref(config, &url, "v1.1").await.is_err());
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_fetch_default_branch() -> Result<()> {
+    for_all_protocols("lix-project/lix", |config, url| async move {
+        assert_eq!(fetch_default_branch(config, &url).await?, "main");
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_git_update() -> Result<()> {
+    let services = common::setup();
+    let pin = GitPin::new(
+        Repository::git(services.git.join("oliverwatkins/swing_library.git")?),
+        "master".into(),
+        false,
+    );
+    let version = pin.update(&services.config, None).await?;
+    assert_eq!(
+        version,
+        GitRevision::new("1edb0a9cebe046cc915a218c57dbf7f40739aeee".into())?,
+    );
+    assert_eq!(
+        pin.fetch(&services.config, &version).await?,
+        OptionalUrlHashes {
+            url: None,
+            hash: NixHash::from_sri(common::GIT_HASH).unwrap(),
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_git_release_update() -> Result<()> {
+    let services = common::setup();
+    let pin = GitReleasePin::new(
+        Repository::git(services.git.join("jstutters/MidiOSC.git")?),
+        false,
+        None,
+        None,
+        false,
+    );
+    let version = pin.update(&services.config, None).await?;
+    assert_eq!(
+        version,
+        GenericVersion {
+            version: "v1.1".into(),
+            date: None,
+        }
+    );
+    assert_eq!(
+        pin.fetch(&services.config, &version).await?,
+        ReleasePinHashes {
+            revision: "35be5b2b2c3431de1100996487d53134f658b866".into(),
+            url: None,
+            hash: NixHash::from_sri(common::GIT_HASH).unwrap(),
+        }
+    );
+
+    /* Versions must not go backwards */
+    let newer = GenericVersion {
+        version: "v1.2".into(),
+        date: None,
+    };
+    assert!(pin.update(&services.config, Some(&newer)).await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_git_release_pre_releases() -> Result<()> {
+    let services = common::setup();
+    let pin = GitReleasePin::new(
+        Repository::git(services.git.join("jstutters/MidiOSC.git")?),
+        true,
+        None,
+        None,
+        false,
+    );
+    assert_eq!(
+        pin.update(&services.config, None).await?,
+        GenericVersion {
+            version: "v2.0-beta".into(),
+            date: None,
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_github_update() -> Result<()> {
+    let services = common::setup();
+    let pin = GitPin::new(
+        Repository::github("oliverwatkins", "swing_library"),
+        "master".into(),
+        false,
+    );
+    let version = pin.update(&services.config, None).await?;
+    assert_eq!(
+        version,
+        GitRevision::new("1edb0a9cebe046cc915a218c57dbf7f40739aeee".into())?,
+    );
+    assert_eq!(
+        pin.fetch(&services.config, &version).await?,
+        OptionalUrlHashes {
+            url: Some(services.github.join(
+                "oliverwatkins/swing_library/archive/1edb0a9cebe046cc915a218c57dbf7f40739aeee.tar.gz"
+            )?),
+            hash: NixHash::from_sri("sha256-x/L6Bial+esNbdwXdFRMsDSrpNRTJYCp7HLFe1PSfWo=").unwrap(),
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_github_release_update() -> Result<()> {
+    let services = common::setup();
+    let pin = GitReleasePin::new(
+        Repository::github("jstutters", "MidiOSC"),
+        false,
+        None,
+        None,
+        false,
+    );
+    let version = pin.update(&services.config, None).await?;
+    assert_eq!(
+        version,
+        GenericVersion {
+            version: "v1.1".into(),
+            date: None,
+        }
+    );
+    assert_eq!(
+        pin.fetch(&services.config, &version).await?,
+        ReleasePinHashes {
+            revision: "35be5b2b2c3431de1100996487d53134f658b866".into(),
+            url: Some(
+                services
+                    .github_api
+                    .join("repos/jstutters/MidiOSC/tarball/refs/tags/v1.1")?
+            ),
+            hash: NixHash::from_sri("sha256-LJSpTOSONDKTgDDBg2n1+2wk5xUp9zp9nxKJ0UagjyE=").unwrap(),
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_github_release_tag_pattern() -> Result<()> {
+    let services = common::setup();
+    /* Without the pattern, v2.0-beta would be the latest pre-release */
+    let pin = GitReleasePin {
+        tag_pattern: Some(r"v(\d+)\.(\d+)".into()),
+        ..GitReleasePin::new(
+            Repository::github("jstutters", "MidiOSC"),
+            true,
+            None,
+            None,
+            false,
+        )
+    };
+    let old = GenericVersion {
+        version: "v1.0".into(),
+        date: None,
+    };
+    let version = pin.update(&services.config, Some(&old)).await?;
+    assert_eq!(
+        version,
+        GenericVersion {
+            version: "v1.1".into(),
+            date: None,
+        }
+    );
+    /* The full tag is pinned, so it is fetched just like without a pattern */
+    assert_eq!(
+        pin.fetch(&services.config, &version).await?,
+        ReleasePinHashes {
+            revision: "35be5b2b2c3431de1100996487d53134f658b866".into(),
+            url: Some(
+                services
+                    .github_api
+                    .join("repos/jstutters/MidiOSC/tarball/refs/tags/v1.1")?
+            ),
+            hash: NixHash::from_sri("sha256-LJSpTOSONDKTgDDBg2n1+2wk5xUp9zp9nxKJ0UagjyE=").unwrap(),
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_github_release_version_scheme() -> Result<()> {
+    let services = common::setup();
+    /* With plain string comparison, there is no notion of pre-releases */
+    let pin = GitReleasePin {
+        version_scheme: VersionScheme::Lexical,
+        ..GitReleasePin::new(
+            Repository::github("jstutters", "MidiOSC"),
+            false,
+            Some("<v3".into()),
+            None,
+            false,
+        )
+    };
+    assert_eq!(
+        pin.update(&services.config, None).await?,
+        GenericVersion {
+            version: "v2.0-beta".into(),
+            date: None,
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_forgejo_update() -> Result<()> {
+    let services = common::setup();
+    let pin = GitPin::new(
+        Repository::forgejo(services.forgejo.clone(), "lix-project", "lix"),
+        "release-2.90".into(),
+        false,
+    );
+    let version = pin.update(&services.config, None).await?;
+    assert_eq!(
+        version,
+        GitRevision::new("4bbdb2f5564b9b42bcaf0e1eec28325300f31c72".into())?,
+    );
+    assert_eq!(
+        pin.fetch(&services.config, &version).await?,
+        OptionalUrlHashes {
+            url: Some(
+                services.forgejo.join(
+                    "lix-project/lix/archive/4bbdb2f5564b9b42bcaf0e1eec28325300f31c72.tar.gz"
+                )?
+            ),
+            hash: NixHash::from_sri("sha256-CJz5itdX6Eowf2CLxAonuo2P6iGlC12qw5NE5DivjdE=").unwrap(),
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_forgejo_release_update() -> Result<()> {
+    let services = common::setup();
+    let pin = GitReleasePin::new(
+        Repository::forgejo(services.forgejo.clone(), "lix-project", "lix"),
+        false,
+        Some("<2.90.1".into()),
+        None,
+        false,
+    );
+    let version = pin.update(&services.config, None).await?;
+    assert_eq!(
+        version,
+        GenericVersion {
+            version: "2.90.0".into(),
+            date: None,
+        }
+    );
+    assert_eq!(
+        pin.fetch(&services.config, &version).await?,
+        ReleasePinHashes {
+            revision: "2a4376be20d70feaa2b0e640c5041fb66ddc67ed".into(),
+            url: Some(
+                services
+                    .forgejo
+                    .join("api/v1/repos/lix-project/lix/archive/2.90.0.tar.gz")?
+            ),
+            hash: NixHash::from_sri("sha256-75mPULE3WWtRpAjqF6KNm/bT5UIFk/MJIs6IOuafHvc=").unwrap(),
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_gitlab_update() -> Result<()> {
+    let services = common::setup();
+    let pin = GitPin::new(
+        Repository::gitlab(
+            "maxigaz/gitlab-dark".into(),
+            Some(services.gitlab.clone()),
+            None,
+        ),
+        "master".into(),
+        false,
+    );
+    let version = pin.update(&services.config, None).await?;
+    assert_eq!(
+        version,
+        GitRevision::new("e7145078163692697b843915a665d4f41139a65c".into())?,
+    );
+    assert_eq!(
+        pin.fetch(&services.config, &version).await?,
+        OptionalUrlHashes {
+            url: Some(services.gitlab.join(
+                "api/v4/projects/maxigaz%2Fgitlab-dark/repository/archive.tar.gz?sha=e7145078163692697b843915a665d4f41139a65c"
+            )?),
+            hash: NixHash::from_sri("sha256-iGRw8wQGlX3lgVodN7bCKuBxFZi75ye7VZWka9/pA8c=").unwrap(),
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_gitlab_release_update() -> Result<()> {
+    let services = common::setup();
+    let pin = GitReleasePin::new(
+        Repository::gitlab(
+            "maxigaz/gitlab-dark".into(),
+            Some(services.gitlab.clone()),
+            None,
+        ),
+        false,
+        None,
+        None,
+        false,
+    );
+    let version = pin.update(&services.config, None).await?;
+    assert_eq!(
+        version,
+        GenericVersion {
+            version: "v1.16.0".into(),
+            date: None,
+        }
+    );
+    assert_eq!(
+        pin.fetch(&services.config, &version).await?,
+        ReleasePinHashes {
+            revision: "d42ec2b04df9da97e465883fcd1f9a5d6e794027".into(),
+            url: Some(services.gitlab.join(
+                "api/v4/projects/maxigaz%2Fgitlab-dark/repository/archive.tar.gz?sha=v1.16.0"
+            )?),
+            hash: NixHash::from_sri("sha256-TkRPeDBj/4iAVH0UHUoCjDwFcDaZ86SrnT5wFfQp0E0=").unwrap(),
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_gitlab_credential() -> Result<()> {
+    let services = common::setup();
+    let pin = GitPin::new(
+        Repository::gitlab(
+            "private/secret-repo".into(),
+            Some(services.gitlab.clone()),
+            Some("work-gitlab".into()),
+        ),
+        "main".into(),
+        false,
+    );
+    /* The credential is unknown */
+    assert!(pin.update(&services.config, None).await.is_err());
+
+    let config = Config::builder()
+        .hosts(services.config.hosts().clone())
+        .credentials(Credentials::default().secret("work-gitlab", common::GITLAB_PRIVATE_TOKEN))
+        .build()?;
+    let version = pin.update(&config, None).await?;
+    assert_eq!(
+        version,
+        GitRevision::new("5c6b0bc1d85a5e2b3f1d8a9e7c4f2b1a0d9e8c7b".into())?,
+    );
+    let url = pin.fetch(&config, &version).await?.url.unwrap();
+    assert!(!url.as_str().contains(common::GITLAB_PRIVATE_TOKEN));
+    assert_eq!(
+        url.query(),
+        Some("sha=5c6b0bc1d85a5e2b3f1d8a9e7c4f2b1a0d9e8c7b")
+    );
+
+    /* Nothing about the credential ends up in the lock file */
+    assert!(!serde_json::to_string(&pin)?.contains(common::GITLAB_PRIVATE_TOKEN));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_request_cache() -> Result<()> {
+    let services = common::setup();
+    let config = common::marked(&services.config, "git-request-cache")
+        .with_request_cache(RequestCache::default());
+    let repository = Repository::git(services.git.join("lix-project/lix.git")?);
+    let main = GitPin::new(repository.clone(), "main".into(), false);
+    let main_with_submodules = GitPin::new(repository.clone(), "main".into(), true);
+    let release = GitPin::new(repository, "release-2.90".into(), false);
+
+    let (main, main_with_submodules, release) = tokio::join!(
+        main.update(&config, None),
+        main_with_submodules.update(&config, None),
+        release.update(&config, None),
+    );
+    assert_eq!(main?, main_with_submodules?);
+    assert_eq!(
+        release?,
+        GitRevision::new("4bbdb2f5564b9b42bcaf0e1eec28325300f31c72".into())?,
+    );
+
+    /* The tags are listed in `update` and looked up again in `fetch` */
+    let tagged = GitReleasePin::new(
+        Repository::git(services.git.join("lix-project/lix.git")?),
+        false,
+        None,
+        None,
+        false,
+    );
+    let version = tagged.update(&config, None).await?;
+    tagged.fetch(&config, &version).await?;
+
+    /* The first branch is queried on its own, then all branches and tags are listed once for the
+     * other lookups: two `info/refs` and two `ls-refs` requests
+     */
+    let requests = common::marked_requests("git-request-cache");
+    assert_eq!(requests.len(), 4, "{requests:?}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_repository_auto() -> Result<()> {
+    let services = common::setup();
+    assert_eq!(
+        Repository::git_auto(
+            &services.config,
+            services.gitlab.join("maxigaz/gitlab-dark")?
+        )
+        .await,
+        Repository::GitLab {
+            repo_path: "/maxigaz/gitlab-dark".into(),
+            server: services.gitlab.clone(),
+            credential: None,
+        },
+    );
+    assert_eq!(
+        Repository::git_auto(
+            &services.config,
+            services.forgejo.join("lix-project/lix.git")?
+        )
+        .await,
+        Repository::forgejo(services.forgejo.clone(), "lix-project", "lix"),
+    );
+    let url = services.git.join("lix-project/lix.git")?;
+    assert_eq!(
+        Repository::git_auto(&services.config, url.clone()).await,
+        Repository::git(url)
+    );
+    Ok(())
+}
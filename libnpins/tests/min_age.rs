@@ -0,0 +1,327 @@
+//! Minimum release age against the emulated forges and registries, see [`common`]
+
+mod common;
+
+use anyhow::Result;
+use libnpins::git::{GitPin, GitReleasePin, GitRevision, Repository};
+use libnpins::{Config, GenericVersion, NixPins, Updatable, crates, npm, pypi};
+use std::time::Duration;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn release_pin(repository: Repository, min_age: Option<u32>) -> GitReleasePin {
+    GitReleasePin {
+        min_age,
+        ..GitReleasePin::new(repository, false, None, None, false)
+    }
+}
+
+#[tokio::test]
+async fn test_github_branch_min_age() -> Result<()> {
+    let services = common::setup();
+    let mut pin = GitPin::new(
+        Repository::github("jstutters", "MidiOSC"),
+        "master".into(),
+        false,
+    );
+
+    /* The head is one day old, v2.0-beta two days and v1.1 30 days */
+    pin.min_age = Some(7);
+    let version = pin.update(&services.config, None).await?;
+    let revision = "35be5b2b2c3431de1100996487d53134f658b866";
+    assert_eq!(
+        version,
+        GitRevision::new(revision.into())?.with_date(common::commit_date(revision))
+    );
+
+    pin.min_age = Some(0);
+    let version = pin.update(&services.config, None).await?;
+    assert_eq!(
+        version,
+        GitRevision::new("a8d8ab5f0e1d1ac9c1b1eb8e6e7cfb1a3e0c4d25".into())?
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_gitlab_branch_global_min_age() -> Result<()> {
+    let services = common::setup();
+    let config = Config::builder()
+        .hosts(services.config.hosts().clone())
+        .min_age(DAY * 7)
+        .build()?;
+    let pin = GitPin::new(
+        Repository::gitlab(
+            "jstutters/MidiOSC".into(),
+            Some(services.gitlab.clone()),
+            None,
+        ),
+        "master".into(),
+        false,
+    );
+
+    let version = pin.update(&config, None).await?;
+    let revision = "35be5b2b2c3431de1100996487d53134f658b866";
+    assert_eq!(
+        version,
+        GitRevision::new(revision.into())?.with_date(common::commit_date(revision))
+    );
+
+    /* Don't go back to an older commit */
+    let old = GitRevision::new("a8d8ab5f0e1d1ac9c1b1eb8e6e7cfb1a3e0c4d25".into())?.with_date(
+        common::commit_date("a8d8ab5f0e1d1ac9c1b1eb8e6e7cfb1a3e0c4d25"),
+    );
+    assert_eq!(pin.update(&config, Some(&old)).await?, old);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_github_release_min_age() -> Result<()> {
+    let services = common::setup();
+    let repository = Repository::github("jstutters", "MidiOSC");
+
+    let pin = release_pin(repository.clone(), Some(7));
+    let version = pin.update(&services.config, None).await?;
+    assert_eq!(
+        version,
+        GenericVersion {
+            version: "v1.1".into(),
+            date: Some(common::commit_date(
+                "35be5b2b2c3431de1100996487d53134f658b866"
+            )),
+        }
+    );
+
+    /* v1.1 is too new now, but it is already pinned */
+    let pin = release_pin(repository, Some(60));
+    assert_eq!(pin.update(&services.config, Some(&version)).await?, version);
+    let version = pin.update(&services.config, None).await?;
+    assert_eq!(version.version, "v1.0");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_forgejo_release_min_age() -> Result<()> {
+    let services = common::setup();
+    let pin = release_pin(
+        Repository::forgejo(services.forgejo.clone(), "lix-project", "lix"),
+        Some(7),
+    );
+
+    /* 2.90.1 is three days old */
+    let version = pin.update(&services.config, None).await?;
+    assert_eq!(version.version, "2.90.0");
+    assert_eq!(
+        version.date,
+        Some(common::commit_date(
+            "2a4376be20d70feaa2b0e640c5041fb66ddc67ed"
+        ))
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_gitlab_release_min_age() -> Result<()> {
+    let services = common::setup();
+    let pin = release_pin(
+        Repository::gitlab(
+            "maxigaz/gitlab-dark".into(),
+            Some(services.gitlab.clone()),
+            None,
+        ),
+        Some(7),
+    );
+
+    /* v1.16.0 is two days old */
+    let version = pin.update(&services.config, None).await?;
+    assert_eq!(version.version, "v1.15.1");
+
+    let pin = release_pin(pin.repository, Some(1000));
+    let err = pin.update(&services.config, None).await.unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "No matching versions older than 1000 days found"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_plain_git_min_age() -> Result<()> {
+    let services = common::setup();
+    let pin = GitPin {
+        min_age: Some(7),
+        ..GitPin::new(
+            Repository::git(services.git.join("jstutters/MidiOSC.git")?),
+            "master".into(),
+            false,
+        )
+    };
+    assert!(pin.update(&services.config, None).await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pypi_min_age() -> Result<()> {
+    let services = common::setup();
+    let pin = |min_age| pypi::Pin {
+        name: "streamlit".into(),
+        index: None,
+        wheel: None,
+        pre_releases: false,
+        constraint: None,
+        min_age: Some(min_age),
+    };
+
+    /* 1.2.0 was uploaded two days ago, the ones before every 100 days */
+    let version = pin(7).update(&services.config, None).await?;
+    assert_eq!(version.version, "1.0.0");
+    assert!(version.date.unwrap().age() >= DAY * 102);
+
+    let version = pin(150).update(&services.config, None).await?;
+    assert_eq!(version.version, "0.89.0");
+
+    let latest = pin(0).update(&services.config, None).await?;
+    assert_eq!(
+        latest,
+        GenericVersion {
+            version: "1.2.0".into(),
+            date: None,
+        }
+    );
+    assert_eq!(
+        pin(7).update(&services.config, Some(&latest)).await?,
+        latest
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_crate_min_age() -> Result<()> {
+    let services = common::setup();
+    let pin = |name: &str, min_age| crates::Pin {
+        name: name.into(),
+        index: None,
+        pre_releases: false,
+        constraint: None,
+        min_age: Some(min_age),
+    };
+
+    /* 0.4.0-alpha.1 was published two days ago, 0.3.25 102 days ago and 0.3.24 202 days ago */
+    let version = pin("rustc-serialize", 7)
+        .update(&services.config, None)
+        .await?;
+    assert_eq!(version.version, "0.3.25");
+    assert!(version.date.unwrap().age() >= DAY * 102);
+
+    let version = pin("rustc-serialize", 150)
+        .update(&services.config, None)
+        .await?;
+    assert_eq!(version.version, "0.3.24");
+    /* Yanked releases are skipped */
+    let version = pin("syn", 7).update(&services.config, None).await?;
+    assert_eq!(version.version, "2.0.0");
+
+    /* Don't go back when the minimum age is raised */
+    assert_eq!(
+        pin("rustc-serialize", 150)
+            .update(&services.config, Some(&version_of("0.3.25")))
+            .await?,
+        version_of("0.3.25")
+    );
+
+    /* The custom index has no web API to tell the age of releases */
+    let pin = crates::Pin {
+        index: Some(services.crates_index.join("custom/")?),
+        ..pin("syn", 7)
+    };
+    assert!(pin.update(&services.config, None).await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_npm_global_min_age() -> Result<()> {
+    let services = common::setup();
+    let config = Config::builder()
+        .hosts(services.config.hosts().clone())
+        .min_age(DAY * 150)
+        .build()?;
+    let pin = |dist_tag: Option<&str>, constraint: Option<&str>| npm::Pin {
+        name: "left-pad".into(),
+        dist_tag: dist_tag.map(Into::into),
+        constraint: constraint.map(Into::into),
+        min_age: None,
+    };
+
+    /* 2.0.0-beta.1 was published two days ago, 1.3.0 102 days ago, then 1.1.0 and 1.0.0 */
+    let version = pin(None, None).update(&config, None).await?;
+    assert_eq!(version.version, "1.1.0");
+    assert!(version.date.unwrap().age() >= DAY * 202);
+
+    let version = pin(None, Some("<1.1")).update(&config, None).await?;
+    assert_eq!(version.version, "1.0.0");
+
+    /* The pre-release of the dist-tag is too new, so the latest release before it is pinned */
+    let version = npm::Pin {
+        min_age: Some(7),
+        ..pin(Some("next"), None)
+    }
+    .update(&config, None)
+    .await?;
+    assert_eq!(version.version, "1.3.0");
+
+    let version = npm::Pin {
+        min_age: Some(0),
+        ..pin(None, None)
+    }
+    .update(&config, None)
+    .await?;
+    assert_eq!(
+        version,
+        GenericVersion {
+            version: "1.3.0".into(),
+            date: None,
+        }
+    );
+    Ok(())
+}
+
+fn version_of(version: &str) -> GenericVersion {
+    GenericVersion {
+        version: version.into(),
+        date: None,
+    }
+}
+
+#[tokio::test]
+async fn test_min_age_without_recorded_date() -> Result<()> {
+    let services = common::setup();
+    let config = Config::builder()
+        .hosts(services.config.hosts().clone())
+        .min_age(DAY * 7)
+        .build()?;
+    /* A pin locked before minimum ages existed, on the head of the branch, which is one day old */
+    let mut pins = NixPins::from_json_versioned(serde_json::json!({
+        "pins": {
+            "midiosc": {
+                "type": "Git",
+                "repository": {
+                    "type": "GitHub",
+                    "owner": "jstutters",
+                    "repo": "MidiOSC"
+                },
+                "branch": "master",
+                "submodules": false,
+                "revision": "a8d8ab5f0e1d1ac9c1b1eb8e6e7cfb1a3e0c4d25",
+                "url": null,
+                "hash": common::GIT_HASH
+            }
+        },
+        "version": 10
+    }))?;
+    let pin = pins.pins.get_mut("midiosc").unwrap();
+
+    /* The pin must not go back to the older commit which satisfies the minimum age */
+    assert_eq!(pin.update(&config).await?, vec![]);
+    assert_eq!(pin.date(), None);
+    Ok(())
+}
@@ -0,0 +1,103 @@
+//! npm pins against the emulated registry, see [`common`]
+
+mod common;
+
+use anyhow::Result;
+use libnpins::npm::Pin;
+use libnpins::{GenericUrlHashes, GenericVersion, Updatable};
+use nix_compat::nixhash::NixHash;
+
+fn pin(name: &str) -> Pin {
+    Pin {
+        name: name.into(),
+        dist_tag: None,
+        constraint: None,
+        min_age: None,
+    }
+}
+
+#[tokio::test]
+async fn test_npm_update() -> Result<()> {
+    let services = common::setup();
+    let pin = pin("left-pad");
+    let version = pin.update(&services.config, None).await?;
+    assert_eq!(
+        version,
+        GenericVersion {
+            version: "1.3.0".into(),
+            date: None,
+        }
+    );
+    assert_eq!(
+        pin.fetch(&services.config, &version).await?,
+        GenericUrlHashes {
+            url: services.files.join("npm/left-pad/-/left-pad-1.3.0.tgz")?,
+            hash: NixHash::from_sri("sha512-Vkj2jVijrmdU9zkE6HzKxWRDq0NKR2SL3JRdafqyz9umN+o6IO4IZp7/g8h5vbvUj0DZS6YL3PwThsS1wI1ehg==").unwrap(),
+        }
+    );
+
+    /* Versions must not go backwards */
+    let newer = GenericVersion {
+        version: "1.4.0".into(),
+        date: None,
+    };
+    assert!(pin.update(&services.config, Some(&newer)).await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_npm_scoped() -> Result<()> {
+    let services = common::setup();
+    let pin = pin("@types/node");
+    let version = pin.update(&services.config, None).await?;
+    assert_eq!(version.version, "22.4.1");
+    assert_eq!(
+        pin.fetch(&services.config, &version).await?.url,
+        services.files.join("npm/@types/node/-/node-22.4.1.tgz")?,
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_npm_versions() -> Result<()> {
+    let services = common::setup();
+    let version = |pin: Pin| async move {
+        pin.update(&services.config, None)
+            .await
+            .map(|version| version.version)
+    };
+
+    assert_eq!(
+        version(Pin {
+            dist_tag: Some("next".into()),
+            ..pin("left-pad")
+        })
+        .await?,
+        "2.0.0-beta.1"
+    );
+    assert!(
+        version(Pin {
+            dist_tag: Some("beta".into()),
+            ..pin("left-pad")
+        })
+        .await
+        .is_err()
+    );
+    assert_eq!(
+        version(Pin {
+            constraint: Some("<1.3".into()),
+            ..pin("left-pad")
+        })
+        .await?,
+        "1.1.0"
+    );
+    assert_eq!(
+        version(Pin {
+            constraint: Some("<22".into()),
+            ..pin("@types/node")
+        })
+        .await?,
+        "20.1.0"
+    );
+    Ok(())
+}
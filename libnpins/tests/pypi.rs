@@ -0,0 +1,289 @@
+//! PyPi pins against the emulated registry, see [`common`]
+
+mod common;
+
+use anyhow::Result;
+use libnpins::cache::RequestCache;
+use libnpins::pypi::Pin;
+use libnpins::{GenericUrlHashes, GenericVersion, Updatable};
+use nix_compat::nixhash::NixHash;
+
+#[tokio::test]
+async fn test_pypi_update() -> Result<()> {
+    let services = common::setup();
+    let pin = Pin {
+        name: "gaiatest".into(),
+        index: None,
+        wheel: None,
+        pre_releases: false,
+        constraint: None,
+        min_age: None,
+    };
+    let version = pin.update(&services.config, None).await?;
+    assert_eq!(
+        version,
+        GenericVersion {
+            version: "0.34".into(),
+            date: None,
+        }
+    );
+    assert_eq!(
+        pin.fetch(&services.config, &version).await?,
+        GenericUrlHashes {
+            url: services.files.join("packages/gaiatest-0.34.tar.gz")?,
+            hash: NixHash::from_sri("sha256-PDFjfsG3u5qq31Kxvq/NhJ49dbDTO0kEVbCySjYokvI=").unwrap(),
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pypi_request_cache() -> Result<()> {
+    let services = common::setup();
+    let config = common::marked(&services.config, "pypi-request-cache")
+        .with_request_cache(RequestCache::default());
+    let pin = Pin {
+        name: "streamlit".into(),
+        index: None,
+        wheel: None,
+        pre_releases: false,
+        constraint: None,
+        min_age: None,
+    };
+    let version = pin.update(&config, None).await?;
+    pin.fetch(&config, &version).await?;
+
+    /* `fetch` reuses the metadata from `update` */
+    let requests = common::marked_requests("pypi-request-cache");
+    assert_eq!(
+        requests
+            .iter()
+            .filter(|path| path.starts_with("/pypi/"))
+            .count(),
+        1,
+        "{requests:?}"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pypi_update_upper_bound() -> Result<()> {
+    let services = common::setup();
+    let pin = Pin {
+        name: "streamlit".into(),
+        index: None,
+        wheel: None,
+        pre_releases: false,
+        constraint: Some("<1.0.0".into()),
+        min_age: None,
+    };
+    let version = pin.update(&services.config, None).await?;
+    assert_eq!(
+        version,
+        GenericVersion {
+            version: "0.89.0".into(),
+            date: None,
+        }
+    );
+    assert_eq!(
+        pin.fetch(&services.config, &version).await?,
+        GenericUrlHashes {
+            url: services.files.join("packages/streamlit-0.89.0.tar.gz")?,
+            hash: NixHash::from_sri("sha256-q7fIw3jDH6gaeDKxT50R6hCsVhpjEDAfYolFzAQb2AQ=").unwrap(),
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pypi_update_constraint() -> Result<()> {
+    let services = common::setup();
+    let pin = |constraint: &str| Pin {
+        name: "streamlit".into(),
+        index: None,
+        wheel: None,
+        pre_releases: false,
+        constraint: Some(constraint.into()),
+        min_age: None,
+    };
+    let version = |constraint| async move {
+        pin(constraint)
+            .update(&services.config, None)
+            .await
+            .map(|version| version.version)
+    };
+    assert_eq!(version(">=0.89, <2, !=1.2.0").await?, "1.0.0");
+    assert_eq!(version("~=0.88").await?, "0.89.0");
+    assert_eq!(version("~0.88").await?, "0.88.0");
+    assert!(version(">1.2").await.is_err());
+    assert!(version("1.2").await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pypi_pre_releases() -> Result<()> {
+    let services = common::setup();
+    let pin = |pre_releases| Pin {
+        name: "attrs".into(),
+        index: None,
+        wheel: None,
+        pre_releases,
+        constraint: None,
+        min_age: None,
+    };
+    /* 24.1.0 has been yanked */
+    assert_eq!(
+        pin(false).update(&services.config, None).await?.version,
+        "23.2.0"
+    );
+    assert_eq!(
+        pin(true).update(&services.config, None).await?.version,
+        "24.1.1.dev1"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pypi_yanked() -> Result<()> {
+    let services = common::setup();
+    let pin = Pin {
+        name: "attrs".into(),
+        index: None,
+        wheel: None,
+        pre_releases: false,
+        constraint: None,
+        min_age: None,
+    };
+    let yanked = GenericVersion {
+        version: "24.1.0".into(),
+        date: None,
+    };
+    let err = pin
+        .update(&services.config, Some(&yanked))
+        .await
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("24.1.0 of attrs has been yanked (Breaks installation on Python 3.8)"),
+        "{err}"
+    );
+    /* An explicitly pinned version can still be fetched */
+    assert_eq!(
+        pin.fetch(&services.config, &yanked).await?.url,
+        services.files.join("packages/attrs-24.1.0.tar.gz")?
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pypi_wheel() -> Result<()> {
+    let services = common::setup();
+    let pin = |wheel: Option<&str>| Pin {
+        name: "black".into(),
+        index: None,
+        wheel: wheel.map(Into::into),
+        pre_releases: false,
+        constraint: None,
+        min_age: None,
+    };
+    let url = |wheel| {
+        let pin = pin(Some(wheel));
+        async move {
+            let version = pin.update(&services.config, None).await?;
+            pin.fetch(&services.config, &version)
+                .await
+                .map(|hashes| hashes.url.to_string())
+        }
+    };
+    /* There is no source distribution */
+    assert!(pin(None).update(&services.config, None).await.is_err());
+    assert_eq!(
+        url("py3-none-any").await?,
+        format!("{}packages/black-24.2.0-py3-none-any.whl", services.files)
+    );
+    /* Compressed tag sets are expanded */
+    assert_eq!(
+        url("cp312-*-manylinux2014_*").await?,
+        format!(
+            "{}packages/black-24.2.0-cp312-cp312-manylinux_2_17_x86_64.manylinux2014_x86_64.whl",
+            services.files
+        )
+    );
+    assert_eq!(
+        url("black-{version}-*-win_amd64.whl").await?,
+        format!(
+            "{}packages/black-24.2.0-cp312-cp312-win_amd64.whl",
+            services.files
+        )
+    );
+    let err = url("cp312-cp312-*").await.unwrap_err();
+    assert!(
+        err.to_string()
+            .starts_with("Multiple wheels of version 24.2.0 match cp312-cp312-*"),
+        "{err}"
+    );
+    assert!(url("py3").await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pypi_simple_index() -> Result<()> {
+    let services = common::setup();
+    let pin = |name: &str, wheel: Option<&str>| Pin {
+        name: name.into(),
+        /* The trailing slash is optional */
+        index: Some(services.pypi.join("simple").unwrap()),
+        wheel: wheel.map(Into::into),
+        pre_releases: false,
+        constraint: None,
+        min_age: None,
+    };
+
+    /* The name is normalized */
+    let black = pin("Black", Some("py3-none-any"));
+    let version = black.update(&services.config, None).await?;
+    assert_eq!(version.version, "24.2.0");
+    assert_eq!(
+        black.fetch(&services.config, &version).await?,
+        GenericUrlHashes {
+            /* Relative to the index page */
+            url: services.pypi.join("files/black-24.2.0-py3-none-any.whl")?,
+            hash: NixHash::from_sri("sha256-SsP3zEhG/cJXsDD9acK/4lyOH5qhdvZVXbByc0wLsQE=").unwrap(),
+        }
+    );
+
+    /* Yanks are reported with their reason, like in the JSON API */
+    let attrs = pin("attrs", None);
+    assert_eq!(
+        attrs.update(&services.config, None).await?.version,
+        "23.2.0"
+    );
+    let yanked = GenericVersion {
+        version: "24.1.0".into(),
+        date: None,
+    };
+    let err = attrs
+        .update(&services.config, Some(&yanked))
+        .await
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("(Breaks installation on Python 3.8)"),
+        "{err}"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pypi_unknown_package() {
+    let services = common::setup();
+    let pin = Pin {
+        name: "does-not-exist".into(),
+        index: None,
+        wheel: None,
+        pre_releases: false,
+        constraint: None,
+        min_age: None,
+    };
+    assert!(pin.update(&services.config, None).await.is_err());
+}
@@ -0,0 +1,180 @@
+//! Release asset pins against the emulated forges, see [`common`]
+
+mod common;
+
+use anyhow::Result;
+use libnpins::credentials::Credentials;
+use libnpins::git::{GITHUB_CREDENTIAL, Repository};
+use libnpins::release_asset::ReleaseAssetPin;
+use libnpins::version_scheme::VersionScheme;
+use libnpins::{Config, GenericUrlHashes, GenericVersion, Updatable};
+use nix_compat::nixhash::NixHash;
+
+fn pin(repository: Repository, asset: &str) -> ReleaseAssetPin {
+    ReleaseAssetPin {
+        repository,
+        asset: asset.into(),
+        unpack: true,
+        pre_releases: false,
+        constraint: None,
+        release_prefix: None,
+        tag_pattern: None,
+        version_scheme: VersionScheme::default(),
+    }
+}
+
+#[tokio::test]
+async fn test_github_release_asset() -> Result<()> {
+    let services = common::setup();
+    let pin = pin(
+        Repository::github("jstutters", "MidiOSC"),
+        "MidiOSC-{version}-x86_64-linux.tar.gz",
+    );
+    let version = pin.update(&services.config, None).await?;
+    assert_eq!(
+        version,
+        GenericVersion {
+            version: "v1.1".into(),
+            date: None,
+        }
+    );
+    assert_eq!(
+        pin.fetch(&services.config, &version).await?,
+        GenericUrlHashes {
+            url: services
+                .files
+                .join("releases/jstutters/MidiOSC/v1.1/MidiOSC-1.1-x86_64-linux.tar.gz")?,
+            hash: NixHash::from_sri("sha256-ZszecySptZdHk1ctnkWlKxVJ2sGb/sgTGrRu8qeY4Ls=").unwrap(),
+        }
+    );
+
+    /* Versions must not go backwards */
+    let newer = GenericVersion {
+        version: "v1.2".into(),
+        date: None,
+    };
+    assert!(pin.update(&services.config, Some(&newer)).await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_gitlab_release_asset() -> Result<()> {
+    let services = common::setup();
+    let pin = pin(
+        Repository::gitlab(
+            "maxigaz/gitlab-dark".into(),
+            Some(services.gitlab.clone()),
+            None,
+        ),
+        "gitlab-dark-{version}-x86_64-linux.tar.gz",
+    );
+    let version = pin.update(&services.config, None).await?;
+    assert_eq!(version.version, "v1.16.0");
+    assert_eq!(
+        pin.fetch(&services.config, &version).await?.hash,
+        NixHash::from_sri("sha256-kRWLEy51ZNBJRqjbUA8RbMHisGuRYmLESQXcwZ8jEhI=").unwrap(),
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_forgejo_release_asset() -> Result<()> {
+    let services = common::setup();
+    let pin = |pre_releases| ReleaseAssetPin {
+        pre_releases,
+        ..pin(
+            Repository::forgejo(services.forgejo.clone(), "lix-project", "lix"),
+            "lix-{version}-aarch64-linux.tar.gz",
+        )
+    };
+    /* The latest stable release has no assets yet */
+    assert_eq!(
+        pin(false).update(&services.config, None).await?.version,
+        "2.90.0"
+    );
+    assert_eq!(
+        pin(true).update(&services.config, None).await?.version,
+        "2.91.0-rc1"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_release_asset_selection() -> Result<()> {
+    let services = common::setup();
+    let repository = Repository::github("jstutters", "MidiOSC");
+
+    let bounded = ReleaseAssetPin {
+        constraint: Some("<1.1".into()),
+        ..pin(repository.clone(), "MidiOSC-*-x86_64-linux.tar.gz")
+    };
+    assert_eq!(
+        bounded.update(&services.config, None).await?.version,
+        "v1.0"
+    );
+
+    let flat = ReleaseAssetPin {
+        unpack: false,
+        ..pin(repository.clone(), "*-{version}-aarch64-linux.tar.gz")
+    };
+    let version = flat.update(&services.config, None).await?;
+    assert_eq!(
+        flat.fetch(&services.config, &version).await?.url,
+        services
+            .files
+            .join("releases/jstutters/MidiOSC/v1.1/MidiOSC-1.1-aarch64-linux.tar.gz")?,
+    );
+
+    /* Ambiguous patterns only fail when fetching */
+    let ambiguous = pin(repository, "MidiOSC-{version}-*");
+    let version = ambiguous.update(&services.config, None).await?;
+    assert!(ambiguous.fetch(&services.config, &version).await.is_err());
+
+    let missing = pin(
+        Repository::github("jstutters", "MidiOSC"),
+        "MidiOSC-{version}-x86_64-darwin.tar.gz",
+    );
+    assert!(missing.update(&services.config, None).await.is_err());
+
+    let git = pin(
+        Repository::git(services.git.join("jstutters/MidiOSC.git")?),
+        "*",
+    );
+    assert!(git.update(&services.config, None).await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_github_rate_limit() -> Result<()> {
+    let services = common::setup();
+    let pin = pin(
+        Repository::github("rate-limited", "popular"),
+        "popular-{version}-x86_64-linux.tar.gz",
+    );
+
+    /* The reset is too far away to wait for it */
+    let err = pin.update(&services.config, None).await.unwrap_err();
+    let message = format!("{err:#}");
+    assert!(
+        message.contains("Rate limit of localhost exceeded"),
+        "{message}"
+    );
+    assert!(
+        message.contains("0 of 60 requests left, resets in"),
+        "{message}"
+    );
+
+    /* With a token, the secondary rate limit is retried */
+    let config = Config::builder()
+        .hosts(services.config.hosts().clone())
+        .credentials(Credentials::default().secret(GITHUB_CREDENTIAL, common::GITHUB_TOKEN))
+        .build()?;
+    assert_eq!(
+        pin.update(&config, None).await?,
+        GenericVersion {
+            version: "v3.1.0".into(),
+            date: None,
+        }
+    );
+    Ok(())
+}
@@ -0,0 +1,127 @@
+//! URL pins against the emulated file server, see [`common`]
+
+mod common;
+
+use anyhow::Result;
+use libnpins::urlpin::{LockedTarballVersion, MutableUrlPin, PerSystemUrlPin, UrlPin};
+use libnpins::{GenericHash, GenericUrlHashes, PerSystemHashes, Updatable};
+use nix_compat::nixhash::NixHash;
+
+#[tokio::test]
+async fn test_url_flat() -> Result<()> {
+    let services = common::setup();
+    let pin = UrlPin {
+        url: services.files.join("files/hello.txt")?,
+        unpack: false,
+    };
+    pin.update(&services.config, None).await?;
+    assert_eq!(
+        pin.fetch(&services.config, &()).await?,
+        GenericHash {
+            hash: NixHash::from_sri("sha256-RUTjYZ7AY60D1DkzDe49Pnm1K0gZW5hCLkz4Xd5KZLc=").unwrap(),
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_url_unpack() -> Result<()> {
+    let services = common::setup();
+    let pin = UrlPin {
+        url: services.files.join("archive/foo-1.0.tar.gz")?,
+        unpack: true,
+    };
+    pin.update(&services.config, None).await?;
+    assert_eq!(
+        pin.fetch(&services.config, &()).await?,
+        GenericHash {
+            hash: NixHash::from_sri("sha256-02HB3atfh5ESlZk01N9jMdVgAW93pRipCxpPuuFBA7I=").unwrap(),
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mutable_url() -> Result<()> {
+    let services = common::setup();
+    let pin = MutableUrlPin {
+        update_url: services.files.join("latest.tar.gz")?,
+        unpack: true,
+    };
+    let version = pin.update(&services.config, None).await?;
+    assert_eq!(
+        version,
+        LockedTarballVersion {
+            url: services.files.join("v1.0.0.tar.gz")?,
+        }
+    );
+    assert_eq!(
+        pin.fetch(&services.config, &version).await?,
+        GenericHash {
+            hash: NixHash::from_sri("sha256-s/8kxLRM+Oddb18hJTbBjYo8+bmEJKqnmubkdKMU3rU=").unwrap(),
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_per_system_url() -> Result<()> {
+    let services = common::setup();
+    let x86_64_linux = services.files.join("tools/hello-x86_64-linux.tar.gz")?;
+    let aarch64_darwin = services.files.join("tools/hello-macos-arm64.tar.gz")?;
+    let pin = PerSystemUrlPin {
+        urls: [
+            ("x86_64-linux".into(), x86_64_linux.clone()),
+            ("aarch64-darwin".into(), aarch64_darwin.clone()),
+        ]
+        .into(),
+        unpack: true,
+    };
+    pin.update(&services.config, None).await?;
+    assert_eq!(
+        pin.fetch(&services.config, &()).await?,
+        PerSystemHashes {
+            systems: [
+                (
+                    "x86_64-linux".into(),
+                    GenericUrlHashes {
+                        url: x86_64_linux,
+                        hash: NixHash::from_sri(
+                            "sha256-qCTrJQWnXDcRY2tcICTOAW6zP5MIjEG6CqlLJqi8cZ8="
+                        )
+                        .unwrap(),
+                    }
+                ),
+                (
+                    "aarch64-darwin".into(),
+                    GenericUrlHashes {
+                        url: aarch64_darwin,
+                        hash: NixHash::from_sri(
+                            "sha256-UPXS3PmtzQZrWcjhwC+W1jCxzjP/aVpdzVROa87eW/E="
+                        )
+                        .unwrap(),
+                    }
+                ),
+            ]
+            .into(),
+        }
+    );
+
+    /* All systems must succeed */
+    let pin = PerSystemUrlPin {
+        urls: [
+            (
+                "x86_64-linux".into(),
+                services.files.join("tools/hello-x86_64-linux.tar.gz")?,
+            ),
+            (
+                "i686-linux".into(),
+                services.files.join("tools/hello-i686-linux.txt")?,
+            ),
+        ]
+        .into(),
+        unpack: true,
+    };
+    assert!(pin.fetch(&services.config, &()).await.is_err());
+    Ok(())
+}
@@ -11,7 +11,7 @@
   # runtime dependencies
   nix-prefetch-git,
   nix-prefetch-docker,
-  git, # for git ls-remote
+  git, # for git ls-remote on non-HTTP remotes
 }:
 let
   paths = [
@@ -80,8 +80,8 @@ let
       installShellFiles
     ];
 
-    # (Almost) all tests require internet
-    doCheck = false;
+    # Tests requiring internet access are marked as ignored, the others run against local stand-ins
+    cargoTestFlags = [ "--workspace" ];
 
     postFixup = ''
       installShellCompletion --cmd npins \
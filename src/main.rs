@@ -10,21 +10,27 @@ use crossterm::{
 };
 use futures_util::{
     TryStreamExt,
-    stream::{self, StreamExt},
+    stream::{FuturesUnordered, StreamExt},
 };
 use std::{
     cell::{Cell, RefCell},
     collections::{BTreeMap, BTreeSet},
     fs::File,
     future,
-    io::{BufReader, IsTerminal, Write, stderr},
+    io::{BufReader, IsTerminal, Write, stderr, stdout},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
 };
 use url::{ParseError, Url};
 
 use crate::opts::*;
+use crate::output::PinResult;
+use libnpins::changelog::Changelog;
 use libnpins::*;
 
 mod opts;
+mod output;
 
 impl UpdateStrategy {
     /// Whether the latest version should be fetched
@@ -63,11 +69,47 @@ impl ChannelAddOpts {
     }
 }
 
+/// The `--constraint` of `add`, or the one given with the `--upper-bound` shorthand
+fn constraint(constraint: &Option<String>, upper_bound: &Option<String>) -> Option<String> {
+    constraint
+        .clone()
+        .or_else(|| upper_bound.as_ref().map(|bound| format!("<{bound}")))
+}
+
 impl GenericGitAddOpts {
     fn add(&self, repository: git::Repository) -> Result<Pin> {
+        if let Some(asset) = &self.asset {
+            anyhow::ensure!(
+                !matches!(repository, git::Repository::Git { .. }),
+                "Release assets are only supported for GitHub, GitLab and Forgejo repositories"
+            );
+            let pin = release_asset::ReleaseAssetPin {
+                repository,
+                asset: asset.clone(),
+                unpack: self.unpack,
+                pre_releases: self.pre_releases,
+                constraint: constraint(&self.constraint, &self.version_upper_bound),
+                release_prefix: self.release_prefix.clone(),
+                tag_pattern: self.tag_pattern.clone(),
+                version_scheme: self.version_scheme,
+            };
+            let version = self.at.as_ref().map(|at| GenericVersion {
+                version: at.clone(),
+                date: None,
+            });
+            return Ok((pin, version).into());
+        }
+        anyhow::ensure!(
+            self.min_age.is_none() || !matches!(repository, git::Repository::Git { .. }),
+            "A minimum age is only supported for GitHub, GitLab and Forgejo repositories"
+        );
+
         Ok(match &self.branch {
             Some(branch) => {
-                let pin = git::GitPin::new(repository, branch.clone(), self.submodules);
+                let pin = git::GitPin {
+                    min_age: self.min_age,
+                    ..git::GitPin::new(repository, branch.clone(), self.submodules)
+                };
                 let version = self
                     .at
                     .as_ref()
@@ -76,15 +118,21 @@ impl GenericGitAddOpts {
                 (pin, version).into()
             },
             None => {
-                let pin = git::GitReleasePin::new(
-                    repository,
-                    self.pre_releases,
-                    self.version_upper_bound.clone(),
-                    self.release_prefix.clone(),
-                    self.submodules,
-                );
+                let pin = git::GitReleasePin {
+                    tag_pattern: self.tag_pattern.clone(),
+                    version_scheme: self.version_scheme,
+                    min_age: self.min_age,
+                    ..git::GitReleasePin::new(
+                        repository,
+                        self.pre_releases,
+                        constraint(&self.constraint, &self.version_upper_bound),
+                        self.release_prefix.clone(),
+                        self.submodules,
+                    )
+                };
                 let version = self.at.as_ref().map(|at| GenericVersion {
                     version: at.clone(),
+                    date: None,
                 });
                 (pin, version).into()
             },
@@ -119,7 +167,7 @@ impl GitLabAddOpts {
         let repository = git::Repository::gitlab(
             self.repo_path.join("/"),
             Some(self.server.clone()),
-            self.private_token.clone(),
+            self.credential.clone(),
         );
         Ok((
             Some(self.repo_path
@@ -132,7 +180,7 @@ impl GitLabAddOpts {
 }
 
 impl GitAddOpts {
-    pub async fn add(&self) -> Result<(Option<String>, Pin)> {
+    pub async fn add(&self, config: &Config) -> Result<(Option<String>, Pin)> {
         let url = Url::parse(&self.url)
             .map_err(|e| {
                 match e {
@@ -166,7 +214,7 @@ impl GitAddOpts {
 
         use git::Repository;
         let repository = match self.forge {
-            GitForgeOpts::Auto => Repository::git_auto(url).await,
+            GitForgeOpts::Auto => Repository::git_auto(config, url).await,
             GitForgeOpts::None => Repository::git(url),
             GitForgeOpts::Github => Repository::github_from_url(url)
                 .context("Could not parse the URL as GitHub repository")?,
@@ -185,10 +233,54 @@ impl PyPiAddOpts {
         Ok((Some(self.package_name.clone()), {
             let pin = pypi::Pin {
                 name: self.package_name.clone(),
-                version_upper_bound: self.version_upper_bound.clone(),
+                index: self.index.clone(),
+                wheel: self.wheel.clone(),
+                pre_releases: self.pre_releases,
+                constraint: constraint(&self.constraint, &self.version_upper_bound),
+                min_age: self.min_age,
+            };
+            let version = self.at.as_ref().map(|at| GenericVersion {
+                version: at.clone(),
+                date: None,
+            });
+            (pin, version).into()
+        }))
+    }
+}
+
+impl CrateAddOpts {
+    pub fn add(&self) -> Result<(Option<String>, Pin)> {
+        Ok((Some(self.crate_name.clone()), {
+            let pin = crates::Pin {
+                name: self.crate_name.clone(),
+                index: self.index.clone(),
+                pre_releases: self.pre_releases,
+                constraint: constraint(&self.constraint, &self.version_upper_bound),
+                min_age: self.min_age,
+            };
+            let version = self.at.as_ref().map(|at| GenericVersion {
+                version: at.clone(),
+                date: None,
+            });
+            (pin, version).into()
+        }))
+    }
+}
+
+impl NpmAddOpts {
+    pub fn add(&self) -> Result<(Option<String>, Pin)> {
+        /* Use `types-node` for `@types/node`, which is easier to refer to from Nix */
+        let name = self.package_name.trim_start_matches('@').replace('/', "-");
+        Ok((Some(name), {
+            let pin = npm::Pin {
+                name: self.package_name.clone(),
+                dist_tag: self.dist_tag.clone(),
+                constraint: constraint(&self.constraint, &self.version_upper_bound),
+                min_age: self.min_age,
             };
             let version = self.at.as_ref().map(|at| GenericVersion {
                 version: at.clone(),
+                date: None,
             });
             (pin, version).into()
         }))
@@ -215,6 +307,7 @@ impl TarballAddOpts {
         UrlAddOpts {
             url: self.url.clone(),
             mutable: self.mutable,
+            systems: self.systems.clone(),
         }
         .add(true)
         .await
@@ -223,7 +316,27 @@ impl TarballAddOpts {
 
 impl UrlAddOpts {
     pub async fn add(&self, unpack: bool) -> Result<(Option<String>, Pin)> {
-        let pin: Pin = if self.mutable {
+        let pin: Pin = if !self.systems.is_empty() {
+            /* `{` and `}` are not valid in URLs and thus get escaped while parsing */
+            let template = self.url.as_str().replace("%7Bsystem%7D", "{system}");
+            anyhow::ensure!(
+                template.contains("{system}"),
+                "The URL must contain `{{system}}` when pinning it for multiple systems"
+            );
+            let urls = self
+                .systems
+                .iter()
+                .map(|system| {
+                    let (system, value) = system.split_once('=').unwrap_or((system, system));
+                    let url = template
+                        .replace("{system}", value)
+                        .parse()
+                        .with_context(|| format!("Invalid URL for system {system}"))?;
+                    Ok((system.to_owned(), url))
+                })
+                .collect::<Result<_>>()?;
+            urlpin::PerSystemUrlPin { urls, unpack }.into()
+        } else if self.mutable {
             urlpin::MutableUrlPin {
                 update_url: self.url.clone(),
                 unpack,
@@ -241,14 +354,16 @@ impl UrlAddOpts {
 }
 
 impl AddOpts {
-    async fn run(&self) -> Result<(String, Pin)> {
+    async fn run(&self, config: &Config) -> Result<(String, Pin)> {
         let (name, mut pin) = match &self.command {
             AddCommands::Channel(c) => c.add()?,
-            AddCommands::Git(g) => g.add().await?,
+            AddCommands::Git(g) => g.add(config).await?,
             AddCommands::GitHub(gh) => gh.add()?,
             AddCommands::Forgejo(fg) => fg.add()?,
             AddCommands::GitLab(gl) => gl.add()?,
             AddCommands::PyPi(p) => p.add()?,
+            AddCommands::Crate(c) => c.add()?,
+            AddCommands::Npm(n) => n.add()?,
             AddCommands::Tarball(p) => p.add().await?,
             AddCommands::Url(p) => p.add(false).await?,
             AddCommands::Container(p) => p.add()?,
@@ -282,13 +397,164 @@ fn write_diff(writer: &mut impl Write, name: &str, diff: &[diff::DiffEntry]) {
     }
 }
 
+fn write_changelog(writer: &mut impl Write, changelog: &Changelog) {
+    writeln!(writer, "     compare: {}", changelog.compare_url).unwrap();
+    for commit in changelog.commits.iter().flatten() {
+        let id = commit.id.get(..12).unwrap_or(&commit.id);
+        writeln!(writer, "       {id} {}", commit.title).unwrap();
+    }
+}
+
+fn format_age(age: std::time::Duration) -> String {
+    match age.as_secs() / (24 * 60 * 60) {
+        0 => "less than a day".into(),
+        1 => "1 day".into(),
+        days => format!("{days} days"),
+    }
+}
+
+/// The message of the commit created by `npins update --commit` for one pin
+fn commit_message(result: &PinResult) -> String {
+    let name = &result.name;
+    let short = |value: &str| value.get(..12).unwrap_or(value).to_owned();
+    let mut message = match outdated::Outdated::from_diff(&result.changes) {
+        Some(outdated) => {
+            let (current, latest) = match outdated.kind {
+                outdated::UpdateKind::BranchMoved => (
+                    outdated.current.as_deref().map(short),
+                    short(&outdated.latest),
+                ),
+                _ => (outdated.current, outdated.latest),
+            };
+            match current {
+                Some(current) => format!("Update {name} from {current} to {latest}\n"),
+                None => format!("Update {name} to {latest}\n"),
+            }
+        },
+        None => format!("Update {name}\n"),
+    };
+
+    message.push('\n');
+    for entry in &result.changes {
+        match &entry.change {
+            diff::Change::Added { new } => {
+                message.push_str(&format!("{}: {new}\n", entry.property))
+            },
+            diff::Change::Removed { old } => {
+                message.push_str(&format!("{}: {old} (removed)\n", entry.property))
+            },
+            diff::Change::Changed { old, new } => {
+                message.push_str(&format!("{}: {old} -> {new}\n", entry.property))
+            },
+        }
+    }
+    if let Some(changelog) = &result.changelog {
+        message.push_str(&format!("\nCompare: {}\n", changelog.compare_url));
+        for commit in changelog.commits.iter().flatten() {
+            message.push_str(&format!("- {} {}\n", short(&commit.id), commit.title));
+        }
+    }
+    message
+}
+
+/// Run `git <args> -- <file>` from the directory of the file at `path`, returning its standard output
+fn git_on_file(path: &Path, args: &[&str]) -> Result<String> {
+    let file = path
+        .file_name()
+        .context("The lock file path has no file name")?;
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(directory)
+        .args(args)
+        .arg("--")
+        .arg(file)
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "git {} failed: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Print a table of the pins for which `action` failed, sorted by name
+fn write_failures(
+    writer: &mut impl Write,
+    action: &str,
+    failures: &mut [(String, anyhow::Error)],
+    total: usize,
+) {
+    failures.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let width = failures
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    writeln!(
+        writer,
+        "Failed to {action} {} out of {total} pins:",
+        failures.len()
+    )
+    .unwrap();
+    for (name, err) in failures.iter() {
+        writeln!(writer, "  {name:width$}  {err:#}").unwrap();
+    }
+}
+
+/// Exit status if `update --keep-going` updated some but not all pins
+const EXIT_PARTIAL_UPDATE: u8 = 3;
+/// Exit status if `outdated --fail-if-outdated` found outdated pins
+const EXIT_OUTDATED: u8 = 4;
+
+/// An error that makes npins exit with a specific status instead of 1
+#[derive(Debug)]
+struct ExitStatus {
+    status: u8,
+    message: String,
+}
+
+impl std::fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ExitStatus {}
+
 impl Opts {
-    fn read_pins(&self) -> Result<NixPins> {
-        let path = if let Some(lock_file) = self.lock_file.as_ref() {
+    fn pins_path(&self) -> PathBuf {
+        if let Some(lock_file) = self.lock_file.as_ref() {
             lock_file.to_owned()
         } else {
             self.folder.join("sources.json")
-        };
+        }
+    }
+
+    /// The optional pin specification lives next to the lock file
+    fn spec_path(&self) -> PathBuf {
+        self.pins_path().with_file_name(spec::SPEC_FILE)
+    }
+
+    /// Changes to the lock file alone will be undone by `npins lock` if there is a specification
+    fn warn_if_specified(&self) {
+        let path = self.spec_path();
+        if path.exists() {
+            log::warn!(
+                "{} exists, make sure to apply this change there too. Otherwise it will be reverted by the next `npins lock`.",
+                path.display()
+            );
+        }
+    }
+
+    fn read_pins(&self) -> Result<NixPins> {
+        let path = self.pins_path();
         let fh = BufReader::new(File::open(&path).with_context(move || {
             format!(
                 "Failed to open {}. You must initialize npins before you can show current pins.",
@@ -315,7 +581,7 @@ impl Opts {
         Ok(())
     }
 
-    async fn init(&self, o: &InitOpts) -> Result<()> {
+    async fn init(&self, config: &Config, o: &InitOpts) -> Result<()> {
         log::info!("Welcome to npins!");
 
         // Skip the entire default.nix and convenience creating folders bit in lockfile mode
@@ -351,6 +617,7 @@ impl Opts {
             );
             let mut pin = NixPins::new_with_nixpkgs();
             Self::update_one(
+                config,
                 "nixpkgs",
                 pin.pins.get_mut("nixpkgs").unwrap(),
                 UpdateStrategy::Full,
@@ -373,42 +640,44 @@ impl Opts {
     fn show(&self, opts: &ShowOpts) -> Result<()> {
         let pins = self.read_pins()?;
 
-        let print_pin = if opts.plain {
-            |name: &str, _: _| println!("{name}")
-        } else {
-            |name: &str, pin: &Pin| {
-                println!("{name}: ({})", pin.pin_type());
-                println!("{pin}");
-            }
-        };
-
         let mut errors = Vec::new();
 
-        match &opts.names[..] {
-            [] => {
-                for (name, pin) in pins.pins.iter() {
-                    print_pin(name, pin);
-                }
-            },
-            names if opts.exclude => {
-                for (name, pin) in pins.pins.iter() {
-                    if !names.contains(name) {
-                        print_pin(name, pin);
-                    }
+        let selected: Vec<(&String, &Pin)> = match &opts.names[..] {
+            [] => pins.pins.iter().collect(),
+            names if opts.exclude => pins
+                .pins
+                .iter()
+                .filter(|(name, _)| !names.contains(name))
+                .collect(),
+            names => names
+                .iter()
+                .filter_map(|name| match pins.pins.get_key_value(name) {
+                    None => {
+                        errors.push(name.clone());
+                        None
+                    },
+                    Some(pin) => Some(pin),
+                })
+                .collect(),
+        };
+
+        match self.output {
+            OutputFormat::Text if opts.plain => {
+                for (name, _) in &selected {
+                    println!("{name}");
                 }
             },
-            names => {
-                for name in names {
-                    match pins.pins.get(name) {
-                        None => {
-                            errors.push(name.clone());
-                        },
-                        Some(pin) => {
-                            print_pin(name, pin);
-                        },
+            OutputFormat::Text => {
+                for (name, pin) in &selected {
+                    println!("{name}: ({})", pin.pin_type());
+                    print!("{pin}");
+                    if let Some(date) = pin.date() {
+                        println!("    age: {}", format_age(date.age()));
                     }
+                    println!();
                 }
             },
+            format => output::write_pins(&mut stdout(), format, &selected)?,
         }
 
         anyhow::ensure!(
@@ -420,9 +689,10 @@ impl Opts {
         Ok(())
     }
 
-    async fn add(&self, opts: &AddOpts) -> Result<()> {
+    async fn add(&self, config: &Config, opts: &AddOpts) -> Result<()> {
         let mut pins = self.read_pins()?;
-        let (name, mut pin) = opts.run().await?;
+        self.warn_if_specified();
+        let (name, mut pin) = opts.run(config).await?;
         if opts.frozen {
             log::info!("Adding '{}' (frozen) …", name);
         } else {
@@ -434,7 +704,7 @@ impl Opts {
         } else {
             UpdateStrategy::Full
         };
-        Self::update_one(&name, &mut pin, strategy)
+        Self::update_one(config, &name, &mut pin, strategy)
             .await
             .context("Failed to fully initialize the pin")?;
         pins.pins.insert(name.clone(), pin.clone());
@@ -447,13 +717,14 @@ impl Opts {
     }
 
     async fn update_one(
+        config: &Config,
         name: &str,
         pin: &mut Pin,
         strategy: UpdateStrategy,
     ) -> Result<Vec<diff::DiffEntry>> {
         /* Skip this for partial updates */
         let diff1 = if strategy.should_update() {
-            pin.update()
+            pin.update(config)
                 .await
                 .with_context(|| format!("Updating {}", name))?
         } else {
@@ -463,10 +734,10 @@ impl Opts {
         /* We only need to fetch the hashes if the version changed, or if the flags indicate that we should */
         let diff = if !diff1.is_empty() || strategy.must_fetch() {
             let diff2 = pin
-                .fetch()
+                .fetch(config)
                 .await
                 .with_context(|| format!("Fetching {}", name))?;
-            diff1.into_iter().chain(diff2.into_iter()).collect()
+            diff1.into_iter().chain(diff2).collect()
         } else {
             diff1
         };
@@ -474,9 +745,43 @@ impl Opts {
         Ok(diff)
     }
 
-    async fn update(&self, opts: &UpdateOpts) -> Result<()> {
+    /// The compare link of an updated pin, and its commits if requested
+    ///
+    /// This is purely informational, so failures are only logged.
+    async fn changelog(
+        config: &Config,
+        name: &str,
+        pin: &Pin,
+        diff: &[diff::DiffEntry],
+        fetch_commits: bool,
+    ) -> Option<Changelog> {
+        let mut changelog = Changelog::new(config, pin, diff)
+            .inspect_err(|err| log::warn!("Could not build the compare link of {name}: {err:#}"))
+            .ok()??;
+        if fetch_commits && let Err(err) = changelog.fetch_commits(config).await {
+            log::warn!("Could not fetch the commits of {name}: {err:#}");
+        }
+        Some(changelog)
+    }
+
+    async fn update(&self, config: &Config, opts: &UpdateOpts) -> Result<()> {
         let mut pins = self.read_pins()?;
 
+        /* Otherwise the first commit would include unrelated changes. An untracked lock file
+         * (`??`) is fine, it gets added with the first commit, see `commit_pins`.
+         */
+        if opts.commit {
+            let path = self.pins_path();
+            let status = git_on_file(&path, &["status", "--porcelain"])
+                .context("--commit requires the lock file to be inside a git repository")?;
+            anyhow::ensure!(
+                status.lines().all(|line| line.starts_with("??")),
+                "{} has uncommitted changes, commit or stash them before using --commit",
+                path.display()
+            );
+        }
+        let original_pins = opts.commit.then(|| pins.clone());
+
         let mut selected_pins = BTreeSet::new();
         for name in &opts.names {
             if !selected_pins.insert(name) {
@@ -519,6 +824,7 @@ impl Opts {
             write!(stderr, "Updated {finished}/{length} pins").unwrap()
         });
         let animation = &animation;
+        let scheduler = &opts.concurrency.scheduler();
 
         let update_iter = pins
             .pins
@@ -527,18 +833,47 @@ impl Opts {
                 selected_pins.contains(name)
                     || (opts.names.is_empty() && (opts.update_frozen || !pin.is_frozen()))
             })
-            .map(|(name, pin)| async move {
-                animation.on_pin_start(name);
-                let diff = Self::update_one(name, pin, strategy).await?;
-                animation.on_pin_finish(name, |stderr| write_diff(stderr, name, &diff));
-                anyhow::Result::<_, anyhow::Error>::Ok((name, diff))
+            .map(|(name, pin)| {
+                scheduler.run(pin.host(config).as_deref(), async move {
+                    animation.on_pin_start(name);
+                    let old_pin = pin.clone();
+                    let pin_type = pin.pin_type();
+                    match Self::update_one(config, name, pin, strategy).await {
+                        Ok(diff) => {
+                            let changelog =
+                                Self::changelog(config, name, pin, &diff, opts.changelog).await;
+                            animation.on_pin_finish(name, |stderr| {
+                                write_diff(stderr, name, &diff);
+                                if let Some(changelog) = &changelog {
+                                    write_changelog(stderr, changelog);
+                                }
+                            });
+                            Ok((name, pin_type, Ok(diff), changelog))
+                        },
+                        Err(err) if opts.keep_going => {
+                            /* The version may have been updated without the hashes, don't write that back */
+                            *pin = old_pin;
+                            animation.on_pin_finish(name, |stderr| {
+                                writeln!(stderr, "[{name}] Failed").unwrap()
+                            });
+                            Ok((name, pin_type, Err(err), None))
+                        },
+                        Err(err) => Err(err),
+                    }
+                })
             });
 
         let mut has_diff = false;
-        stream::iter(update_iter)
-            .buffer_unordered(opts.max_concurrent_downloads)
-            .try_for_each(|(_name, diff)| {
-                has_diff |= !diff.is_empty();
+        let mut failures = Vec::new();
+        let mut results = Vec::new();
+        update_iter
+            .collect::<FuturesUnordered<_>>()
+            .try_for_each(|(name, pin_type, result, changelog)| {
+                results.push(PinResult::new(name, pin_type, &result).with_changelog(changelog));
+                match result {
+                    Ok(diff) => has_diff |= !diff.is_empty(),
+                    Err(err) => failures.push((name.clone(), err)),
+                }
                 future::ready(Ok(()))
             })
             .await
@@ -554,11 +889,148 @@ impl Opts {
             eprintln!();
         }
 
-        if !opts.dry_run {
+        output::write_results(&mut stdout(), self.output, &mut results)?;
+
+        if !failures.is_empty() {
+            write_failures(&mut stderr(), "update", &mut failures, length);
+            anyhow::ensure!(failures.len() < length, "None of the pins could be updated");
+        }
+
+        if let Some(mut committed_pins) = original_pins {
+            /* `write_results` sorted the results by name */
+            for result in results
+                .iter()
+                .filter(|r| r.status == output::Status::Changed)
+            {
+                committed_pins
+                    .pins
+                    .insert(result.name.clone(), pins.pins[&result.name].clone());
+                self.write_pins(&committed_pins)?;
+                self.commit_pins(&commit_message(result))
+                    .with_context(|| format!("Failed to commit the update of {}", result.name))?;
+            }
+            if failures.is_empty() {
+                log::info!("Update successful.");
+            }
+        } else if !opts.dry_run {
             if has_diff {
                 self.write_pins(&pins)?;
             }
-            log::info!("Update successful.");
+            if failures.is_empty() {
+                log::info!("Update successful.");
+            }
+        } else if failures.is_empty() {
+            log::info!("Dry run successful.");
+        }
+
+        if !failures.is_empty() {
+            return Err(ExitStatus {
+                status: EXIT_PARTIAL_UPDATE,
+                message: format!(
+                    "{} out of {length} pins could not be updated, the others have been updated",
+                    failures.len()
+                ),
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Commit the lock file alone, leaving anything else in the index untouched
+    fn commit_pins(&self, message: &str) -> Result<()> {
+        let path = self.pins_path();
+        /* Also needed to commit a lock file that isn't tracked yet */
+        git_on_file(&path, &["add"])?;
+        git_on_file(&path, &["commit", "--quiet", "--only", "-m", message])?;
+        Ok(())
+    }
+
+    async fn lock(&self, config: &Config, opts: &LockOpts) -> Result<()> {
+        let path = self.spec_path();
+        let spec = std::fs::read_to_string(&path).with_context(|| {
+            format!(
+                "Failed to open {}. You must write a pin specification before you can lock it.",
+                path.display()
+            )
+        })?;
+        let spec = spec::Spec::from_toml(&spec)
+            .with_context(|| format!("Failed to load {}", path.display()))?;
+
+        /* Without a lock file, all pins get resolved */
+        let locked = self.pins_path().exists();
+        let old_pins = if locked {
+            self.read_pins()?
+        } else {
+            NixPins::default()
+        };
+        let mut pins = spec.lock(&old_pins);
+
+        for name in old_pins.pins.keys() {
+            if !pins.pins.contains_key(name) {
+                log::info!("Removing '{}'", name);
+            }
+        }
+
+        let length = pins.pins.values().filter(|pin| !pin.has_hashes()).count();
+
+        let animation = Animation::new(|stderr, finished| {
+            write!(stderr, "Resolved {finished}/{length} pins").unwrap()
+        });
+        let animation = &animation;
+        let scheduler = &opts.concurrency.scheduler();
+
+        let lock_iter = pins
+            .pins
+            .iter_mut()
+            .filter(|(_, pin)| !pin.has_hashes())
+            .map(|(name, pin)| {
+                scheduler.run(pin.host(config).as_deref(), async move {
+                    animation.on_pin_start(name);
+                    /* Only fetch the hashes if the specification contains a version */
+                    let strategy = if pin.has_version() {
+                        UpdateStrategy::HashesOnly
+                    } else {
+                        UpdateStrategy::Full
+                    };
+                    let diff = Self::update_one(config, name, pin, strategy).await?;
+                    animation.on_pin_finish(name, |stderr| write_diff(stderr, name, &diff));
+                    anyhow::Result::<_, anyhow::Error>::Ok(())
+                })
+            });
+
+        lock_iter
+            .collect::<FuturesUnordered<_>>()
+            .try_collect::<()>()
+            .await
+            .inspect_err(|_| {
+                /* Flush the status line */
+                if length != 0 && stderr().is_terminal() {
+                    eprintln!();
+                }
+            })?;
+
+        /* Flush the status line */
+        if length != 0 && stderr().is_terminal() {
+            eprintln!();
+        }
+
+        if !opts.dry_run {
+            /* Allow starting out with only a specification */
+            let default_nix = self.folder.join("default.nix");
+            if self.lock_file.is_none() && !default_nix.exists() {
+                log::info!("Writing default.nix");
+                if !self.folder.exists() {
+                    std::fs::create_dir(&self.folder).context("Failed to create npins folder")?;
+                }
+                let mut fh =
+                    File::create(&default_nix).context("Failed to create npins default.nix")?;
+                fh.write_all(DEFAULT_NIX.as_bytes())?;
+            }
+            if !locked || pins != old_pins {
+                self.write_pins(&pins)?;
+            }
+            log::info!("Lock successful.");
         } else {
             log::info!("Dry run successful.");
         }
@@ -566,7 +1038,115 @@ impl Opts {
         Ok(())
     }
 
-    async fn verify(&self, opts: &VerifyOpts) -> Result<()> {
+    async fn outdated(&self, config: &Config, opts: &OutdatedOpts) -> Result<()> {
+        /* Only the versions are updated and the pins are never written back */
+        let mut pins = self.read_pins()?;
+
+        let mut selected_pins = BTreeSet::new();
+        for name in &opts.names {
+            if !selected_pins.insert(name) {
+                log::warn!("Ignoring duplicate pin: {name}")
+            }
+        }
+        selected_pins.retain(|&name| match pins.pins.get(name) {
+            Some(_) => true,
+            None => {
+                log::warn!("Specified pin does not exist: {name}");
+                false
+            },
+        });
+
+        let length = if opts.names.is_empty() {
+            pins.pins.len()
+        } else {
+            selected_pins.len()
+        };
+
+        let animation = Animation::new(|stderr, finished| {
+            write!(stderr, "Checked {finished}/{length} pins").unwrap()
+        });
+        let animation = &animation;
+        let scheduler = &opts.concurrency.scheduler();
+
+        let outdated_iter = pins
+            .pins
+            .iter_mut()
+            .filter(|(name, _pin)| selected_pins.contains(name) || opts.names.is_empty())
+            .map(|(name, pin)| {
+                scheduler.run(pin.host(config).as_deref(), async move {
+                    animation.on_pin_start(name);
+                    let result = pin
+                        .update(config)
+                        .await
+                        .with_context(|| format!("Updating {}", name));
+                    animation.on_pin_finish(name, |stderr| match &result {
+                        Ok(diff) => write_diff(stderr, name, diff),
+                        Err(_) => writeln!(stderr, "[{name}] Failed").unwrap(),
+                    });
+                    (name, pin.pin_type(), pin.is_frozen(), result)
+                })
+            });
+
+        let checked: Vec<_> = outdated_iter
+            .collect::<FuturesUnordered<_>>()
+            .collect()
+            .await;
+
+        /* Flush the status line */
+        if length != 0 && stderr().is_terminal() {
+            eprintln!();
+        }
+
+        let mut results = Vec::new();
+        let mut failures = Vec::new();
+        for (name, pin_type, frozen, result) in checked {
+            let (outdated, error) = match result {
+                Ok(diff) => (outdated::Outdated::from_diff(&diff), None),
+                Err(err) => {
+                    let error = format!("{err:#}");
+                    failures.push((name.clone(), err));
+                    (None, Some(error))
+                },
+            };
+            if outdated.is_some() || error.is_some() {
+                results.push(output::OutdatedResult {
+                    name: name.clone(),
+                    pin_type,
+                    frozen,
+                    outdated,
+                    error,
+                });
+            }
+        }
+        results.sort_by(|a, b| a.name.cmp(&b.name));
+
+        output::write_outdated(&mut stdout(), self.output, &results)?;
+
+        if !failures.is_empty() {
+            write_failures(&mut stderr(), "check", &mut failures, length);
+            anyhow::bail!("Not all pins could be checked");
+        }
+
+        let count = results
+            .iter()
+            .filter(|result| result.outdated.is_some() && !result.frozen)
+            .count();
+        if results.is_empty() {
+            log::info!("All pins are up to date.");
+        } else if count == 0 {
+            log::info!("Only frozen pins are outdated.");
+        } else if opts.fail_if_outdated {
+            return Err(ExitStatus {
+                status: EXIT_OUTDATED,
+                message: format!("{count} pins are outdated"),
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+
+    async fn verify(&self, config: &Config, opts: &VerifyOpts) -> Result<()> {
         let mut pins = self.read_pins()?;
 
         let mut selected_pins = BTreeSet::new();
@@ -595,43 +1175,40 @@ impl Opts {
             write!(stderr, "Verified {finished}/{length} pins").unwrap()
         });
         let animation = &animation;
+        let scheduler = &opts.concurrency.scheduler();
 
         let update_iter = pins
             .pins
             .iter_mut()
             .filter(|(name, _pin)| selected_pins.contains(name) || opts.names.is_empty())
-            .map(|(name, pin)| async move {
-                animation.on_pin_start(name);
-                let diff_result = Self::update_one(name, pin, STRATEGY).await;
-                animation.on_pin_finish(name, |stderr| match &diff_result {
-                    Ok(diff) => write_diff(stderr, name, diff),
-                    Err(err) => {
-                        writeln!(stderr, "[{name}] Failed download").unwrap();
-                        writeln!(stderr, "{err:?}").unwrap();
-                    },
-                });
-                (name, diff_result)
+            .map(|(name, pin)| {
+                scheduler.run(pin.host(config).as_deref(), async move {
+                    animation.on_pin_start(name);
+                    let pin_type = pin.pin_type();
+                    let diff_result = Self::update_one(config, name, pin, STRATEGY).await;
+                    animation.on_pin_finish(name, |stderr| match &diff_result {
+                        Ok(diff) => write_diff(stderr, name, diff),
+                        Err(err) => {
+                            writeln!(stderr, "[{name}] Failed download").unwrap();
+                            writeln!(stderr, "{err:?}").unwrap();
+                        },
+                    });
+                    (name, pin_type, diff_result)
+                })
             });
 
-        let (differences, failed) = stream::iter(update_iter)
-            .buffer_unordered(opts.max_concurrent_downloads)
-            /* Filter out empty diffs */
-            .filter(|(_, diff_result)| {
-                future::ready(
-                    diff_result
-                        .as_ref()
-                        .map(|diff| !diff.is_empty())
-                        .unwrap_or(true),
-                )
-            })
+        let (differences, failed, mut results) = update_iter
+            .collect::<FuturesUnordered<_>>()
             .fold(
-                (vec![], vec![]),
-                |(mut differences, mut failed), (name, diff_result)| async move {
+                (vec![], vec![], vec![]),
+                |(mut differences, mut failed, mut results), (name, pin_type, diff_result)| async move {
+                    results.push(PinResult::new(name, pin_type, &diff_result));
                     match diff_result {
+                        Ok(diff) if diff.is_empty() => {},
                         Ok(_) => differences.push(name),
                         Err(_) => failed.push(name),
                     }
-                    (differences, failed)
+                    (differences, failed, results)
                 },
             )
             .await;
@@ -641,6 +1218,8 @@ impl Opts {
             eprintln!();
         }
 
+        output::write_results(&mut stdout(), self.output, &mut results)?;
+
         if differences.is_empty() && failed.is_empty() {
             log::info!("Verification passed.");
             Ok(())
@@ -703,6 +1282,7 @@ impl Opts {
 
     fn remove(&self, r: &RemoveOpts) -> Result<()> {
         let mut pins = self.read_pins()?;
+        self.warn_if_specified();
 
         let mut errors = Vec::new();
 
@@ -725,6 +1305,7 @@ impl Opts {
 
     async fn freeze(&self, o: &FreezeOpts) -> Result<()> {
         let mut pins = self.read_pins()?;
+        self.warn_if_specified();
 
         for name in o.names.iter() {
             let pin = match pins.pins.get_mut(name) {
@@ -743,6 +1324,7 @@ impl Opts {
 
     async fn unfreeze(&self, o: &FreezeOpts) -> Result<()> {
         let mut pins = self.read_pins()?;
+        self.warn_if_specified();
 
         for name in o.names.iter() {
             let pin = match pins.pins.get_mut(name) {
@@ -760,7 +1342,7 @@ impl Opts {
         Ok(())
     }
 
-    async fn import_niv(&self, o: &ImportOpts) -> Result<()> {
+    async fn import_niv(&self, config: &Config, o: &ImportOpts) -> Result<()> {
         let mut pins = self.read_pins()?;
 
         let niv: BTreeMap<String, serde_json::Value> =
@@ -774,6 +1356,7 @@ impl Opts {
         );
 
         async fn import(
+            config: &Config,
             name: &str,
             pin: Option<&serde_json::Value>,
             npins: &mut NixPins,
@@ -793,19 +1376,23 @@ impl Opts {
             let mut pin: Pin = pin
                 .try_into()
                 .context("Could not convert pin to npins format")?;
-            pin.update().await.context("Failed to update the pin")?;
-            pin.fetch().await.context("Failed to update the pin")?;
+            pin.update(config)
+                .await
+                .context("Failed to update the pin")?;
+            pin.fetch(config)
+                .await
+                .context("Failed to update the pin")?;
             npins.pins.insert(name.to_string(), pin);
 
             Ok(())
         }
 
         if let Some(name) = &o.name {
-            import(name, None, &mut pins, &niv).await?;
+            import(config, name, None, &mut pins, &niv).await?;
         } else {
             for (name, pin) in niv.iter() {
                 log::info!("Importing {}", name);
-                if let Err(err) = import(name, Some(pin), &mut pins, &niv).await {
+                if let Err(err) = import(config, name, Some(pin), &mut pins, &niv).await {
                     log::error!("Failed to import pin '{}'", name);
                     log::error!("{}", err);
                     err.chain()
@@ -820,7 +1407,7 @@ impl Opts {
         Ok(())
     }
 
-    async fn import_flake(&self, o: &ImportFlakeOpts) -> Result<()> {
+    async fn import_flake(&self, config: &Config, o: &ImportFlakeOpts) -> Result<()> {
         let mut pins = self.read_pins()?;
 
         let flake: serde_json::Value =
@@ -861,6 +1448,7 @@ impl Opts {
             ))?;
 
         async fn import(
+            config: &Config,
             name: &str,
             npins: &mut NixPins,
             nodes: &serde_json::Map<String, serde_json::Value>,
@@ -883,12 +1471,14 @@ impl Opts {
             }
 
             let mut pin: Pin = pin
-                .try_to_pin()
+                .try_to_pin(config)
                 .await
                 .context("Could not convert pin to npins format")?;
 
-            pin.update().await?;
-            pin.fetch().await.context("Failed to update the pin")?;
+            pin.update(config).await?;
+            pin.fetch(config)
+                .await
+                .context("Failed to update the pin")?;
             npins.pins.insert(name.to_string(), pin);
 
             Ok(())
@@ -896,6 +1486,7 @@ impl Opts {
 
         if let Some(name) = &o.name {
             import(
+                config,
                 inputs
                     .get(name)
                     .context(format!("flake input {name} not found"))?,
@@ -907,7 +1498,7 @@ impl Opts {
         } else {
             for (name, input_name) in inputs.iter() {
                 log::info!("Importing {}", name);
-                if let Err(err) = import(input_name, &mut pins, nodes).await {
+                if let Err(err) = import(config, input_name, &mut pins, nodes).await {
                     log::error!("Failed to import pin '{}'", name);
                     log::error!("{}", err);
                     err.chain()
@@ -941,22 +1532,43 @@ impl Opts {
         Ok(())
     }
 
+    /// The configuration from the environment, with the retry and timeout options applied
+    fn config(&self) -> Result<Config> {
+        let timeout = |seconds| (seconds > 0).then(|| Duration::from_secs(seconds));
+        config::ConfigBuilder::from_env()?
+            .retry_policy(retry::RetryPolicy {
+                attempts: self.retries + 1,
+                ..retry::RetryPolicy::default()
+            })
+            .timeouts(retry::Timeouts {
+                request: timeout(self.request_timeout),
+                git: timeout(self.git_timeout),
+                prefetch: timeout(self.prefetch_timeout),
+            })
+            /* Each invocation is one run, pins on the same remote share the requests */
+            .request_cache(cache::RequestCache::default())
+            .build()
+    }
+
     pub fn run(&self) -> Result<()> {
         if self.lock_file.is_some() && &*self.folder != std::path::Path::new("npins") {
             anyhow::bail!(
                 "If --lock-file is set, --directory will be ignored and thus should not be set to a non-default value (which is \"npins\")"
             );
         }
+        let config = &self.config()?;
         match &self.command {
-            Command::Init(o) => start_runtime(self.init(o))?,
+            Command::Init(o) => start_runtime(self.init(config, o))?,
             Command::Show(o) => self.show(o)?,
-            Command::Add(a) => start_runtime(self.add(a))?,
-            Command::Update(o) => start_runtime(self.update(o))?,
-            Command::Verify(o) => start_runtime(self.verify(o))?,
+            Command::Add(a) => start_runtime(self.add(config, a))?,
+            Command::Update(o) => start_runtime(self.update(config, o))?,
+            Command::Lock(o) => start_runtime(self.lock(config, o))?,
+            Command::Outdated(o) => start_runtime(self.outdated(config, o))?,
+            Command::Verify(o) => start_runtime(self.verify(config, o))?,
             Command::Upgrade => self.upgrade()?,
             Command::Remove(r) => self.remove(r)?,
-            Command::ImportNiv(o) => start_runtime(self.import_niv(o))?,
-            Command::ImportFlake(o) => start_runtime(self.import_flake(o))?,
+            Command::ImportNiv(o) => start_runtime(self.import_niv(config, o))?,
+            Command::ImportFlake(o) => start_runtime(self.import_flake(config, o))?,
             Command::Freeze(o) => start_runtime(self.freeze(o))?,
             Command::Unfreeze(o) => start_runtime(self.unfreeze(o))?,
             Command::GetPath(o) => start_runtime(self.get_path(o))?,
@@ -1053,7 +1665,7 @@ impl<'a, F: for<'b> Fn(&'b mut std::io::StderrLock, i32)> Animation<'a, F> {
     }
 }
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     let opts = Opts::parse();
 
     env_logger::builder()
@@ -1066,7 +1678,14 @@ fn main() -> Result<()> {
         .format_target(false)
         .init();
 
-    opts.run()
+    match opts.run().map_err(anyhow::Error::downcast::<ExitStatus>) {
+        Ok(()) => Ok(ExitCode::SUCCESS),
+        Err(Ok(exit)) => {
+            eprintln!("Error: {exit}");
+            Ok(ExitCode::from(exit.status))
+        },
+        Err(Err(err)) => Err(err),
+    }
 }
 
 fn start_runtime(future: impl Future<Output = Result<()>>) -> Result<()> {
@@ -1,5 +1,8 @@
 use clap::{Parser, Subcommand, ValueEnum, ValueHint};
 use libnpins::channel;
+use libnpins::retry::{RetryPolicy, Timeouts};
+use libnpins::schedule::Scheduler;
+use libnpins::version_scheme::VersionScheme;
 use std::path::PathBuf;
 use url::Url;
 
@@ -44,12 +47,23 @@ pub struct GenericGitAddOpts {
     #[arg(long, conflicts_with = "branch")]
     pub pre_releases: bool,
 
+    /// Restrict the version resolution. For example, ">=1.4, <2, !=1.4.3" or "^1.4".
+    /// Supported operators are =, !=, <, <=, >, >=, ~, ^ and ~=.
+    /// Conflicts with the --branch option.
+    #[arg(
+        long,
+        value_name = "constraint",
+        conflicts_with_all = &["branch", "at"],
+        value_hint = ValueHint::Other
+    )]
+    pub constraint: Option<String>,
+
     /// Bound the version resolution. For example, setting this to "2" will
-    /// restrict updates to 1.X versions. Conflicts with the --branch option.
+    /// restrict updates to 1.X versions. Shorthand for --constraint "<2".
     #[arg(
         long = "upper-bound",
         value_name = "version",
-        conflicts_with_all = &["branch", "at"],
+        conflicts_with_all = &["branch", "at", "constraint"],
         value_hint = ValueHint::Other
     )]
     pub version_upper_bound: Option<String>,
@@ -60,9 +74,51 @@ pub struct GenericGitAddOpts {
     #[arg(long = "release-prefix", value_hint = ValueHint::Other)]
     pub release_prefix: Option<String>,
 
+    /// Regular expression that release tags must match, with capture groups for the
+    /// version. Multiple groups are joined with dots. For example "curl-(\d+)_(\d+)_(\d+)"
+    /// or "foo@(.+)". Conflicts with the --release-prefix option.
+    #[arg(
+        long,
+        value_name = "regex",
+        conflicts_with_all = &["branch", "release_prefix"],
+        value_hint = ValueHint::Other
+    )]
+    pub tag_pattern: Option<String>,
+
+    /// How release versions are ordered: semver (lenient), calver (e.g. 24.04.1),
+    /// date (e.g. 2024-01-15), lexical (plain string comparison) or debian (e.g. 1:2.0~rc1-3)
+    #[arg(
+        long,
+        value_name = "scheme",
+        default_value_t,
+        conflicts_with = "branch",
+        value_hint = ValueHint::Other
+    )]
+    pub version_scheme: VersionScheme,
+
     /// Also fetch submodules
     #[arg(long)]
     pub submodules: bool,
+
+    /// Track an asset of the releases instead of the source code. `*` matches anything,
+    /// `{version}` is replaced with the release tag without the release prefix and a leading "v".
+    /// For example "foo-{version}-x86_64-linux.tar.gz". Not supported for plain git repositories.
+    #[arg(
+        long,
+        value_name = "name",
+        conflicts_with_all = &["branch", "submodules"],
+        value_hint = ValueHint::Other
+    )]
+    pub asset: Option<String>,
+
+    /// Unpack the release asset
+    #[arg(long, requires = "asset")]
+    pub unpack: bool,
+
+    /// Skip releases or commits younger than this many days, overriding NPINS_MIN_AGE.
+    /// 0 disables the check. Not supported for plain git repositories and release assets.
+    #[arg(long, value_name = "days", conflicts_with = "asset", value_hint = ValueHint::Other)]
+    pub min_age: Option<u32>,
 }
 
 #[derive(Debug, Parser)]
@@ -107,16 +163,28 @@ pub struct GitLabAddOpts {
 
     #[arg(
         long,
-        help = "Use a private token to access the repository.",
-        value_name = "token",
+        help = "Name of the credential with the access token of a private repository. The token is looked up in $NPINS_CREDENTIAL_<NAME>, the $NPINS_CREDENTIAL_HELPER or the netrc file, and is never written to the lock file",
+        value_name = "name",
         value_hint = ValueHint::Other
     )]
-    pub private_token: Option<String>,
+    pub credential: Option<String>,
 
     #[command(flatten)]
     pub more: GenericGitAddOpts,
 }
 
+/// Format of the results printed to stdout by `show`, `update` and `verify`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable text
+    #[default]
+    Text,
+    /// A JSON document, for further processing by scripts
+    Json,
+    /// Markdown tables, e.g. for pull request descriptions
+    Markdown,
+}
+
 #[derive(Debug, Parser, Clone, Copy, Default, ValueEnum)]
 pub enum GitForgeOpts {
     /// A generic git pin, with no further information
@@ -151,14 +219,102 @@ pub struct PyPiAddOpts {
     #[arg(value_hint = ValueHint::Other)]
     pub package_name: String,
 
+    /// Use a package index with the JSON simple API (PEP 691) instead of PyPi.org,
+    /// for example <https://pypi.org/simple/> or a devpi or Artifactory mirror
+    #[arg(long, value_name = "url", value_hint = ValueHint::Url)]
+    pub index: Option<Url>,
+
+    /// Pin a wheel instead of the source distribution, selected by a compatibility tag
+    /// like "py3-none-any" or a file name like "foo-{version}-py3-none-any.whl".
+    /// Both may contain * to match any sequence of characters.
+    #[arg(long, value_name = "tag-or-filename", value_hint = ValueHint::Other)]
+    pub wheel: Option<String>,
+
+    /// Use a specific release instead of the latest.
+    #[arg(long, value_name = "version", value_hint = ValueHint::Other)]
+    pub at: Option<String>,
+
+    /// Also track pre-releases.
+    #[arg(long)]
+    pub pre_releases: bool,
+
+    /// Restrict the version resolution. For example, ">=1.4, <2, !=1.4.3" or "~=1.4".
+    /// Supported operators are =, !=, <, <=, >, >=, ~, ^ and ~=.
+    #[arg(long, value_name = "constraint", conflicts_with = "at", value_hint = ValueHint::Other)]
+    pub constraint: Option<String>,
+
+    /// Bound the version resolution. For example, setting this to "2" will
+    /// restrict updates to 1.X versions. Shorthand for --constraint "<2".
+    #[arg(long = "upper-bound", value_name = "version", conflicts_with_all = ["at", "constraint"], value_hint = ValueHint::Other)]
+    pub version_upper_bound: Option<String>,
+
+    /// Skip releases younger than this many days, overriding NPINS_MIN_AGE.
+    /// 0 disables the check.
+    #[arg(long, value_name = "days", value_hint = ValueHint::Other)]
+    pub min_age: Option<u32>,
+}
+
+#[derive(Debug, Parser)]
+pub struct CrateAddOpts {
+    /// Name of the crate
+    #[arg(value_hint = ValueHint::Other)]
+    pub crate_name: String,
+
+    /// Use a registry with a sparse index instead of crates.io, for example <https://index.crates.io/>
+    #[arg(long, value_name = "url", value_hint = ValueHint::Url)]
+    pub index: Option<Url>,
+
+    /// Use a specific release instead of the latest.
+    #[arg(long, value_name = "version", value_hint = ValueHint::Other)]
+    pub at: Option<String>,
+
+    /// Also track pre-releases.
+    #[arg(long)]
+    pub pre_releases: bool,
+
+    /// Restrict the version resolution. For example, ">=1.4, <2, !=1.4.3" or "^1.4".
+    /// Supported operators are =, !=, <, <=, >, >=, ~, ^ and ~=.
+    #[arg(long, value_name = "constraint", conflicts_with = "at", value_hint = ValueHint::Other)]
+    pub constraint: Option<String>,
+
+    /// Bound the version resolution. For example, setting this to "2" will
+    /// restrict updates to 1.X versions. Shorthand for --constraint "<2".
+    #[arg(long = "upper-bound", value_name = "version", conflicts_with_all = ["at", "constraint"], value_hint = ValueHint::Other)]
+    pub version_upper_bound: Option<String>,
+    /// Skip releases younger than this many days, overriding NPINS_MIN_AGE.
+    /// 0 disables the check.
+    #[arg(long, value_name = "days", value_hint = ValueHint::Other)]
+    pub min_age: Option<u32>,
+}
+
+#[derive(Debug, Parser)]
+pub struct NpmAddOpts {
+    /// Name of the package, for example `left-pad` or `@types/node`
+    #[arg(value_hint = ValueHint::Other)]
+    pub package_name: String,
+
     /// Use a specific release instead of the latest.
     #[arg(long, value_name = "version", value_hint = ValueHint::Other)]
     pub at: Option<String>,
 
+    /// Follow a dist-tag other than `latest`, for example `next`
+    #[arg(long, value_name = "tag", value_hint = ValueHint::Other)]
+    pub dist_tag: Option<String>,
+
+    /// Restrict the version resolution. For example, ">=1.4, <2, !=1.4.3" or "^1.4".
+    /// Supported operators are =, !=, <, <=, >, >=, ~, ^ and ~=.
+    /// Conflicts with the --dist-tag option.
+    #[arg(long, value_name = "constraint", conflicts_with_all = ["at", "dist_tag"], value_hint = ValueHint::Other)]
+    pub constraint: Option<String>,
+
     /// Bound the version resolution. For example, setting this to "2" will
-    /// restrict updates to 1.X versions. Conflicts with the --branch option.
-    #[arg(long = "upper-bound", value_name = "version", conflicts_with = "at", value_hint = ValueHint::Other)]
+    /// restrict updates to 1.X versions. Shorthand for --constraint "<2".
+    #[arg(long = "upper-bound", value_name = "version", conflicts_with_all = ["at", "dist_tag", "constraint"], value_hint = ValueHint::Other)]
     pub version_upper_bound: Option<String>,
+    /// Skip releases younger than this many days, overriding NPINS_MIN_AGE.
+    /// 0 disables the check.
+    #[arg(long, value_name = "days", value_hint = ValueHint::Other)]
+    pub min_age: Option<u32>,
 }
 
 #[derive(Debug, Parser)]
@@ -180,6 +336,9 @@ pub struct TarballAddOpts {
     /// Treat this URL as mutable, and assume it will redirect to an immutable version of the content to be pinned. For example, a HEAD URL redirecting to the currently latest commit
     #[arg(long)]
     pub mutable: bool,
+    /// Pin a different tarball for each Nix system, by replacing `{system}` in the URL. Use `SYSTEM=VALUE` to substitute a different value, for example `aarch64-darwin=macos-arm64`. May be given multiple times
+    #[arg(long = "system", value_name = "system", conflicts_with = "mutable", value_hint = ValueHint::Other)]
+    pub systems: Vec<String>,
 }
 
 #[derive(Debug, Parser)]
@@ -189,6 +348,9 @@ pub struct UrlAddOpts {
     /// Treat this URL as mutable, and assume it will redirect to an immutable version of the content to be pinned. For example, a HEAD URL redirecting to the currently latest commit
     #[arg(long)]
     pub mutable: bool,
+    /// Pin a different URL for each Nix system, by replacing `{system}` in the URL. Use `SYSTEM=VALUE` to substitute a different value, for example `aarch64-darwin=macos-arm64`. May be given multiple times
+    #[arg(long = "system", value_name = "system", conflicts_with = "mutable", value_hint = ValueHint::Other)]
+    pub systems: Vec<String>,
 }
 
 #[derive(Debug, Subcommand)]
@@ -211,6 +373,12 @@ pub enum AddCommands {
     /// Track a package on PyPi
     #[command(name = "pypi")]
     PyPi(PyPiAddOpts),
+    /// Track a Rust crate on crates.io or another registry
+    #[command(name = "crate")]
+    Crate(CrateAddOpts),
+    /// Track a package on npm
+    #[command(name = "npm")]
+    Npm(NpmAddOpts),
     /// Track an OCI container
     #[command(name = "container")]
     Container(ContainerAddOpts),
@@ -282,9 +450,80 @@ pub struct UpdateOpts {
     /// Allow updating frozen pins, which would otherwise be ignored
     #[arg(long = "frozen")]
     pub update_frozen: bool,
+    /// Also list the commits between the old and new version of pins hosted on a forge.
+    /// Compare links are always shown.
+    #[arg(long)]
+    pub changelog: bool,
+    /// Write back each updated pin separately and create one git commit per pin,
+    /// with a message listing the changes and the compare link.
+    /// Only the lock file is committed, it must not have uncommitted changes.
+    #[arg(long, conflicts_with = "dry_run")]
+    pub commit: bool,
+    /// Continue with the other pins if a pin fails to update, and write back the successful ones.
+    /// Exits with status 3 if only some of the pins could be updated.
+    #[arg(short, long)]
+    pub keep_going: bool,
+    #[command(flatten)]
+    pub concurrency: ConcurrencyOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct ConcurrencyOpts {
     /// Maximum number of simultaneous downloads
     #[arg(default_value = "5", long, value_hint = ValueHint::Other)]
     pub max_concurrent_downloads: usize,
+    /// Maximum number of simultaneous downloads from the same host.
+    /// Only --max-concurrent-downloads applies by default.
+    #[arg(long, value_hint = ValueHint::Other)]
+    pub max_concurrent_per_host: Option<usize>,
+    /// Use a different limit of simultaneous downloads for a host, e.g. `gitlab.example.org=1`.
+    /// May be given multiple times.
+    #[arg(long, value_name = "host=n", value_parser = parse_host_limit, value_hint = ValueHint::Other)]
+    pub host_limit: Vec<(String, usize)>,
+}
+
+impl ConcurrencyOpts {
+    pub fn scheduler(&self) -> Scheduler {
+        self.host_limit.iter().fold(
+            Scheduler::new(
+                self.max_concurrent_downloads,
+                self.max_concurrent_per_host
+                    .unwrap_or(self.max_concurrent_downloads),
+            ),
+            |scheduler, (host, limit)| scheduler.host_limit(host, *limit),
+        )
+    }
+}
+
+fn parse_host_limit(value: &str) -> Result<(String, usize), String> {
+    let (host, limit) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected `host=n`, got `{value}`"))?;
+    let limit = limit
+        .parse()
+        .map_err(|err| format!("invalid limit `{limit}`: {err}"))?;
+    Ok((host.to_owned(), limit))
+}
+
+#[derive(Debug, Parser)]
+pub struct LockOpts {
+    /// Print the diff, but don't write back the changes
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+    #[command(flatten)]
+    pub concurrency: ConcurrencyOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct OutdatedOpts {
+    /// Checks only the specified pins.
+    #[arg(value_hint = ValueHint::Other)]
+    pub names: Vec<String>,
+    /// Exit with status 4 if any pin that is not frozen is outdated
+    #[arg(long)]
+    pub fail_if_outdated: bool,
+    #[command(flatten)]
+    pub concurrency: ConcurrencyOpts,
 }
 
 #[derive(Debug, Parser)]
@@ -292,9 +531,8 @@ pub struct VerifyOpts {
     /// Verifies only the specified pins.
     #[arg(value_hint = ValueHint::Other)]
     pub names: Vec<String>,
-    /// Maximum number of simultaneous downloads
-    #[arg(default_value = "5", long, value_hint = ValueHint::Other)]
-    pub max_concurrent_downloads: usize,
+    #[command(flatten)]
+    pub concurrency: ConcurrencyOpts,
 }
 
 #[derive(Debug, Parser)]
@@ -353,6 +591,13 @@ pub enum Command {
     /// Updates all or the given pins to the latest version.
     Update(UpdateOpts),
 
+    /// Lists the pins with newer versions upstream, without fetching them or changing sources.json.
+    Outdated(OutdatedOpts),
+
+    /// Resolves the pins specified in npins.toml into sources.json. Pins whose specification
+    /// didn't change keep their version, new or changed ones are resolved and unspecified ones are removed.
+    Lock(LockOpts),
+
     /// Verifies that all or the given pins still have correct hashes. This is like `update --partial --dry-run` and then checking that the diff is empty
     Verify(VerifyOpts),
 
@@ -408,6 +653,31 @@ pub struct Opts {
     #[arg(global = true, short = 'v', long = "verbose")]
     pub verbose: bool,
 
+    /// Format of the results printed by `show`, `update` and `verify`.
+    #[arg(global = true, short = 'o', long, value_enum, default_value_t)]
+    pub output: OutputFormat,
+
+    /// How often to retry network operations which failed for transient reasons, like timeouts,
+    /// connection errors, server errors and rate limits.
+    #[arg(global = true, long, value_name = "n", default_value_t = RetryPolicy::default().attempts - 1)]
+    pub retries: u32,
+
+    /// Timeout in seconds of each API request, 0 disables it.
+    #[arg(global = true, long, value_name = "seconds", default_value_t = timeout_secs(Timeouts::default().request))]
+    pub request_timeout: u64,
+
+    /// Timeout in seconds of listing the refs of a git repository, 0 disables it.
+    #[arg(global = true, long, value_name = "seconds", default_value_t = timeout_secs(Timeouts::default().git))]
+    pub git_timeout: u64,
+
+    /// Timeout in seconds of downloading and hashing a source, 0 disables it.
+    #[arg(global = true, long, value_name = "seconds", default_value_t = timeout_secs(Timeouts::default().prefetch))]
+    pub prefetch_timeout: u64,
+
     #[command(subcommand)]
     pub command: Command,
 }
+
+fn timeout_secs(timeout: Option<std::time::Duration>) -> u64 {
+    timeout.map_or(0, |timeout| timeout.as_secs())
+}
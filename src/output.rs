@@ -0,0 +1,287 @@
+//! Machine readable output of `show`, `update`, `verify` and `outdated`
+//!
+//! In text mode, the commands keep printing their human readable output themselves, except for
+//! `outdated`, whose only output is its table.
+
+use anyhow::Result;
+use libnpins::{
+    Pin,
+    changelog::Changelog,
+    diff::{Change, Diff, DiffEntry},
+    outdated::Outdated,
+};
+use serde::Serialize;
+use std::io::Write;
+
+use crate::opts::OutputFormat;
+
+/// The result of updating or verifying a single pin
+#[derive(Debug, Serialize)]
+pub struct PinResult {
+    pub name: String,
+    /// Human readable name of the pin type
+    #[serde(rename = "type")]
+    pub pin_type: &'static str,
+    pub status: Status,
+    pub changes: Vec<DiffEntry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Compare link and commits, only for updated pins hosted on a forge
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub changelog: Option<Changelog>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Unchanged,
+    Changed,
+    Failed,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Status::Unchanged => "unchanged",
+            Status::Changed => "changed",
+            Status::Failed => "failed",
+        })
+    }
+}
+
+impl PinResult {
+    pub fn new(name: &str, pin_type: &'static str, result: &Result<Vec<DiffEntry>>) -> Self {
+        let (status, changes, error) = match result {
+            Ok(diff) if diff.is_empty() => (Status::Unchanged, vec![], None),
+            Ok(diff) => (Status::Changed, diff.clone(), None),
+            Err(err) => (Status::Failed, vec![], Some(format!("{err:#}"))),
+        };
+        Self {
+            name: name.to_owned(),
+            pin_type,
+            status,
+            changes,
+            error,
+            changelog: None,
+        }
+    }
+
+    pub fn with_changelog(self, changelog: Option<Changelog>) -> Self {
+        Self { changelog, ..self }
+    }
+}
+
+/// Escape a value for use in a Markdown table cell
+fn markdown_cell(value: &str) -> String {
+    value.replace('|', "\\|").replace('\n', "<br>")
+}
+
+/// Print the full pin objects
+///
+/// Does nothing in text mode.
+pub fn write_pins(
+    writer: &mut impl Write,
+    format: OutputFormat,
+    pins: &[(&String, &Pin)],
+) -> Result<()> {
+    match format {
+        OutputFormat::Text => {},
+        OutputFormat::Json => {
+            let pins: serde_json::Map<String, serde_json::Value> = pins
+                .iter()
+                .map(|(name, pin)| Ok(((*name).clone(), serde_json::to_value(pin)?)))
+                .collect::<Result<_>>()?;
+            serde_json::to_writer_pretty(&mut *writer, &pins)?;
+            writeln!(writer)?;
+        },
+        OutputFormat::Markdown => {
+            for (i, (name, pin)) in pins.iter().enumerate() {
+                if i > 0 {
+                    writeln!(writer)?;
+                }
+                writeln!(writer, "## {name}")?;
+                writeln!(writer)?;
+                writeln!(writer, "| Property | Value |")?;
+                writeln!(writer, "| --- | --- |")?;
+                writeln!(writer, "| type | {} |", pin.pin_type())?;
+                for (key, value) in pin.properties() {
+                    writeln!(writer, "| {key} | `{}` |", markdown_cell(&value))?;
+                }
+            }
+        },
+    }
+    Ok(())
+}
+
+/// Print the per-pin results of `update` or `verify`, sorted by name
+///
+/// Does nothing in text mode.
+pub fn write_results(
+    writer: &mut impl Write,
+    format: OutputFormat,
+    results: &mut [PinResult],
+) -> Result<()> {
+    results.sort_by(|a, b| a.name.cmp(&b.name));
+    match format {
+        OutputFormat::Text => {},
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut *writer, &serde_json::json!({ "pins": results }))?;
+            writeln!(writer)?;
+        },
+        OutputFormat::Markdown => {
+            writeln!(writer, "| Pin | Type | Status | Changes |")?;
+            writeln!(writer, "| --- | --- | --- | --- |")?;
+            for result in results.iter() {
+                let details = match &result.error {
+                    Some(error) => markdown_cell(error),
+                    None => result
+                        .changes
+                        .iter()
+                        .map(|entry| {
+                            let change = match &entry.change {
+                                Change::Added { new } => format!("`{new}` (added)"),
+                                Change::Removed { old } => format!("`{old}` (removed)"),
+                                Change::Changed { old, new } => format!("`{old}` → `{new}`"),
+                            };
+                            markdown_cell(&format!("{}: {change}", entry.property))
+                        })
+                        .chain(
+                            result
+                                .changelog
+                                .iter()
+                                .map(|changelog| format!("[Compare]({})", changelog.compare_url)),
+                        )
+                        .collect::<Vec<_>>()
+                        .join("<br>"),
+                };
+                writeln!(
+                    writer,
+                    "| {} | {} | {} | {details} |",
+                    result.name, result.pin_type, result.status,
+                )?;
+            }
+
+            /* The commit lists are too long for the table */
+            for result in results.iter() {
+                let Some(commits) = result
+                    .changelog
+                    .as_ref()
+                    .and_then(|changelog| changelog.commits.as_ref())
+                else {
+                    continue;
+                };
+                writeln!(writer)?;
+                writeln!(
+                    writer,
+                    "<details><summary>{}: {} commits</summary>",
+                    result.name,
+                    commits.len()
+                )?;
+                writeln!(writer)?;
+                for commit in commits {
+                    let id = commit.id.get(..12).unwrap_or(&commit.id);
+                    match &commit.url {
+                        Some(url) => writeln!(writer, "- [`{id}`]({url}) {}", commit.title)?,
+                        None => writeln!(writer, "- `{id}` {}", commit.title)?,
+                    }
+                }
+                writeln!(writer)?;
+                writeln!(writer, "</details>")?;
+            }
+        },
+    }
+    Ok(())
+}
+
+/// A pin that is outdated or could not be checked
+#[derive(Debug, Serialize)]
+pub struct OutdatedResult {
+    pub name: String,
+    /// Human readable name of the pin type
+    #[serde(rename = "type")]
+    pub pin_type: &'static str,
+    pub frozen: bool,
+    #[serde(flatten)]
+    pub outdated: Option<Outdated>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Print the outdated pins, with the frozen ones in a separate table
+///
+/// Pins that could not be checked are only part of the JSON output.
+pub fn write_outdated(
+    writer: &mut impl Write,
+    format: OutputFormat,
+    results: &[OutdatedResult],
+) -> Result<()> {
+    if format == OutputFormat::Json {
+        serde_json::to_writer_pretty(&mut *writer, &serde_json::json!({ "pins": results }))?;
+        writeln!(writer)?;
+        return Ok(());
+    }
+
+    const HEADER: [&str; 4] = ["Pin", "Current", "Latest", "Update"];
+    let rows = |frozen: bool| -> Vec<[String; 4]> {
+        results
+            .iter()
+            .filter(|result| result.frozen == frozen)
+            .filter_map(|result| {
+                let outdated = result.outdated.as_ref()?;
+                Some([
+                    result.name.clone(),
+                    outdated.current.clone().unwrap_or_else(|| "-".into()),
+                    outdated.latest.clone(),
+                    outdated.kind.to_string(),
+                ])
+            })
+            .collect()
+    };
+
+    let mut first = true;
+    for (frozen, rows) in [(false, rows(false)), (true, rows(true))] {
+        if rows.is_empty() {
+            continue;
+        }
+        if !first {
+            writeln!(writer)?;
+        }
+        first = false;
+        if frozen {
+            writeln!(writer, "Frozen pins:")?;
+            if format == OutputFormat::Markdown {
+                writeln!(writer)?;
+            }
+        }
+
+        match format {
+            OutputFormat::Markdown => {
+                writeln!(writer, "| {} |", HEADER.join(" | "))?;
+                writeln!(writer, "|{}", " --- |".repeat(HEADER.len()))?;
+                for row in &rows {
+                    let [name, current, latest, kind] =
+                        row.each_ref().map(|cell| markdown_cell(cell));
+                    writeln!(writer, "| {name} | `{current}` | `{latest}` | {kind} |")?;
+                }
+            },
+            _ => {
+                let mut widths = HEADER.map(str::len);
+                for row in &rows {
+                    for (width, cell) in widths.iter_mut().zip(row) {
+                        *width = (*width).max(cell.chars().count());
+                    }
+                }
+                for row in std::iter::once(HEADER.map(str::to_owned)).chain(rows) {
+                    let line = row
+                        .iter()
+                        .zip(widths)
+                        .map(|(cell, width)| format!("{cell:width$}"))
+                        .collect::<Vec<_>>()
+                        .join("  ");
+                    writeln!(writer, "{}", line.trim_end())?;
+                }
+            },
+        }
+    }
+    Ok(())
+}